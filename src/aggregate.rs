@@ -0,0 +1,704 @@
+use std::{fmt, iter::Peekable, vec};
+
+use crate::error::{Error, Result};
+use crate::iter::QueryIter;
+use crate::sketch::DDSketch;
+use crate::sstable::BlockStats;
+use crate::wal::{Timestamp, Value};
+
+/// 精确分位数最多计算的数据点数，超过时报错
+pub const MAX_EXACT_QUANTILE_POINTS: usize = 1_000_000;
+
+/// 聚合函数
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregation {
+    Count,
+    Sum,
+    Min,
+    Max,
+    Avg,
+    First,
+    Last,
+    /// 样本标准差，少于两个点时没有结果
+    Stddev,
+    /// 计数器的每秒平均增长率，处理计数器重置并按Prometheus的方式外推到区间两端
+    Rate,
+    /// 计数器最后两个点之间的每秒增长率
+    Irate,
+    /// 计数器在区间内的增长量，即`rate`乘以区间长度
+    Increase,
+    /// 普通数值在区间内的变化量，外推方式同`increase`但不处理重置
+    Delta,
+    /// 第一个点到最后一个点的每秒变化率，不外推也不处理重置
+    Derivative,
+    /// 同`derivative`，结果为负时没有结果
+    NonNegativeDerivative,
+    /// 分位数（0到1），用DDSketch估算，相对误差不超过`sketch::RELATIVE_ACCURACY`
+    Quantile(f64),
+    /// 精确分位数，保留区间内所有的值，相邻两个值之间线性插值，只适合小区间
+    ExactQuantile(f64),
+}
+
+impl Aggregation {
+    /// 按名称解析聚合函数，不区分大小写，`mean`是`avg`的别名
+    ///
+    /// 分位数写作`quantile(0.99)`、`quantile_exact(0.99)`或`p99`。
+    pub fn parse(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "count" => Ok(Aggregation::Count),
+            "sum" => Ok(Aggregation::Sum),
+            "min" => Ok(Aggregation::Min),
+            "max" => Ok(Aggregation::Max),
+            "avg" | "mean" => Ok(Aggregation::Avg),
+            "first" => Ok(Aggregation::First),
+            "last" => Ok(Aggregation::Last),
+            "stddev" => Ok(Aggregation::Stddev),
+            "rate" => Ok(Aggregation::Rate),
+            "irate" => Ok(Aggregation::Irate),
+            "increase" => Ok(Aggregation::Increase),
+            "delta" => Ok(Aggregation::Delta),
+            "derivative" => Ok(Aggregation::Derivative),
+            "non_negative_derivative" => Ok(Aggregation::NonNegativeDerivative),
+            other => parse_quantile(other).ok_or_else(|| Error::DataError(format!("未知的聚合函数: {}", name))),
+        }
+    }
+}
+
+fn parse_quantile(name: &str) -> Option<Aggregation> {
+    let argument = |prefix: &str| name.strip_prefix(prefix)?.strip_suffix(')')?.parse::<f64>().ok();
+    let agg = if let Some(q) = argument("quantile(") {
+        Aggregation::Quantile(q)
+    } else if let Some(q) = argument("quantile_exact(") {
+        Aggregation::ExactQuantile(q)
+    } else {
+        Aggregation::Quantile(name.strip_prefix('p')?.parse::<f64>().ok()? / 100.0)
+    };
+    match agg {
+        Aggregation::Quantile(q) | Aggregation::ExactQuantile(q) if (0.0..=1.0).contains(&q) => Some(agg),
+        _ => None,
+    }
+}
+
+impl Aggregation {
+    /// 能否直接用块的预聚合统计计算，标准差和变化率需要逐点累加
+    pub fn uses_block_stats(&self) -> bool {
+        matches!(
+            self,
+            Aggregation::Count | Aggregation::Sum | Aggregation::Min | Aggregation::Max
+                | Aggregation::Avg | Aggregation::First | Aggregation::Last
+        )
+    }
+
+    /// 能否直接合并块的分位数草图计算
+    pub fn uses_block_sketch(&self) -> bool {
+        matches!(self, Aggregation::Quantile(_))
+    }
+
+    /// 能否把多个序列的数据合在一起计算：结果与数据点的先后顺序无关
+    pub fn combines_series(&self) -> bool {
+        matches!(
+            self,
+            Aggregation::Count | Aggregation::Sum | Aggregation::Min | Aggregation::Max | Aggregation::Avg
+                | Aggregation::Stddev | Aggregation::Quantile(_) | Aggregation::ExactQuantile(_)
+        )
+    }
+
+    /// 能否用降采样的汇总数据计算，汇总表只有count、sum、min、max
+    pub fn uses_rollup(&self) -> bool {
+        matches!(self, Aggregation::Count | Aggregation::Sum | Aggregation::Min | Aggregation::Max | Aggregation::Avg)
+    }
+}
+
+impl fmt::Display for Aggregation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Aggregation::Count => "count",
+            Aggregation::Sum => "sum",
+            Aggregation::Min => "min",
+            Aggregation::Max => "max",
+            Aggregation::Avg => "avg",
+            Aggregation::First => "first",
+            Aggregation::Last => "last",
+            Aggregation::Stddev => "stddev",
+            Aggregation::Rate => "rate",
+            Aggregation::Irate => "irate",
+            Aggregation::Increase => "increase",
+            Aggregation::Delta => "delta",
+            Aggregation::Derivative => "derivative",
+            Aggregation::NonNegativeDerivative => "non_negative_derivative",
+            Aggregation::Quantile(q) => return write!(f, "quantile({})", q),
+            Aggregation::ExactQuantile(q) => return write!(f, "quantile_exact({})", q),
+        };
+        f.write_str(name)
+    }
+}
+
+/// 流式聚合的中间状态，按时间升序逐点累加，一次遍历即可得到所有聚合函数的结果
+///
+/// 均值和方差用Welford算法累加，避免大数相减造成的精度损失。
+#[derive(Debug, Clone, Default)]
+pub struct Accumulator {
+    count: u64,
+    sum: Value,
+    min: Value,
+    max: Value,
+    first: Option<(Timestamp, Value)>,
+    last: Option<(Timestamp, Value)>,
+    // 最后一个点之前的点，用于irate
+    previous: Option<(Timestamp, Value)>,
+    // 计数器每次重置前的值之和，加到首尾之差上得到实际增长量
+    resets: Value,
+    mean: Value,
+    m2: Value,
+    // 只在计算分位数时保留
+    sketch: Option<DDSketch>,
+    values: Option<Vec<Value>>,
+}
+
+impl Accumulator {
+    /// 不计算分位数的累加器
+    pub fn new() -> Self {
+        Accumulator::default()
+    }
+
+    /// 能计算`agg`的累加器，分位数需要额外保留草图或所有的值
+    pub fn for_aggregation(agg: Aggregation) -> Self {
+        Accumulator::for_aggregations(&[agg])
+    }
+
+    /// 一次累加就能计算`aggs`中所有聚合函数的累加器
+    pub fn for_aggregations(aggs: &[Aggregation]) -> Self {
+        Accumulator {
+            sketch: aggs.iter().any(|agg| matches!(agg, Aggregation::Quantile(_))).then(DDSketch::new),
+            values: aggs.iter().any(|agg| matches!(agg, Aggregation::ExactQuantile(_))).then(Vec::new),
+            ..Accumulator::default()
+        }
+    }
+
+    /// 累加一个数据点，数据点须按时间升序给出
+    pub fn push(&mut self, ts: Timestamp, value: Value) {
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+            self.first = Some((ts, value));
+        } else {
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+        }
+        if let Some((_, last)) = self.last.filter(|&(_, last)| value < last) {
+            self.resets += last;
+        }
+        self.previous = self.last;
+        self.last = Some((ts, value));
+        self.count += 1;
+        self.sum += value;
+
+        let delta = value - self.mean;
+        self.mean += delta / self.count as Value;
+        self.m2 += delta * (value - self.mean);
+
+        if let Some(sketch) = &mut self.sketch {
+            sketch.add(value);
+        }
+        if let Some(values) = &mut self.values {
+            values.push(value);
+        }
+    }
+
+    /// 累加一整块的预聚合统计，块须与前后的数据点按时间顺序给出
+    ///
+    /// 块统计中没有方差，也看不出块内的计数器重置，累加过块统计后标准差和变化率不再准确，
+    /// 只用于`uses_block_stats`的聚合函数。
+    pub fn push_block(&mut self, min_ts: Timestamp, max_ts: Timestamp, stats: &BlockStats) {
+        if stats.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            self.min = stats.min;
+            self.max = stats.max;
+            self.first = Some((min_ts, stats.first));
+        } else {
+            self.min = self.min.min(stats.min);
+            self.max = self.max.max(stats.max);
+        }
+        self.previous = None;
+        self.last = Some((max_ts, stats.last));
+        self.count += stats.count;
+        self.sum += stats.sum;
+        self.mean = self.sum / self.count as Value;
+    }
+
+    /// 累加一个输入，块带有草图时合并到分位数草图中；精确分位数超过点数上限时报错
+    pub fn push_sample(&mut self, sample: &Sample) -> Result<()> {
+        match sample {
+            Sample::Point(ts, value) => self.push(*ts, *value),
+            Sample::Block { min_ts, max_ts, stats, sketch } => {
+                self.push_block(*min_ts, *max_ts, stats);
+                if let (Some(acc), Some(block)) = (&mut self.sketch, sketch) {
+                    acc.merge(block);
+                }
+            }
+        }
+        if self.values.as_ref().is_some_and(|v| v.len() > MAX_EXACT_QUANTILE_POINTS) {
+            return Err(Error::DataError(format!(
+                "精确分位数最多计算 {} 个点，请缩小查询区间或改用quantile",
+                MAX_EXACT_QUANTILE_POINTS
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> Value {
+        self.sum
+    }
+
+    pub fn min(&self) -> Value {
+        self.min
+    }
+
+    pub fn max(&self) -> Value {
+        self.max
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// 聚合结果，没有数据点时为None，变化率以第一个点到最后一个点为区间，不外推
+    pub fn result(&self, agg: Aggregation) -> Option<Value> {
+        let (start, end) = (self.first?.0, self.last?.0);
+        self.result_in(agg, start, end)
+    }
+
+    /// 区间`[start, end)`上的聚合结果，区间只影响变化率的外推和`rate`的分母
+    pub fn result_in(&self, agg: Aggregation, start: Timestamp, end: Timestamp) -> Option<Value> {
+        if self.count == 0 {
+            return None;
+        }
+        match agg {
+            Aggregation::Count => Some(self.count as Value),
+            Aggregation::Sum => Some(self.sum),
+            Aggregation::Min => Some(self.min),
+            Aggregation::Max => Some(self.max),
+            Aggregation::Avg => Some(self.sum / self.count as Value),
+            Aggregation::First => self.first.map(|(_, v)| v),
+            Aggregation::Last => self.last.map(|(_, v)| v),
+            Aggregation::Stddev if self.count < 2 => None,
+            Aggregation::Stddev => Some((self.m2 / (self.count - 1) as Value).sqrt()),
+            Aggregation::Rate => self.extrapolated_delta(start, end, true)
+                .filter(|_| end > start)
+                .map(|delta| delta / (end - start) as Value),
+            Aggregation::Increase => self.extrapolated_delta(start, end, true),
+            Aggregation::Delta => self.extrapolated_delta(start, end, false),
+            Aggregation::Irate => {
+                let ((t0, v0), (t1, v1)) = (self.previous?, self.last?);
+                // 重置后的计数器从0开始
+                let delta = if v1 < v0 { v1 } else { v1 - v0 };
+                Some(delta / (t1 - t0) as Value)
+            }
+            Aggregation::Derivative | Aggregation::NonNegativeDerivative => {
+                let ((t0, v0), (t1, v1)) = (self.first?, self.last?);
+                if t1 == t0 {
+                    return None;
+                }
+                let derivative = (v1 - v0) / (t1 - t0) as Value;
+                Some(derivative).filter(|d| agg == Aggregation::Derivative || *d >= 0.0)
+            }
+            Aggregation::Quantile(q) => self.sketch.as_ref()?.quantile(q),
+            Aggregation::ExactQuantile(q) => {
+                let mut values = self.values.clone()?;
+                values.sort_by(|a, b| a.total_cmp(b));
+                let rank = q * (values.len() - 1) as Value;
+                let (lo, hi) = (values[rank.floor() as usize], values[rank.ceil() as usize]);
+                Some(lo + (hi - lo) * rank.fract())
+            }
+        }
+    }
+
+    /// 区间内的变化量，从首尾两点外推到区间两端，`counter`时加上重置前的值
+    ///
+    /// 与Prometheus相同：离区间端点不超过平均采样间隔1.1倍时外推到端点，否则只外推半个采样间隔；
+    /// 计数器向前外推不超过它为0的时刻。
+    fn extrapolated_delta(&self, start: Timestamp, end: Timestamp, counter: bool) -> Option<Value> {
+        if self.count < 2 {
+            return None;
+        }
+        let ((t0, v0), (t1, v1)) = (self.first?, self.last?);
+        let sampled = (t1 - t0) as Value;
+        let mut delta = v1 - v0;
+        if counter {
+            delta += self.resets;
+        }
+
+        let average = sampled / (self.count - 1) as Value;
+        let mut to_start = t0.saturating_sub(start) as Value;
+        let to_end = end.saturating_sub(t1) as Value;
+        if counter && delta > 0.0 && v0 >= 0.0 {
+            to_start = to_start.min(sampled * v0 / delta);
+        }
+        let extend = |d: Value| if d < average * 1.1 { d } else { average / 2.0 };
+        Some(delta * (sampled + extend(to_start) + extend(to_end)) / sampled)
+    }
+}
+
+/// 聚合的输入：单个数据点，或不用解码的整块预聚合统计
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sample {
+    Point(Timestamp, Value),
+    Block {
+        min_ts: Timestamp,
+        max_ts: Timestamp,
+        stats: BlockStats,
+        /// 块的分位数草图，只在计算分位数时读取
+        sketch: Option<Box<DDSketch>>,
+    },
+}
+
+impl Sample {
+    pub fn min_ts(&self) -> Timestamp {
+        match *self {
+            Sample::Point(ts, _) => ts,
+            Sample::Block { min_ts, .. } => min_ts,
+        }
+    }
+}
+
+/// 把解码出的数据点和按统计信息聚合的块按时间顺序合并
+///
+/// 块的时间范围内没有任何其他数据，块在它之后的第一个数据点之前给出。
+pub struct SampleIter {
+    points: QueryIter,
+    blocks: Peekable<vec::IntoIter<Sample>>,
+    pending: Option<(Timestamp, Value)>,
+}
+
+impl SampleIter {
+    /// `blocks`须按时间排列
+    pub fn new(points: QueryIter, blocks: Vec<Sample>) -> Self {
+        SampleIter { points, blocks: blocks.into_iter().peekable(), pending: None }
+    }
+}
+
+impl Iterator for SampleIter {
+    type Item = Result<Sample>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pending.is_none() {
+            match self.points.next() {
+                Some(Ok(point)) => self.pending = Some(point),
+                Some(Err(e)) => return Some(Err(e)),
+                None => {}
+            }
+        }
+        if let Some(block) = self.blocks.next_if(|b| self.pending.is_none_or(|(ts, _)| b.min_ts() < ts)) {
+            return Some(Ok(block));
+        }
+        self.pending.take().map(|(ts, value)| Ok(Sample::Point(ts, value)))
+    }
+}
+
+/// 分组聚合最多生成的桶数，防止过小的桶宽在补齐空桶时占满内存
+pub const MAX_BUCKETS: u64 = 1_000_000;
+
+/// 按时间分桶：每个桶覆盖`[bucket, bucket + width)`，桶的起点满足`bucket % width == offset % width`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    pub width: u64,
+    pub offset: u64,
+}
+
+impl Window {
+    pub fn new(width: u64, offset: u64) -> Result<Self> {
+        if width == 0 {
+            return Err(Error::DataError("分组的桶宽必须大于0".to_string()));
+        }
+        Ok(Window { width, offset: offset % width })
+    }
+
+    /// 时间戳所在桶的起点，对齐点之前不足一个桶的部分归入从0开始的桶
+    pub fn bucket_start(&self, ts: Timestamp) -> Timestamp {
+        let rem = (ts % self.width + self.width - self.offset) % self.width;
+        ts.saturating_sub(rem)
+    }
+}
+
+/// 空桶的填充方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fill {
+    /// 不返回空桶
+    None,
+    /// 空桶的值为null
+    Null,
+    /// 沿用前一个有值的桶
+    Previous,
+    /// 用前后两个有值的桶线性插值，两端的空桶为null
+    Linear,
+    /// 固定值
+    Constant(Value),
+}
+
+impl Fill {
+    /// 解析填充方式：none、null、previous、linear或一个数值
+    pub fn parse(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Fill::None),
+            "null" => Ok(Fill::Null),
+            "previous" => Ok(Fill::Previous),
+            "linear" => Ok(Fill::Linear),
+            other => other
+                .parse::<Value>()
+                .map(Fill::Constant)
+                .map_err(|_| Error::DataError(format!("未知的填充方式: {}", s))),
+        }
+    }
+}
+
+/// 对按时间升序的数据做分组聚合，返回`[start, end]`内每个桶的起点和聚合结果
+///
+/// 同一时间只保留当前桶的累加状态，数据边读边累加。整块统计须落在同一个桶内。
+pub fn aggregate_windows<I>(
+    samples: I,
+    start: Timestamp,
+    end: Timestamp,
+    window: Window,
+    agg: Aggregation,
+    fill: Fill,
+) -> Result<Vec<(Timestamp, Option<Value>)>>
+where
+    I: Iterator<Item = Result<Sample>>,
+{
+    let rows = aggregate_windows_many(samples, start, end, window, &[agg], fill)?;
+    Ok(rows.into_iter().map(|(bucket, values)| (bucket, values[0])).collect())
+}
+
+/// 同`aggregate_windows`，一次遍历同时计算多个聚合函数，每个桶返回各聚合函数的结果
+///
+/// 每个桶只用一个累加器，空桶按`fill`逐列补齐。
+pub fn aggregate_windows_many<I>(
+    samples: I,
+    start: Timestamp,
+    end: Timestamp,
+    window: Window,
+    aggs: &[Aggregation],
+    fill: Fill,
+) -> Result<Vec<(Timestamp, Vec<Option<Value>>)>>
+where
+    I: Iterator<Item = Result<Sample>>,
+{
+    let first = window.bucket_start(start);
+    if start > end {
+        return Ok(Vec::new());
+    }
+    if fill != Fill::None && (end - first) / window.width >= MAX_BUCKETS {
+        return Err(Error::DataError(format!(
+            "分组数超过上限 {}，请增大桶宽或缩小查询区间",
+            MAX_BUCKETS
+        )));
+    }
+
+    // 先得到有数据的桶，桶的区间截断到查询区间内
+    let range = |bucket: Timestamp| {
+        let next = bucket.checked_add(window.width).map_or(Timestamp::MAX, |next| window.bucket_start(next));
+        (bucket.max(start), next.min(end.saturating_add(1)))
+    };
+    let results = |bucket: Timestamp, acc: &Accumulator| {
+        let (lo, hi) = range(bucket);
+        aggs.iter().map(|&agg| acc.result_in(agg, lo, hi)).collect::<Vec<_>>()
+    };
+    let mut rows: Vec<(Timestamp, Vec<Option<Value>>)> = Vec::new();
+    let mut current: Option<(Timestamp, Accumulator)> = None;
+    for sample in samples {
+        let sample = sample?;
+        let bucket = window.bucket_start(sample.min_ts());
+        match &mut current {
+            Some((b, acc)) if *b == bucket => acc.push_sample(&sample)?,
+            _ => {
+                if let Some((b, acc)) = current.take() {
+                    rows.push((b, results(b, &acc)));
+                }
+                let mut acc = Accumulator::for_aggregations(aggs);
+                acc.push_sample(&sample)?;
+                current = Some((bucket, acc));
+            }
+        }
+    }
+    if let Some((b, acc)) = current {
+        rows.push((b, results(b, &acc)));
+    }
+    if fill == Fill::None {
+        return Ok(rows);
+    }
+
+    // 补齐空桶，每列分别沿用或插值
+    let last = window.bucket_start(end);
+    let mut filled = Vec::with_capacity(((last - first) / window.width + 1) as usize);
+    let mut rows = rows.into_iter().peekable();
+    let mut previous: Vec<Option<(Timestamp, Value)>> = vec![None; aggs.len()];
+    let mut bucket = first;
+    loop {
+        let values = match rows.next_if(|(b, _)| *b == bucket) {
+            Some((_, values)) => {
+                // 沿用和插值都只以有数据的桶为基准
+                for (prev, value) in previous.iter_mut().zip(&values) {
+                    if let Some(v) = value {
+                        *prev = Some((bucket, *v));
+                    }
+                }
+                values
+            }
+            None => {
+                let next = rows.peek();
+                (0..aggs.len())
+                    .map(|i| match fill {
+                        Fill::Previous => previous[i].map(|(_, v)| v),
+                        Fill::Linear => match (previous[i], next.and_then(|(t1, values)| Some((*t1, values[i]?)))) {
+                            (Some((t0, v0)), Some((t1, v1))) => {
+                                Some(v0 + (v1 - v0) * (bucket - t0) as Value / (t1 - t0) as Value)
+                            }
+                            _ => None,
+                        },
+                        Fill::Constant(c) => Some(c),
+                        Fill::None | Fill::Null => None,
+                    })
+                    .collect()
+            }
+        };
+        filled.push((bucket, values));
+
+        // 第一个桶可能因从0开始而没有对齐，下一个桶重新对齐
+        match bucket.checked_add(window.width).map(|next| window.bucket_start(next)) {
+            Some(next) if next <= last => bucket = next,
+            _ => break,
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accumulator() {
+        let mut acc = Accumulator::new();
+        assert_eq!(acc.result(Aggregation::Count), None);
+        for (ts, value) in [(1, 2.0), (2, 4.0), (3, 4.0), (4, 4.0), (5, 5.0), (6, 5.0), (7, 7.0), (8, 9.0)] {
+            acc.push(ts, value);
+        }
+
+        assert_eq!(acc.result(Aggregation::Count), Some(8.0));
+        assert_eq!(acc.result(Aggregation::Sum), Some(40.0));
+        assert_eq!(acc.result(Aggregation::Min), Some(2.0));
+        assert_eq!(acc.result(Aggregation::Max), Some(9.0));
+        assert_eq!(acc.result(Aggregation::Avg), Some(5.0));
+        assert_eq!(acc.result(Aggregation::First), Some(2.0));
+        assert_eq!(acc.result(Aggregation::Last), Some(9.0));
+        let stddev = acc.result(Aggregation::Stddev).unwrap();
+        assert!((stddev - (32.0f64 / 7.0).sqrt()).abs() < 1e-12);
+
+        assert_eq!(Aggregation::parse("MEAN").unwrap(), Aggregation::Avg);
+        assert!(Aggregation::parse("median").is_err());
+    }
+
+    #[test]
+    fn test_rate() {
+        // 每10秒一个点，30秒时计数器重置
+        let mut acc = Accumulator::new();
+        for (ts, value) in [(0, 0.0), (10, 10.0), (20, 20.0), (30, 5.0), (40, 15.0), (50, 25.0)] {
+            acc.push(ts, value);
+        }
+        // 实际增长45，最后一个点离区间终点不到1.1个采样间隔，外推到60秒
+        assert_eq!(acc.result_in(Aggregation::Increase, 0, 60), Some(54.0));
+        assert_eq!(acc.result_in(Aggregation::Rate, 0, 60), Some(0.9));
+        assert_eq!(acc.result_in(Aggregation::Delta, 0, 60), Some(30.0));
+        assert_eq!(acc.result_in(Aggregation::Irate, 0, 60), Some(1.0));
+        assert_eq!(acc.result(Aggregation::Derivative), Some(0.5));
+        assert_eq!(acc.result(Aggregation::Increase), Some(45.0));
+
+        let mut acc = Accumulator::new();
+        for (ts, value) in [(10, 5.0), (20, 15.0), (30, 25.0)] {
+            acc.push(ts, value);
+        }
+        // 向前外推到计数器为0的5秒处；离终点太远时只外推半个采样间隔
+        assert_eq!(acc.result_in(Aggregation::Increase, 0, 40), Some(35.0));
+        assert_eq!(acc.result_in(Aggregation::Increase, 0, 100), Some(30.0));
+        assert_eq!(acc.result_in(Aggregation::Rate, 0, 100), Some(0.3));
+
+        let mut acc = Accumulator::new();
+        acc.push(0, 10.0);
+        assert_eq!(acc.result(Aggregation::Rate), None);
+        acc.push(5, 0.0);
+        assert_eq!(acc.result(Aggregation::Derivative), Some(-2.0));
+        assert_eq!(acc.result(Aggregation::NonNegativeDerivative), None);
+        assert_eq!(acc.result(Aggregation::Irate), Some(0.0));
+
+        let name = Aggregation::NonNegativeDerivative.to_string();
+        assert_eq!(Aggregation::parse(&name).unwrap(), Aggregation::NonNegativeDerivative);
+    }
+
+    #[test]
+    fn test_quantile() {
+        let mut exact = Accumulator::for_aggregation(Aggregation::ExactQuantile(0.5));
+        let mut sketch = Accumulator::for_aggregation(Aggregation::Quantile(0.5));
+        for (ts, value) in [(1, 4.0), (2, 1.0), (3, 3.0), (4, 2.0)] {
+            exact.push_sample(&Sample::Point(ts, value)).unwrap();
+            sketch.push_sample(&Sample::Point(ts, value)).unwrap();
+        }
+        assert_eq!(exact.result(Aggregation::ExactQuantile(0.5)), Some(2.5));
+        assert_eq!(exact.result(Aggregation::ExactQuantile(1.0)), Some(4.0));
+        let median = sketch.result(Aggregation::Quantile(0.5)).unwrap();
+        assert!((median - 2.0).abs() <= 0.02);
+        // 普通累加器不保留分位数所需的数据
+        assert_eq!(Accumulator::new().result(Aggregation::Quantile(0.5)), None);
+
+        assert_eq!(Aggregation::parse("p99").unwrap(), Aggregation::Quantile(0.99));
+        assert_eq!(Aggregation::parse("p50").unwrap(), Aggregation::Quantile(0.5));
+        assert_eq!(Aggregation::parse("quantile_exact(0.9)").unwrap(), Aggregation::ExactQuantile(0.9));
+        assert_eq!(Aggregation::parse(&Aggregation::Quantile(0.999).to_string()).unwrap(), Aggregation::Quantile(0.999));
+        assert!(Aggregation::parse("quantile(1.5)").is_err());
+        assert!(Aggregation::parse("p").is_err());
+    }
+
+    #[test]
+    fn test_window_fill() {
+        // 桶宽10、偏移5：桶为[5, 15)、[15, 25)...，第一个桶从0开始
+        let window = Window::new(10, 5).unwrap();
+        assert_eq!(window.bucket_start(3), 0);
+        assert_eq!(window.bucket_start(15), 15);
+        assert_eq!(window.bucket_start(24), 15);
+
+        let points = [(3, 1.0), (6, 2.0), (8, 4.0), (36, 10.0), (37, 20.0)];
+        let run = |fill| {
+            let samples = points.iter().map(|&(ts, value)| Ok(Sample::Point(ts, value)));
+            aggregate_windows(samples, 0, 50, window, Aggregation::Avg, fill).unwrap()
+        };
+        assert_eq!(run(Fill::None), vec![(0, Some(1.0)), (5, Some(3.0)), (35, Some(15.0))]);
+        assert_eq!(run(Fill::Null),
+                   vec![(0, Some(1.0)), (5, Some(3.0)), (15, None), (25, None), (35, Some(15.0)), (45, None)]);
+        assert_eq!(run(Fill::Previous).iter().map(|&(_, v)| v).collect::<Vec<_>>(),
+                   vec![Some(1.0), Some(3.0), Some(3.0), Some(3.0), Some(15.0), Some(15.0)]);
+        assert_eq!(run(Fill::Linear).iter().map(|&(_, v)| v).collect::<Vec<_>>(),
+                   vec![Some(1.0), Some(3.0), Some(7.0), Some(11.0), Some(15.0), None]);
+        assert_eq!(run(Fill::Constant(0.0)).iter().map(|&(_, v)| v).collect::<Vec<_>>(),
+                   vec![Some(1.0), Some(3.0), Some(0.0), Some(0.0), Some(15.0), Some(0.0)]);
+
+        // 多个聚合函数一次计算，空桶逐列插值
+        let samples = points.iter().map(|&(ts, value)| Ok(Sample::Point(ts, value)));
+        let aggs = [Aggregation::Sum, Aggregation::Count];
+        assert_eq!(aggregate_windows_many(samples, 0, 40, window, &aggs, Fill::Linear).unwrap(), vec![
+            (0, vec![Some(1.0), Some(1.0)]),
+            (5, vec![Some(6.0), Some(2.0)]),
+            (15, vec![Some(14.0), Some(2.0)]),
+            (25, vec![Some(22.0), Some(2.0)]),
+            (35, vec![Some(30.0), Some(2.0)]),
+        ]);
+
+        assert!(Window::new(0, 0).is_err());
+        assert_eq!(Fill::parse("-1.5").unwrap(), Fill::Constant(-1.5));
+        assert!(Fill::parse("zero").is_err());
+    }
+}
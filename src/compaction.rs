@@ -0,0 +1,658 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::{error, info, warn};
+
+use crate::error::{Error, Result};
+use crate::manifest::{Manifest, VersionEdit};
+use crate::memtable::MemTable;
+use crate::retention::RetentionPolicy;
+use crate::sstable::SSTable;
+use crate::wal::Timestamp;
+
+/// 压缩策略
+#[derive(Debug, Clone)]
+pub enum CompactionStrategy {
+    /// 不做后台压缩
+    Disabled,
+    /// 合并相邻的小文件或时间范围重叠的文件
+    SizeTiered {
+        /// 小文件连续达到该数量时才合并
+        min_files: usize,
+        /// 一次最多合并的文件数
+        max_files: usize,
+        /// 小于该大小的文件视为小文件
+        small_file_bytes: u64,
+    },
+    /// 按时间窗口分组，只在同一窗口内合并，适合按时间顺序写入、按时间过期的数据
+    ///
+    /// 文件按`[min_ts, max_ts]`跨越的每个窗口归组，压缩输出在窗口边界处拆分为每个窗口一个文件。
+    /// 最新的`active_windows`个窗口仍在接收写入，文件数达到`min_files`时合并；
+    /// 更早的窗口视为已封存，有多个文件（如迟到数据）或有跨越窗口边界的文件时立即压缩，
+    /// 只剩一个不跨窗口的文件后不再参与压缩，过期时可以整个文件删除。
+    TimeWindow {
+        /// 窗口大小，如1小时或1天
+        window: Duration,
+        /// 仍在接收写入的最新窗口数
+        active_windows: u64,
+        /// 活跃窗口内文件达到该数量时才合并
+        min_files: usize,
+        /// 一次最多合并的文件数
+        max_files: usize,
+    },
+}
+
+impl Default for CompactionStrategy {
+    fn default() -> Self {
+        CompactionStrategy::SizeTiered {
+            min_files: 4,
+            max_files: 32,
+            small_file_bytes: 4 * 1024 * 1024,
+        }
+    }
+}
+
+impl CompactionStrategy {
+    /// 从按新旧排序的SSTable中挑选一组需要合并的文件，返回其下标
+    ///
+    /// 只挑选连续的一段文件，这样合并结果放回最新输入的位置后，
+    /// 与其它文件之间的新旧顺序保持不变。
+    pub fn pick(&self, sstables: &[Arc<SSTable>]) -> Option<Vec<usize>> {
+        match *self {
+            CompactionStrategy::Disabled => None,
+            CompactionStrategy::SizeTiered { min_files, max_files, small_file_bytes } => {
+                let max_files = max_files.max(2);
+                let small = |sst: &SSTable| sst.file_size() < small_file_bytes;
+
+                let mut run: Vec<usize> = Vec::new();
+                let mut has_overlap = false;
+                for i in 0..=sstables.len() {
+                    // 当前文件能否接在上一个文件之后：两者都是小文件，或者时间范围重叠
+                    if i > 0 && i < sstables.len() && run.len() < max_files {
+                        let (prev, cur) = (&sstables[i - 1], &sstables[i]);
+                        let overlap = prev.overlaps(cur);
+                        if overlap || (small(prev) && small(cur)) {
+                            run.push(i);
+                            has_overlap |= overlap;
+                            continue;
+                        }
+                    }
+
+                    if run.len() >= 2 && (has_overlap || run.len() >= min_files) {
+                        return Some(run);
+                    }
+                    run = vec![i];
+                    has_overlap = false;
+                }
+                None
+            }
+            CompactionStrategy::TimeWindow { window, active_windows, min_files, max_files } => {
+                let window_secs = window.as_secs().max(1);
+                let span = |sst: &SSTable| (sst.min_ts() / window_secs, sst.max_ts() / window_secs);
+                let latest = sstables.iter().map(|sst| span(sst).1).max()?;
+
+                // 文件属于它跨越的每个窗口。只需检查各文件起止所在的窗口：
+                // 其它窗口中只有跨越它的文件，这些文件在它们的起止窗口中已被检查
+                let windows: BTreeSet<u64> = sstables.iter().flat_map(|sst| <[u64; 2]>::from(span(sst))).collect();
+                for &w in windows.iter().rev() {
+                    // 组内保持新旧顺序
+                    let files: Vec<usize> = (0..sstables.len())
+                        .filter(|&i| {
+                            let (lo, hi) = span(&sstables[i]);
+                            lo <= w && w <= hi
+                        })
+                        .collect();
+                    let sealed = latest - w >= active_windows;
+                    for chunk in mergeable_chunks(sstables, &files) {
+                        // 封存窗口中跨越窗口边界的文件即使只有一个也要拆分
+                        let crossing = chunk.iter().any(|&i| {
+                            let (lo, hi) = span(&sstables[i]);
+                            lo != hi
+                        });
+                        let threshold = match (sealed, crossing) {
+                            (true, true) => 1,
+                            (true, false) => 2,
+                            (false, _) => min_files.max(2),
+                        };
+                        if chunk.len() >= threshold {
+                            return Some(chunk.into_iter().take(max_files.max(2)).collect());
+                        }
+                    }
+                }
+                None
+            }
+        }
+    }
+
+    /// 压缩输出需要按窗口拆分时的窗口大小（秒）
+    fn split_window(&self) -> Option<u64> {
+        match *self {
+            CompactionStrategy::TimeWindow { window, .. } => Some(window.as_secs().max(1)),
+            _ => None,
+        }
+    }
+}
+
+/// 把合并结果在窗口边界处拆分为每个窗口一个内存表，都沿用合并结果的序列号
+///
+/// 输出依次放在最新输入的位置上，序列号相同时靠后的窗口更新。墓碑只作用于比所在文件更旧的数据，
+/// 因此保留的墓碑都放在最早窗口的输出中，不会屏蔽同一次合并中其它窗口的数据；没有数据时不拆分。
+fn split_by_window(merged: MemTable, window_secs: u64) -> Vec<MemTable> {
+    let window_of = |ts: Timestamp| ts / window_secs;
+    let mut parts: BTreeMap<u64, MemTable> = BTreeMap::new();
+    for (_, points) in merged.iter() {
+        for &ts in points.keys() {
+            parts.entry(window_of(ts)).or_insert_with(|| MemTable::with_seq(merged.seq()));
+        }
+    }
+    if parts.len() <= 1 {
+        return vec![merged];
+    }
+
+    // 先写入墓碑再写入数据，合并结果中晚于墓碑写入的数据不会被它删除
+    if let Some(oldest) = parts.values_mut().next() {
+        for tombstone in merged.tombstones() {
+            oldest.delete(tombstone.clone());
+        }
+    }
+    for (series, points) in merged.iter() {
+        for (&ts, &val) in points {
+            if let Some(part) = parts.get_mut(&window_of(ts)) {
+                part.insert(series, ts, val);
+            }
+        }
+    }
+    parts.into_values().collect()
+}
+
+/// 把同一组候选文件（按新旧排序的下标）切分为可以安全合并的若干段
+///
+/// 合并结果会放在最新输入的位置上，因此夹在输入之间、但不参与合并的文件
+/// 不能与合并后的时间范围（包括墓碑的删除范围）重叠，否则会改变它与输入文件之间的新旧关系。
+fn mergeable_chunks(sstables: &[Arc<SSTable>], candidates: &[usize]) -> Vec<Vec<usize>> {
+    let mut chunks: Vec<Vec<usize>> = Vec::new();
+    let mut chunk: Vec<usize> = Vec::new();
+    let mut span: Option<(Timestamp, Timestamp)> = None;
+
+    for &i in candidates {
+        let merged = match (span, sstables[i].time_span()) {
+            (Some((lo, hi)), Some((s_lo, s_hi))) => Some((lo.min(s_lo), hi.max(s_hi))),
+            (span, None) => span,
+            (None, s) => s,
+        };
+        let blocked = chunk.last().is_some_and(|&prev| {
+            sstables[prev + 1..i].iter().any(|skipped| match (merged, skipped.time_span()) {
+                (Some((lo, hi)), Some((s_lo, s_hi))) => s_lo <= hi && s_hi >= lo,
+                _ => false,
+            })
+        });
+
+        if blocked {
+            chunks.push(std::mem::take(&mut chunk));
+            span = sstables[i].time_span();
+        } else {
+            span = merged;
+        }
+        chunk.push(i);
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+/// 压缩统计信息
+#[derive(Debug, Clone, Default)]
+pub struct CompactionStats {
+    pub compactions: u64,
+    pub input_files: u64,
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+    /// 因数据全部过期而整体删除的文件数
+    pub expired_files: u64,
+}
+
+/// 后台压缩器：挑选SSTable合并为新文件并替换输入文件，同时负责删除过期文件
+pub struct Compactor {
+    sstables: Arc<Mutex<Vec<Arc<SSTable>>>>,
+    manifest: Arc<Manifest>,
+    strategy: CompactionStrategy,
+    retention: RetentionPolicy,
+    block_points: usize,
+    stats: Mutex<CompactionStats>,
+    running: Mutex<()>, // 同一时间只允许一个压缩或过期清理任务
+}
+
+impl Compactor {
+    pub fn new(
+        sstables: Arc<Mutex<Vec<Arc<SSTable>>>>,
+        manifest: Arc<Manifest>,
+        strategy: CompactionStrategy,
+        retention: RetentionPolicy,
+        block_points: usize,
+    ) -> Self {
+        Compactor {
+            sstables,
+            manifest,
+            strategy,
+            retention,
+            block_points,
+            stats: Mutex::new(CompactionStats::default()),
+            running: Mutex::new(()),
+        }
+    }
+
+    pub fn strategy(&self) -> &CompactionStrategy {
+        &self.strategy
+    }
+
+    pub fn stats(&self) -> CompactionStats {
+        self.stats.lock().unwrap().clone()
+    }
+
+    /// 执行一轮压缩，没有需要合并的文件时返回false
+    pub fn compact_once(&self) -> Result<bool> {
+        let _running = self.running.lock().unwrap();
+
+        // 只在挑选时持有锁，合并过程中刷盘线程仍可追加新文件。
+        // 同时记下比输出更旧、但不参与合并的文件，输入中的墓碑可能仍需屏蔽它们的数据
+        let (mut inputs, older): (Vec<Arc<SSTable>>, Vec<Arc<SSTable>>) = {
+            let sstables = self.sstables.lock().unwrap();
+            let picked = match self.strategy.pick(&sstables) {
+                Some(picked) => picked,
+                None => return Ok(false),
+            };
+            let newest = *picked.last().unwrap();
+            let older = (0..newest).filter(|i| !picked.contains(i)).map(|i| Arc::clone(&sstables[i])).collect();
+            (picked.iter().map(|&i| Arc::clone(&sstables[i])).collect(), older)
+        };
+
+        // 文件列表按序列号排列，这里再按序列号稳定排序一次，保证合并顺序确定
+        inputs.sort_by_key(|sst| sst.seq());
+        let bytes_read: u64 = inputs.iter().map(|sst| sst.file_size()).sum();
+        info!("开始压缩 {} 个SSTable文件, 共 {} 字节", inputs.len(), bytes_read);
+
+        // 从旧到新依次合并，同一时间戳保留序列号最大的文件中的值，已过期的数据不再写入。
+        // 每个文件的墓碑先作用于之前合并的更旧的数据，再写入该文件自己的数据。
+        // 输出沿用输入中最大的序列号
+        let cutoff = self.retention.cutoff();
+        let mut merged = MemTable::with_seq(inputs.last().unwrap().seq());
+        for sst in &inputs {
+            for tombstone in sst.tombstones() {
+                merged.delete(tombstone.clone());
+            }
+            for series in sst.series_keys() {
+                for (ts, val) in sst.query(series, cutoff, u64::MAX)? {
+                    merged.insert(series, ts, val);
+                }
+            }
+        }
+
+        // 墓碑已作用于所有输入，只保留还可能屏蔽更旧文件中未过期数据的墓碑
+        let tombstones = merged.tombstones().len();
+        merged.retain_tombstones(|t| t.end >= cutoff && older.iter().any(|sst| sst.may_contain_tombstone(t)));
+        if merged.tombstones().len() < tombstones {
+            info!("压缩清除了 {} 个不再需要的墓碑", tombstones - merged.tombstones().len());
+        }
+
+        // 按时间窗口压缩时输出在窗口边界处拆分。输出写为新编号的文件，
+        // 在清单中依次取代最新的输入文件，保持它在新旧顺序中的位置
+        let parts = match self.strategy.split_window() {
+            Some(window_secs) => split_by_window(merged, window_secs),
+            None => vec![merged],
+        };
+        let newest = inputs.last().unwrap();
+        let outputs = parts
+            .iter()
+            .map(|part| SSTable::create(self.manifest.new_file_path(), part, self.block_points).map(Arc::new))
+            .collect::<Result<Vec<_>>>()?;
+        let bytes_written: u64 = outputs.iter().map(|sst| sst.file_size()).sum();
+
+        // 一次提交替换和删除所有输入文件，提交后再替换内存中的文件列表
+        let file_name = |sst: &SSTable| {
+            sst.file_name().ok_or_else(|| Error::DataError(format!("无效的SSTable路径: {:?}", sst.path)))
+        };
+        let edit = VersionEdit {
+            replaced: outputs.iter().map(|output| Ok((file_name(newest)?, file_name(output)?))).collect::<Result<_>>()?,
+            deleted: inputs[..inputs.len() - 1].iter().map(|sst| file_name(sst)).collect::<Result<_>>()?,
+            ..Default::default()
+        };
+        self.manifest.log_and_apply(&edit)?;
+        let obsolete: Vec<PathBuf> = inputs.iter().map(|sst| sst.path.clone()).collect();
+        {
+            let mut sstables = self.sstables.lock().unwrap();
+            match sstables.iter().position(|sst| sst.path == newest.path) {
+                Some(i) => {
+                    sstables.splice(i..=i, outputs.iter().cloned());
+                }
+                None => {
+                    warn!("压缩期间最新输入文件已不在列表中: {:?}", newest.path);
+                    sstables.extend(outputs.iter().cloned());
+                }
+            }
+            sstables.retain(|sst| !obsolete.contains(&sst.path));
+        }
+
+        // 正在进行的查询仍持有旧文件的内存映射，删除文件不影响它们
+        for path in &obsolete {
+            if let Err(e) = fs::remove_file(path) {
+                error!("删除已压缩的SSTable失败 {:?}: {:?}", path, e);
+            }
+        }
+
+        {
+            let mut stats = self.stats.lock().unwrap();
+            stats.compactions += 1;
+            stats.input_files += inputs.len() as u64;
+            stats.bytes_read += bytes_read;
+            stats.bytes_written += bytes_written;
+        }
+        info!("压缩完成: {} 个文件合并为 {:?}, {} 字节 -> {} 字节",
+              inputs.len(), outputs.iter().map(|sst| &sst.path).collect::<Vec<_>>(), bytes_read, bytes_written);
+        Ok(true)
+    }
+
+    /// 删除所有数据和墓碑都已过期（早于保留期限）的SSTable，返回删除的文件数
+    pub fn expire_once(&self) -> Result<usize> {
+        let _running = self.running.lock().unwrap();
+        let cutoff = self.retention.cutoff();
+        if cutoff == 0 {
+            return Ok(0);
+        }
+
+        let expired: Vec<Arc<SSTable>> = {
+            let sstables = self.sstables.lock().unwrap();
+            sstables
+                .iter()
+                .filter(|sst| sst.max_ts() < cutoff && sst.tombstones().iter().all(|t| t.end < cutoff))
+                .cloned()
+                .collect()
+        };
+        if expired.is_empty() {
+            return Ok(0);
+        }
+
+        // 先在清单中删除，再从内存中的文件列表移除
+        let names = expired.iter().filter_map(|sst| sst.file_name()).collect();
+        self.manifest.log_and_apply(&VersionEdit::delete(names))?;
+        self.sstables
+            .lock()
+            .unwrap()
+            .retain(|sst| !expired.iter().any(|e| e.path == sst.path));
+
+        for sst in &expired {
+            info!("SSTable数据已全部过期，删除文件: {:?}, max_ts={}, cutoff={}", sst.path, sst.max_ts(), cutoff);
+            if let Err(e) = fs::remove_file(&sst.path) {
+                error!("删除过期SSTable失败 {:?}: {:?}", sst.path, e);
+            }
+        }
+        self.stats.lock().unwrap().expired_files += expired.len() as u64;
+        Ok(expired.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memtable::Tombstone;
+    use crate::series::SeriesKey;
+
+    /// 接管目录中已创建的文件
+    fn manifest(dir: &std::path::Path) -> Arc<Manifest> {
+        Arc::new(Manifest::open(&dir.to_string_lossy()).unwrap())
+    }
+
+    fn create(dir: &std::path::Path, id: u32, ts: &[u64], val: f64) -> Arc<SSTable> {
+        let series = SeriesKey::parse("cpu,host=a").unwrap();
+        let mut data = MemTable::with_seq(id as u64);
+        for &t in ts {
+            data.insert(&series, t, val);
+        }
+        let path = dir.join(format!("sstable-{}.db", id));
+        Arc::new(SSTable::create(path, &data, 16).unwrap())
+    }
+
+    #[test]
+    fn test_pick_size_tiered() {
+        let dir = tempfile::tempdir().unwrap();
+        let strategy = CompactionStrategy::SizeTiered { min_files: 3, max_files: 4, small_file_bytes: 1 << 20 };
+
+        // 两个不重叠的小文件不够min_files
+        let mut files = vec![create(dir.path(), 1, &[1, 2], 0.0), create(dir.path(), 2, &[3, 4], 0.0)];
+        assert_eq!(strategy.pick(&files), None);
+
+        // 第三个小文件凑够数量
+        files.push(create(dir.path(), 3, &[5, 6], 0.0));
+        assert_eq!(strategy.pick(&files), Some(vec![0, 1, 2]));
+
+        // 时间范围重叠的两个大文件即使数量不够也会合并
+        let strategy = CompactionStrategy::SizeTiered { min_files: 3, max_files: 4, small_file_bytes: 0 };
+        let files = vec![create(dir.path(), 4, &[1, 2], 0.0), create(dir.path(), 5, &[10, 20], 0.0),
+                         create(dir.path(), 6, &[15, 30], 0.0)];
+        assert_eq!(strategy.pick(&files), Some(vec![1, 2]));
+        assert_eq!(CompactionStrategy::Disabled.pick(&files), None);
+    }
+
+    #[test]
+    fn test_pick_time_window() {
+        let dir = tempfile::tempdir().unwrap();
+        let strategy = CompactionStrategy::TimeWindow {
+            window: Duration::from_secs(100),
+            active_windows: 1,
+            min_files: 3,
+            max_files: 8,
+        };
+
+        // 窗口0已封存且只有一个文件，窗口1是活跃窗口但文件数不够
+        let files = vec![
+            create(dir.path(), 1, &[10, 20], 0.0),
+            create(dir.path(), 2, &[110, 120], 0.0),
+            create(dir.path(), 3, &[130, 140], 0.0),
+        ];
+        assert_eq!(strategy.pick(&files), None);
+
+        // 迟到数据写入封存的窗口0后，窗口0被合并成单个文件，跳过中间窗口1的文件
+        let mut files = files;
+        files.push(create(dir.path(), 4, &[30, 40], 0.0));
+        assert_eq!(strategy.pick(&files), Some(vec![0, 3]));
+
+        // 活跃窗口凑够3个文件
+        files.push(create(dir.path(), 5, &[150, 160], 0.0));
+        assert_eq!(strategy.pick(&files), Some(vec![1, 2, 4]));
+
+        // 夹在中间且删除范围重叠的文件会阻止跨越它合并
+        let mut data = MemTable::with_seq(7);
+        data.delete(Tombstone { series: None, start: 15, end: 20 });
+        data.insert(&SeriesKey::parse("cpu,host=a").unwrap(), 150, 0.0);
+        let files = vec![
+            create(dir.path(), 6, &[10, 20], 0.0),
+            Arc::new(SSTable::create(dir.path().join("sstable-7.db"), &data, 16).unwrap()),
+            create(dir.path(), 8, &[30, 40], 0.0),
+            create(dir.path(), 9, &[300], 0.0),
+        ];
+        assert_eq!(strategy.pick(&files), None);
+    }
+
+    #[test]
+    fn test_pick_time_window_crossing() {
+        let dir = tempfile::tempdir().unwrap();
+        let strategy = CompactionStrategy::TimeWindow {
+            window: Duration::from_secs(100),
+            active_windows: 2,
+            min_files: 3,
+            max_files: 8,
+        };
+
+        // 只在活跃窗口2和3之间跨越边界的文件等文件数凑够再合并
+        let mut files = vec![create(dir.path(), 1, &[10, 20], 0.0), create(dir.path(), 2, &[250, 310], 0.0)];
+        assert_eq!(strategy.pick(&files), None);
+
+        // 跨越封存窗口0和1边界的文件即使只有一个也要拆分
+        files.push(create(dir.path(), 3, &[90, 110], 0.0));
+        assert_eq!(strategy.pick(&files), Some(vec![2]));
+
+        // 跨越的文件也属于窗口0，与窗口0中的文件一起凑够活跃窗口的文件数
+        let strategy = CompactionStrategy::TimeWindow {
+            window: Duration::from_secs(100),
+            active_windows: 10,
+            min_files: 3,
+            max_files: 8,
+        };
+        let files = vec![create(dir.path(), 4, &[90, 110], 0.0), create(dir.path(), 5, &[10, 20], 0.0),
+                         create(dir.path(), 6, &[30, 40], 0.0)];
+        assert_eq!(strategy.pick(&files), Some(vec![0, 1, 2]));
+    }
+
+    #[test]
+    fn test_compact_newest_wins() {
+        let dir = tempfile::tempdir().unwrap();
+        let series = SeriesKey::parse("cpu,host=a").unwrap();
+        let files = vec![
+            create(dir.path(), 1, &[1, 2, 3], 1.0),
+            create(dir.path(), 2, &[2, 3, 4], 2.0),
+            create(dir.path(), 3, &[3, 5], 3.0),
+        ];
+        let sstables = Arc::new(Mutex::new(files));
+        let strategy = CompactionStrategy::SizeTiered { min_files: 2, max_files: 8, small_file_bytes: 1 << 20 };
+        let compactor = Compactor::new(Arc::clone(&sstables), manifest(dir.path()), strategy, RetentionPolicy::default(), 16);
+
+        assert!(compactor.compact_once().unwrap());
+        assert!(!compactor.compact_once().unwrap());
+
+        let sstables = sstables.lock().unwrap();
+        assert_eq!(sstables.len(), 1);
+        assert_eq!(sstables[0].path, dir.path().join("sstable-000004.db"));
+        assert_eq!(sstables[0].seq(), 3);
+        assert_eq!(sstables[0].query(&series, 0, u64::MAX).unwrap(),
+                   vec![(1, 1.0), (2, 2.0), (3, 3.0), (4, 2.0), (5, 3.0)]);
+        assert!(!dir.path().join("sstable-1.db").exists());
+        assert!(!dir.path().join("sstable-2.db").exists());
+        assert!(!dir.path().join("sstable-3.db").exists());
+
+        let stats = compactor.stats();
+        assert_eq!(stats.compactions, 1);
+        assert_eq!(stats.input_files, 3);
+    }
+
+    #[test]
+    fn test_compact_tombstones() {
+        let dir = tempfile::tempdir().unwrap();
+        let series = SeriesKey::parse("cpu,host=a").unwrap();
+        let mut files = vec![
+            create(dir.path(), 1, &[1, 2, 3], 1.0),
+            create(dir.path(), 2, &[50, 100], 2.0),
+        ];
+
+        // 第3个文件删除了[2, 3]和所有序列的[100, 100]，之后又写入了时间戳3和60
+        let mut data = MemTable::with_seq(3);
+        data.delete(Tombstone { series: Some(series.clone()), start: 2, end: 3 });
+        data.delete(Tombstone { series: None, start: 100, end: 100 });
+        data.insert(&series, 3, 3.0);
+        data.insert(&series, 60, 3.0);
+        files.push(Arc::new(SSTable::create(dir.path().join("sstable-3.db"), &data, 16).unwrap()));
+
+        // 先合并后两个文件，墓碑[2, 3]仍需屏蔽未参与合并的第1个文件，墓碑[100, 100]已无作用
+        let sstables = Arc::new(Mutex::new(files));
+        let strategy = CompactionStrategy::SizeTiered { min_files: 2, max_files: 2, small_file_bytes: 0 };
+        let compactor = Compactor::new(Arc::clone(&sstables), manifest(dir.path()), strategy, RetentionPolicy::default(), 16);
+        let first = Arc::clone(&sstables.lock().unwrap()[0]);
+        assert!(compactor.compact_once().unwrap());
+        {
+            let sstables = sstables.lock().unwrap();
+            assert_eq!(sstables.len(), 2);
+            assert_eq!(sstables[1].query(&series, 0, u64::MAX).unwrap(), vec![(3, 3.0), (50, 2.0), (60, 3.0)]);
+            assert_eq!(sstables[1].tombstones(), &[Tombstone { series: Some(series.clone()), start: 2, end: 3 }]);
+        }
+
+        // 墓碑范围与第1个文件重叠，全部合并后墓碑被清除
+        assert!(compactor.compact_once().unwrap());
+        let sstables = sstables.lock().unwrap();
+        assert_eq!(sstables.len(), 1);
+        assert_eq!(sstables[0].query(&series, 0, u64::MAX).unwrap(),
+                   vec![(1, 1.0), (3, 3.0), (50, 2.0), (60, 3.0)]);
+        assert!(sstables[0].tombstones().is_empty());
+        assert!(!first.path.exists());
+    }
+
+    #[test]
+    fn test_compact_split_windows() {
+        let dir = tempfile::tempdir().unwrap();
+        let series = SeriesKey::parse("cpu,host=a").unwrap();
+
+        // 第2个文件跨越窗口0、1、2，并删除了第1个文件中时间戳20的数据
+        let mut data = MemTable::with_seq(2);
+        data.delete(Tombstone { series: Some(series.clone()), start: 20, end: 20 });
+        for ts in [50, 150, 250] {
+            data.insert(&series, ts, 2.0);
+        }
+        let files = vec![
+            create(dir.path(), 1, &[10, 20], 1.0),
+            Arc::new(SSTable::create(dir.path().join("sstable-2.db"), &data, 16).unwrap()),
+            create(dir.path(), 3, &[400], 3.0),
+        ];
+        let sstables = Arc::new(Mutex::new(files));
+        let strategy = CompactionStrategy::TimeWindow {
+            window: Duration::from_secs(100),
+            active_windows: 1,
+            min_files: 4,
+            max_files: 8,
+        };
+        let compactor = Compactor::new(Arc::clone(&sstables), manifest(dir.path()), strategy, RetentionPolicy::default(), 16);
+
+        // 跨越窗口的文件按窗口拆分为3个文件，依次占据它的位置，墓碑放在最早窗口的输出中
+        assert!(compactor.compact_once().unwrap());
+        {
+            let sstables = sstables.lock().unwrap();
+            let spans: Vec<_> = sstables.iter().map(|sst| (sst.seq(), sst.min_ts(), sst.max_ts())).collect();
+            assert_eq!(spans, vec![(1, 10, 20), (2, 50, 50), (2, 150, 150), (2, 250, 250), (3, 400, 400)]);
+            assert_eq!(sstables[1].tombstones(), &[Tombstone { series: Some(series.clone()), start: 20, end: 20 }]);
+        }
+
+        // 窗口0中的两个文件再合并，墓碑作用后被清除，之后每个窗口只剩一个文件
+        assert!(compactor.compact_once().unwrap());
+        assert!(!compactor.compact_once().unwrap());
+        let sstables = sstables.lock().unwrap();
+        let spans: Vec<_> = sstables.iter().map(|sst| (sst.seq(), sst.min_ts(), sst.max_ts())).collect();
+        assert_eq!(spans, vec![(2, 10, 50), (2, 150, 150), (2, 250, 250), (3, 400, 400)]);
+        assert_eq!(sstables[0].query(&series, 0, u64::MAX).unwrap(), vec![(10, 1.0), (50, 2.0)]);
+        assert!(sstables[0].tombstones().is_empty());
+
+        // 清单中的文件顺序与内存中的列表一致
+        let names: Vec<String> = sstables.iter().filter_map(|sst| sst.file_name()).collect();
+        assert_eq!(manifest(dir.path()).live_files(), names);
+    }
+
+    #[test]
+    fn test_retention() {
+        let dir = tempfile::tempdir().unwrap();
+        let series = SeriesKey::parse("cpu,host=a").unwrap();
+        let now = chrono::Utc::now().timestamp() as u64;
+        let hour = 3600;
+        let files = vec![
+            create(dir.path(), 1, &[now - 5 * hour, now - 4 * hour], 1.0),
+            create(dir.path(), 2, &[now - 3 * hour, now - hour], 2.0),
+            create(dir.path(), 3, &[now - 3 * hour + 1, now], 3.0),
+        ];
+        let sstables = Arc::new(Mutex::new(files));
+        let strategy = CompactionStrategy::SizeTiered { min_files: 8, max_files: 8, small_file_bytes: 0 };
+        let retention = RetentionPolicy::new(Some(Duration::from_secs(2 * hour)));
+        let compactor = Compactor::new(Arc::clone(&sstables), manifest(dir.path()), strategy, retention, 16);
+
+        // 整个文件过期时直接删除
+        assert_eq!(compactor.expire_once().unwrap(), 1);
+        assert!(!dir.path().join("sstable-1.db").exists());
+        assert_eq!(sstables.lock().unwrap().len(), 2);
+
+        // 部分过期的文件在压缩时去掉过期数据
+        assert!(compactor.compact_once().unwrap());
+        let sstables = sstables.lock().unwrap();
+        assert_eq!(sstables.len(), 1);
+        assert_eq!(sstables[0].query(&series, 0, u64::MAX).unwrap(), vec![(now - hour, 2.0), (now, 3.0)]);
+        assert_eq!(compactor.stats().expired_files, 1);
+    }
+}
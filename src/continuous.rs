@@ -0,0 +1,224 @@
+use std::{
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+
+use log::info;
+
+use crate::aggregate::Aggregation;
+use crate::error::{Error, Result};
+use crate::index::Matcher;
+use crate::manifest::sync_dir;
+use crate::series::SeriesKey;
+use crate::wal::Timestamp;
+
+/// 连续查询：每隔`interval`对选出的序列计算上一个完整区间的聚合值，写入`target`指标
+///
+/// 不分组时每个序列单独聚合，结果序列为`target`加上源序列的标签；按`group_by`分组时，
+/// 这些标签的值相同的序列合并后一起聚合，结果序列只带分组标签。时间戳为区间的起点。
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContinuousQuery {
+    pub name: String,
+    /// 选择源序列的标签选择器，如`{__name__="latency"}`
+    pub selector: String,
+    pub aggregation: Aggregation,
+    pub interval: Duration,
+    pub target: String,
+    /// 分组的标签名，如`host`；源序列没有该标签时结果序列也不带它
+    pub group_by: Vec<String>,
+}
+
+impl ContinuousQuery {
+    /// 检查定义是否有效，返回解析后的标签匹配器
+    pub fn validate(&self) -> Result<Vec<Matcher>> {
+        if self.name.is_empty() || self.name.contains(char::is_whitespace) {
+            return Err(Error::DataError(format!("连续查询名称无效: '{}'", self.name)));
+        }
+        if self.interval.as_secs() == 0 {
+            return Err(Error::DataError("连续查询的间隔至少为1秒".to_string()));
+        }
+        if self.selector.contains(['\t', '\n']) {
+            return Err(Error::DataError("标签选择器中不能有制表符或换行".to_string()));
+        }
+        if let Some(tag) = self.group_by.iter().find(|t| t.is_empty() || t.contains([',', '=', '\t']) || t.contains(char::is_whitespace)) {
+            return Err(Error::DataError(format!("分组标签名无效: '{}'", tag)));
+        }
+        if !self.group_by.is_empty() && !self.aggregation.combines_series() {
+            return Err(Error::DataError(format!("聚合函数 {} 与数据点的顺序有关，不能合并多个序列分组计算", self.aggregation)));
+        }
+        SeriesKey::new(&self.target, Vec::<(String, String)>::new())?;
+        Matcher::parse_selector(&self.selector)
+    }
+
+    /// 结果序列：目标指标加上源序列的标签，分组时只保留分组标签；结果序列相同的源序列属于同一组
+    pub fn target_series(&self, source: &SeriesKey) -> Result<SeriesKey> {
+        let tags = source
+            .tags()
+            .iter()
+            .filter(|(k, _)| self.group_by.is_empty() || self.group_by.contains(k))
+            .map(|(k, v)| (k.as_str(), v.as_str()));
+        SeriesKey::new(&self.target, tags)
+    }
+}
+
+/// 已注册的连续查询及其进度
+#[derive(Debug, Clone)]
+pub struct ContinuousQueryState {
+    pub query: ContinuousQuery,
+    /// 早于该时间戳的区间都已计算，0表示还没有运行过
+    pub watermark: Timestamp,
+}
+
+/// 连续查询的注册表，定义和进度保存在一个文本文件中
+///
+/// 每行一个查询：`name \t interval_secs \t aggregation \t target \t watermark \t group_by \t selector`，
+/// `group_by`为逗号分隔的标签名，不分组时为空。每次修改都先写临时文件再原子替换。
+pub struct ContinuousQueries {
+    path: PathBuf,
+    queries: Mutex<Vec<ContinuousQueryState>>,
+}
+
+impl ContinuousQueries {
+    /// 加载注册表文件，不存在时为空
+    pub fn open(path: &str) -> Result<Self> {
+        let path = PathBuf::from(path);
+        let mut queries = Vec::new();
+        match fs::read_to_string(&path) {
+            Ok(content) => {
+                for (i, line) in content.lines().enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+                    let state = parse_line(line)
+                        .map_err(|e| Error::DataError(format!("连续查询文件 {:?} 第 {} 行无效: {}", path, i + 1, e)))?;
+                    queries.push(state);
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(Error::IoError(e)),
+        }
+        info!("加载了 {} 个连续查询", queries.len());
+        Ok(ContinuousQueries { path, queries: Mutex::new(queries) })
+    }
+
+    /// 注册一个连续查询，名称不能重复
+    pub fn register(&self, query: ContinuousQuery) -> Result<()> {
+        query.validate()?;
+        let mut queries = self.queries.lock().unwrap();
+        if queries.iter().any(|q| q.query.name == query.name) {
+            return Err(Error::DataError(format!("连续查询 {} 已存在", query.name)));
+        }
+        queries.push(ContinuousQueryState { query, watermark: 0 });
+        self.save(&queries)
+    }
+
+    /// 删除连续查询，不存在时返回false
+    pub fn drop_query(&self, name: &str) -> Result<bool> {
+        let mut queries = self.queries.lock().unwrap();
+        let before = queries.len();
+        queries.retain(|q| q.query.name != name);
+        if queries.len() == before {
+            return Ok(false);
+        }
+        self.save(&queries)?;
+        Ok(true)
+    }
+
+    pub fn list(&self) -> Vec<ContinuousQueryState> {
+        self.queries.lock().unwrap().clone()
+    }
+
+    /// 记录查询的进度，查询已被删除时忽略
+    pub fn set_watermark(&self, name: &str, watermark: Timestamp) -> Result<()> {
+        let mut queries = self.queries.lock().unwrap();
+        match queries.iter_mut().find(|q| q.query.name == name) {
+            Some(state) => state.watermark = watermark,
+            None => return Ok(()),
+        }
+        self.save(&queries)
+    }
+
+    fn save(&self, queries: &[ContinuousQueryState]) -> Result<()> {
+        let dir = self.path.parent().filter(|d| !d.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        fs::create_dir_all(dir)?;
+        let tmp_path = self.path.with_extension("tmp");
+        {
+            let mut tmp = File::create(&tmp_path)?;
+            for state in queries {
+                let q = &state.query;
+                writeln!(tmp, "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                         q.name, q.interval.as_secs(), q.aggregation, q.target, state.watermark,
+                         q.group_by.join(","), q.selector)?;
+            }
+            tmp.sync_all()?;
+        }
+        fs::rename(&tmp_path, &self.path)?;
+        sync_dir(dir)
+    }
+}
+
+fn parse_line(line: &str) -> Result<ContinuousQueryState> {
+    let fields: Vec<&str> = line.splitn(7, '\t').collect();
+    if fields.len() != 7 {
+        return Err(Error::DataError("字段数不足".to_string()));
+    }
+    let number = |s: &str| s.parse::<u64>().map_err(|_| Error::DataError(format!("不是数字: {}", s)));
+    let query = ContinuousQuery {
+        name: fields[0].to_string(),
+        interval: Duration::from_secs(number(fields[1])?),
+        aggregation: Aggregation::parse(fields[2])?,
+        target: fields[3].to_string(),
+        selector: fields[6].to_string(),
+        group_by: fields[5].split(',').filter(|t| !t.is_empty()).map(|t| t.to_string()).collect(),
+    };
+    query.validate()?;
+    Ok(ContinuousQueryState { query, watermark: number(fields[4])? })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_and_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cq").to_string_lossy().into_owned();
+        let query = ContinuousQuery {
+            name: "latency_max".to_string(),
+            selector: r#"{__name__="latency", host=~"a|b"}"#.to_string(),
+            aggregation: Aggregation::Max,
+            interval: Duration::from_secs(60),
+            target: "latency_max_1m".to_string(),
+            group_by: vec!["host".to_string(), "dc".to_string()],
+        };
+
+        let registry = ContinuousQueries::open(&path).unwrap();
+        registry.register(query.clone()).unwrap();
+        assert!(registry.register(query.clone()).is_err());
+        assert!(registry.register(ContinuousQuery { name: "bad".to_string(), selector: "{".to_string(), ..query.clone() }).is_err());
+        assert!(registry.register(ContinuousQuery { name: "bad".to_string(), group_by: vec!["a b".to_string()], ..query.clone() }).is_err());
+        // 与顺序有关的聚合函数不能合并多个序列
+        assert!(registry.register(ContinuousQuery { name: "bad".to_string(), aggregation: Aggregation::Rate, ..query.clone() }).is_err());
+        registry.set_watermark("latency_max", 120).unwrap();
+
+        let registry = ContinuousQueries::open(&path).unwrap();
+        let queries = registry.list();
+        assert_eq!(queries.len(), 1);
+        assert_eq!(queries[0].query, query);
+        assert_eq!(queries[0].watermark, 120);
+
+        // 分组时只保留分组标签，不分组时保留源序列的所有标签
+        let source = SeriesKey::parse("latency,dc=x,host=a,path=/").unwrap();
+        assert_eq!(query.target_series(&source).unwrap(), SeriesKey::parse("latency_max_1m,dc=x,host=a").unwrap());
+        let ungrouped = ContinuousQuery { group_by: Vec::new(), ..query.clone() };
+        assert_eq!(ungrouped.target_series(&source).unwrap(), SeriesKey::parse("latency_max_1m,dc=x,host=a,path=/").unwrap());
+
+        assert!(registry.drop_query("latency_max").unwrap());
+        assert!(!registry.drop_query("latency_max").unwrap());
+        assert!(ContinuousQueries::open(&path).unwrap().list().is_empty());
+
+        // 字段数不对的行报错
+        fs::write(&path, "cpu_avg\t60\tavg\tcpu_avg_1m\t120\t{__name__=\"cpu\"}\n").unwrap();
+        assert!(matches!(ContinuousQueries::open(&path), Err(Error::DataError(_))));
+    }
+}
@@ -1,5 +1,6 @@
 use std::{
     collections::BTreeMap,
+    path::Path,
     sync::{Arc, Mutex},
     thread,
     time::Duration,
@@ -8,19 +9,37 @@ use std::{
 use log::{debug, error, info};
 
 use crate::{
-    error::Result,
-    sstable::SSTable,
-    wal::{Timestamp, Value, Wal},
+    aggregate::{self, Accumulator, Aggregation, Fill, Sample, SampleIter, Window},
+    compaction::{CompactionStats, CompactionStrategy, Compactor},
+    continuous::{ContinuousQueries, ContinuousQuery, ContinuousQueryState},
+    error::{Error, Result},
+    flush::Flusher,
+    index::{Matcher, TagIndex},
+    iter::{Order, PointSource, QueryIter},
+    manifest::Manifest,
+    memtable::{MemTable, Tombstone},
+    retention::RetentionPolicy,
+    rollup::{Roller, RollupRule, RollupTable},
+    series::SeriesKey,
+    sstable::{BlockHandle, DEFAULT_BLOCK_POINTS, SSTable},
+    wal::{Timestamp, Value, Wal, WalRecovery, WalSyncMode},
 };
 
+/// 单个序列的查询结果
+pub type SeriesPoints = (SeriesKey, Vec<(Timestamp, Value)>);
+
 /// 简易LSM-Tree TSDB结构
 pub struct SimpleTSDB {
-    memtable: Arc<Mutex<BTreeMap<Timestamp, Value>>>,
+    memtable: Arc<Mutex<MemTable>>,
+    index: Arc<TagIndex>,
     wal: Arc<Wal>,
-    sstables: Arc<Mutex<Vec<SSTable>>>,
-    sstable_dir: String,
-    wal_path: String,
-    memtable_size_threshold: usize,
+    sstables: Arc<Mutex<Vec<Arc<SSTable>>>>,
+    flusher: Arc<Flusher>,
+    compactor: Arc<Compactor>,
+    roller: Arc<Roller>,
+    continuous: ContinuousQueries,
+    retention: RetentionPolicy,
+    wal_recovery: WalRecovery,
 }
 
 impl SimpleTSDB {
@@ -29,151 +48,876 @@ impl SimpleTSDB {
         std::fs::create_dir_all(&config.sstable_dir)?;
         
         // 初始化WAL并恢复MemTable
-        let wal = Arc::new(Wal::open(&config.wal_path)?);
-        let memtable = wal.load()?;
+        let wal = Arc::new(Wal::open(&config.wal_dir, config.wal_sync)?);
+        let (mut memtable, wal_recovery) = wal.load()?;
 
-        // 加载现有的SSTable文件
+        // 按清单加载已提交的SSTable文件，并清理刷盘或压缩中途崩溃留下的文件
+        let manifest = Arc::new(Manifest::open(&config.sstable_dir)?);
+        manifest.remove_orphans()?;
         let mut sstables = Vec::new();
-        if let Ok(entries) = std::fs::read_dir(&config.sstable_dir) {
-            for entry in entries.flatten() {
-                if entry.path().extension().and_then(|s| s.to_str()) == Some("db") {
-                    match SSTable::open(entry.path()) {
-                        Ok(sst) => sstables.push(sst),
-                        Err(e) => error!("加载SSTable失败: {:?}", e),
-                    }
-                }
-            }
+        for name in manifest.live_files() {
+            let path = Path::new(&config.sstable_dir).join(&name);
+            let sst = SSTable::open(path)
+                .map_err(|e| Error::DataError(format!("加载清单中的SSTable {} 失败: {}", name, e)))?;
+            sstables.push(Arc::new(sst));
+        }
+        // 文件按序列号从旧到新排列，没有序列号的旧文件保持清单中的顺序；
+        // 从WAL恢复的MemTable比所有SSTable都新，也比清单记录的、可能已被删除的文件新
+        sstables.sort_by_key(|sst| sst.seq());
+        let last_seq = manifest.last_seq().max(sstables.last().map_or(0, |sst| sst.seq()));
+        memtable.set_seq(last_seq + 1);
+
+        // 打开倒排索引，并补登记索引文件中缺失的序列（如索引文件丢失）
+        let index = Arc::new(TagIndex::open(&config.index_path)?);
+        for series in memtable.series_keys().chain(sstables.iter().flat_map(|sst| sst.series_keys())) {
+            index.get_or_register(series)?;
+        }
+
+        let memtable = Arc::new(Mutex::new(memtable));
+        let sstables = Arc::new(Mutex::new(sstables));
+        let flusher = Arc::new(Flusher::new(
+            Arc::clone(&memtable),
+            Arc::clone(&wal),
+            Arc::clone(&sstables),
+            Arc::clone(&manifest),
+            config.block_points,
+        ));
+        let retention = RetentionPolicy::new(config.retention);
+        let compactor = Arc::new(Compactor::new(
+            Arc::clone(&sstables),
+            manifest,
+            config.compaction.clone(),
+            retention,
+            config.block_points,
+        ));
+        let mut rollup_tables = Vec::new();
+        for rule in &config.rollups {
+            rollup_tables.push(Arc::new(RollupTable::open(&config.sstable_dir, rule, config.block_points)?));
         }
+        let roller = Arc::new(Roller::new(Arc::clone(&sstables), rollup_tables, retention));
+        let continuous = ContinuousQueries::open(&config.continuous_query_path)?;
 
         let db = SimpleTSDB {
-            memtable: Arc::new(Mutex::new(memtable)),
-            wal: Arc::clone(&wal),
-            sstables: Arc::new(Mutex::new(sstables)),
-            sstable_dir: config.sstable_dir.clone(),
-            wal_path: config.wal_path.clone(),
-            memtable_size_threshold: config.memtable_size_threshold,
+            memtable,
+            index,
+            wal,
+            sstables,
+            flusher,
+            compactor,
+            roller,
+            continuous,
+            retention,
+            wal_recovery,
         };
 
         // 启动后台刷盘线程
         {
             let memtable = Arc::clone(&db.memtable);
-            let wal = Arc::clone(&wal);
-            let sstables = Arc::clone(&db.sstables);
-            let sstable_dir = config.sstable_dir.clone();
+            let flusher = Arc::clone(&db.flusher);
             let threshold = config.memtable_size_threshold;
             
             thread::spawn(move || loop {
                 thread::sleep(Duration::from_secs(5));
-                let mut mem = memtable.lock().unwrap();
-                if mem.len() >= threshold {
+                let full = memtable.lock().unwrap().len() >= threshold;
+                if full {
                     info!("MemTable达到阈值，开始刷盘");
-                    match SSTable::create(&sstable_dir, &mem) {
-                        Ok(sst) => {
-                            sstables.lock().unwrap().push(sst);
-                            mem.clear();
-                            if let Err(e) = wal.clear() {
-                                error!("清空WAL失败: {:?}", e);
-                            }
-                            info!("刷盘完成");
+                    if let Err(e) = flusher.flush() {
+                        error!("刷盘失败: {:?}", e);
+                    }
+                }
+            });
+        }
+
+        // 启动后台WAL同步线程
+        if let WalSyncMode::Interval(interval) = config.wal_sync {
+            let wal = Arc::clone(&db.wal);
+            thread::spawn(move || loop {
+                thread::sleep(interval);
+                if let Err(e) = wal.sync() {
+                    error!("WAL同步失败: {:?}", e);
+                }
+            });
+        }
+
+        // 启动后台压缩线程
+        if !matches!(config.compaction, CompactionStrategy::Disabled) {
+            let compactor = Arc::clone(&db.compactor);
+            thread::spawn(move || loop {
+                thread::sleep(Duration::from_secs(10));
+                // 一次把能合并的都合并完
+                loop {
+                    match compactor.compact_once() {
+                        Ok(true) => continue,
+                        Ok(false) => break,
+                        Err(e) => {
+                            error!("压缩失败: {:?}", e);
+                            break;
                         }
-                        Err(e) => error!("刷盘失败: {:?}", e),
                     }
                 }
             });
         }
 
+        // 启动后台过期清理线程
+        if config.retention.is_some() {
+            let compactor = Arc::clone(&db.compactor);
+            thread::spawn(move || loop {
+                thread::sleep(Duration::from_secs(60));
+                if let Err(e) = compactor.expire_once() {
+                    error!("过期数据清理失败: {:?}", e);
+                }
+            });
+        }
+
+        // 启动后台降采样线程
+        if !config.rollups.is_empty() {
+            let roller = Arc::clone(&db.roller);
+            thread::spawn(move || loop {
+                thread::sleep(Duration::from_secs(30));
+                if let Err(e) = roller.run_once() {
+                    error!("降采样失败: {:?}", e);
+                }
+            });
+        }
+
         info!("TSDB初始化完成，加载了{}个SSTable文件", db.sstables.lock().unwrap().len());
         Ok(db)
     }
 
-    /// 写入单条数据
-    pub fn put(&self, ts: Timestamp, value: Value) -> Result<()> {
-        self.wal.append(ts, value)?;
-        let mut mem = self.memtable.lock().unwrap();
-        mem.insert(ts, value);
-        debug!("写入MemTable ts={}, value={}", ts, value);
-        Ok(())
+    /// 向指定序列写入单条数据
+    pub fn put(&self, series: &SeriesKey, ts: Timestamp, value: Value) -> Result<()> {
+        self.index.get_or_register(series)?;
+        // 持有MemTable的锁写WAL，保证刷盘封存的WAL段与冻结的MemTable一致
+        let lsn = {
+            let mut mem = self.memtable.lock().unwrap();
+            let lsn = self.wal.append(series, ts, value)?;
+            mem.insert(series, ts, value);
+            lsn
+        };
+        debug!("写入MemTable series={}, ts={}, value={}", series, ts, value);
+        self.wal.commit(lsn)
+    }
+
+    /// 向指定序列批量写入数据
+    pub fn batch_put(&self, series: &SeriesKey, data: &[(Timestamp, Value)]) -> Result<()> {
+        self.index.get_or_register(series)?;
+        let lsn = {
+            let mut mem = self.memtable.lock().unwrap();
+            let lsn = self.wal.batch_append(series, data)?;
+            for &(ts, value) in data {
+                mem.insert(series, ts, value);
+            }
+            lsn
+        };
+        debug!("批量写入{}条数据到MemTable series={}", data.len(), series);
+        self.wal.commit(lsn)
     }
 
-    /// 批量写入数据
-    pub fn batch_put(&self, data: &[(Timestamp, Value)]) -> Result<()> {
-        self.wal.batch_append(data)?;
-        let mut mem = self.memtable.lock().unwrap();
-        for &(ts, value) in data {
-            mem.insert(ts, value);
+    /// 查询指定序列的区间数据
+    pub fn query(&self, series: &SeriesKey, start: Timestamp, end: Timestamp) -> Result<Vec<(Timestamp, Value)>> {
+        let result = self.query_iter(series, start, end)?.collect::<Result<Vec<_>>>()?;
+        info!("查询序列{}区间[{}, {}]返回{}条数据", series, start, end, result.len());
+        Ok(result)
+    }
+
+    /// 按选项查询指定序列的区间数据：按`order`排列，跳过`offset`个点后最多返回`limit`个点
+    ///
+    /// 取够`limit`个点后不再解码剩余的块。
+    pub fn query_with(
+        &self,
+        series: &SeriesKey,
+        start: Timestamp,
+        end: Timestamp,
+        options: QueryOptions,
+    ) -> Result<Vec<(Timestamp, Value)>> {
+        let result = self
+            .query_iter_ordered(series, start, end, options.order)?
+            .skip(options.offset)
+            .take(options.limit.unwrap_or(usize::MAX))
+            .collect::<Result<Vec<_>>>()?;
+        info!("查询序列{}区间[{}, {}] {:?}返回{}条数据", series, start, end, options, result.len());
+        Ok(result)
+    }
+
+    /// 按时间升序惰性返回指定序列的区间数据
+    ///
+    /// 只拷贝MemTable和不可变MemTable中区间内的数据，SSTable按块逐个解码；
+    /// 迭代器持有创建时的SSTable集合，之后的刷盘和压缩不影响已创建的迭代器。
+    pub fn query_iter(&self, series: &SeriesKey, start: Timestamp, end: Timestamp) -> Result<QueryIter> {
+        self.query_iter_ordered(series, start, end, Order::Ascending)
+    }
+
+    /// 按指定顺序惰性返回指定序列的区间数据，倒序时SSTable从最后一个块开始逐块解码
+    pub fn query_iter_ordered(&self, series: &SeriesKey, start: Timestamp, end: Timestamp, order: Order) -> Result<QueryIter> {
+        let snapshot = self.snapshot(series, start, end);
+        let blocks = snapshot
+            .sstables
+            .iter()
+            .map(|sst| sst.overlapping_blocks(series, snapshot.start, snapshot.end).to_vec())
+            .collect();
+        snapshot.merge(blocks, order)
+    }
+
+    /// 指定序列在`ts`处的值，从新到旧查找各数据来源，找到即返回
+    ///
+    /// 同一来源中的数据比它自己的墓碑新；更新来源的墓碑覆盖`ts`时，更旧来源中的值不再可见。
+    pub fn get(&self, series: &SeriesKey, ts: Timestamp) -> Result<Option<Value>> {
+        if ts < self.retention.cutoff() {
+            return Ok(None);
         }
-        debug!("批量写入{}条数据到MemTable", data.len());
-        Ok(())
+        let masked = |tombstones: &[Tombstone]| tombstones.iter().any(|t| t.covers(series, ts));
+        {
+            let mem = self.memtable.lock().unwrap();
+            if let Some(value) = mem.get(series, ts) {
+                return Ok(Some(value));
+            }
+            if masked(mem.tombstones()) {
+                return Ok(None);
+            }
+        }
+        if let Some(frozen) = self.flusher.immutable() {
+            if let Some(value) = frozen.get(series, ts) {
+                return Ok(Some(value));
+            }
+            if masked(frozen.tombstones()) {
+                return Ok(None);
+            }
+        }
+        for sst in self.sstables_newest_first() {
+            // 块索引定位到至多一个可能包含ts的块
+            let handles = sst.overlapping_blocks(series, ts, ts).to_vec();
+            if let Some(point) = sst.iter_blocks(handles, ts, ts).next() {
+                return Ok(Some(point?.1));
+            }
+            if masked(sst.tombstones()) {
+                return Ok(None);
+            }
+        }
+        Ok(None)
     }
 
-    /// 查询区间数据
-    pub fn query(&self, start: Timestamp, end: Timestamp) -> Result<Vec<(Timestamp, Value)>> {
-        let mut result = Vec::new();
+    /// 指定序列最新的数据点
+    pub fn last(&self, series: &SeriesKey) -> Result<Option<(Timestamp, Value)>> {
+        self.last_before(series, Timestamp::MAX)
+    }
 
-        // 先查MemTable
+    /// 指定序列不晚于`ts`的最后一个数据点
+    ///
+    /// 从`ts`向前逐段查找：每段的下界是各来源中最靠后的块的起点（MemTable中为最后一个点），
+    /// 只解码与该段有交集的块；段内的数据全被墓碑屏蔽时继续查找更早的一段。
+    pub fn last_before(&self, series: &SeriesKey, ts: Timestamp) -> Result<Option<(Timestamp, Value)>> {
+        let cutoff = self.retention.cutoff();
+        let mut hi = ts;
+        while hi >= cutoff {
+            let mut lo = None;
+            {
+                let mem = self.memtable.lock().unwrap();
+                lo = lo.max(mem.last_before(series, hi).map(|(ts, _)| ts));
+            }
+            if let Some(frozen) = self.flusher.immutable() {
+                lo = lo.max(frozen.last_before(series, hi).map(|(ts, _)| ts));
+            }
+            for sst in self.sstables.lock().unwrap().iter() {
+                lo = lo.max(sst.overlapping_blocks(series, 0, hi).last().map(|h| h.min_ts));
+            }
+            let Some(lo) = lo else {
+                break;
+            };
+            if let Some(point) = self.query_iter(series, lo, hi)?.last() {
+                return point.map(Some);
+            }
+            match lo.checked_sub(1) {
+                Some(prev) => hi = prev,
+                None => break,
+            }
+        }
+        Ok(None)
+    }
+
+    /// 指定序列不早于`ts`的第一个数据点，与`last_before`对称地向后逐段查找
+    pub fn first_after(&self, series: &SeriesKey, ts: Timestamp) -> Result<Option<(Timestamp, Value)>> {
+        let mut lo = ts.max(self.retention.cutoff());
+        loop {
+            let mut hi: Option<Timestamp> = None;
+            let mut earlier = |candidate: Option<Timestamp>| {
+                hi = match (hi, candidate) {
+                    (Some(a), Some(b)) => Some(a.min(b)),
+                    (a, b) => a.or(b),
+                }
+            };
+            {
+                let mem = self.memtable.lock().unwrap();
+                earlier(mem.first_after(series, lo).map(|(ts, _)| ts));
+            }
+            if let Some(frozen) = self.flusher.immutable() {
+                earlier(frozen.first_after(series, lo).map(|(ts, _)| ts));
+            }
+            for sst in self.sstables.lock().unwrap().iter() {
+                earlier(sst.overlapping_blocks(series, lo, Timestamp::MAX).first().map(|h| h.max_ts));
+            }
+            let Some(hi) = hi else {
+                return Ok(None);
+            };
+            if let Some(point) = self.query_iter(series, lo, hi)?.next() {
+                return point.map(Some);
+            }
+            match hi.checked_add(1) {
+                Some(next) => lo = next,
+                None => return Ok(None),
+            }
+        }
+    }
+
+    /// 对指定序列的区间数据做聚合，区间内没有数据时返回None
+    ///
+    /// 边读边累加，不生成完整的结果集合；完整落在区间内、且时间范围内没有其他数据的块直接用块统计。
+    pub fn aggregate(&self, series: &SeriesKey, start: Timestamp, end: Timestamp, agg: Aggregation) -> Result<Option<Value>> {
+        Ok(self.aggregate_many(series, start, end, &[agg])?[0])
+    }
+
+    /// 同`aggregate`，一次读取同时计算多个聚合函数，按`aggs`的顺序返回结果
+    ///
+    /// 所有聚合函数都能用块统计（或块草图）时才跳过块的解码。
+    pub fn aggregate_many(
+        &self,
+        series: &SeriesKey,
+        start: Timestamp,
+        end: Timestamp,
+        aggs: &[Aggregation],
+    ) -> Result<Vec<Option<Value>>> {
+        let mut acc = Accumulator::for_aggregations(aggs);
+        for sample in self.samples(series, start, end, aggs, |_| true)? {
+            acc.push_sample(&sample?)?;
+        }
+        let results: Vec<Option<Value>> = aggs.iter().map(|&agg| acc.result_in(agg, start, end.saturating_add(1))).collect();
+        debug!("聚合序列{}区间[{}, {}] {:?}({}个点) = {:?}", series, start, end, aggs, acc.count(), results);
+        Ok(results)
+    }
+
+    /// 按时间分桶聚合指定序列的区间数据，每个桶返回一行`(桶起点, 聚合结果)`，空桶按`fill`处理
+    pub fn aggregate_window(
+        &self,
+        series: &SeriesKey,
+        start: Timestamp,
+        end: Timestamp,
+        agg: Aggregation,
+        window: Window,
+        fill: Fill,
+    ) -> Result<Vec<(Timestamp, Option<Value>)>> {
+        let rows = self.aggregate_window_many(series, start, end, &[agg], window, fill)?;
+        Ok(rows.into_iter().map(|(bucket, values)| (bucket, values[0])).collect())
+    }
+
+    /// 同`aggregate_window`，一次读取同时计算多个聚合函数，每个桶返回各聚合函数的结果
+    ///
+    /// 所有聚合函数都能用汇总数据计算时才使用汇总表。
+    pub fn aggregate_window_many(
+        &self,
+        series: &SeriesKey,
+        start: Timestamp,
+        end: Timestamp,
+        aggs: &[Aggregation],
+        window: Window,
+        fill: Fill,
+    ) -> Result<Vec<(Timestamp, Vec<Option<Value>>)>> {
+        // 桶宽和对齐都是某个汇总表分辨率的整数倍时，选用其中最粗的汇总表
+        let rollup = self
+            .roller
+            .tables()
+            .iter()
+            .filter(|t| aggs.iter().all(Aggregation::uses_rollup)
+                && window.width.is_multiple_of(t.resolution())
+                && window.offset.is_multiple_of(t.resolution()))
+            .max_by_key(|t| t.resolution());
+        let rows = match rollup {
+            Some(table) => {
+                let samples = self.rollup_samples(table, series, start, end, aggs, window)?;
+                aggregate::aggregate_windows_many(samples.into_iter().map(Ok), start, end, window, aggs, fill)?
+            }
+            None => {
+                let samples = self.samples(series, start, end, aggs, |h| fits_window(window, h))?;
+                aggregate::aggregate_windows_many(samples, start, end, window, aggs, fill)?
+            }
+        };
+        debug!("分组聚合序列{}区间[{}, {}] {:?} 桶宽{}, 返回{}个桶", series, start, end, aggs, window.width, rows.len());
+        Ok(rows)
+    }
+
+    /// 从汇总表取得分组聚合的输入，汇总表还没有反映的桶改用原始数据
+    ///
+    /// MemTable、尚未汇总的SSTable中的数据和墓碑所在的桶，以及查询区间两端不是整桶的部分都读原始数据；
+    /// 汇总数据已过期的区间也读原始数据。
+    fn rollup_samples(
+        &self,
+        table: &RollupTable,
+        series: &SeriesKey,
+        start: Timestamp,
+        end: Timestamp,
+        aggs: &[Aggregation],
+        window: Window,
+    ) -> Result<Vec<Sample>> {
+        if start > end {
+            return Ok(Vec::new());
+        }
+        // 先读汇总进度再读汇总数据，汇总数据至少反映到该进度
+        let processed_seq = table.processed_seq();
+        let resolution = table.resolution();
+        let snapshot = self.snapshot(series, start, end);
+        let relevant = |t: &Tombstone| t.applies_to(series) && t.start <= end && t.end >= start;
+
+        let mut dirty: Vec<(Timestamp, Timestamp)> = Vec::new();
+        for (points, tombstones) in &snapshot.memtables {
+            if let (Some(first), Some(last)) = (points.first(), points.last()) {
+                dirty.push((first.0, last.0));
+            }
+            dirty.extend(tombstones.iter().map(|t| (t.start, t.end)));
+        }
+        for sst in snapshot.sstables.iter().filter(|sst| sst.seq() > processed_seq) {
+            let blocks = sst.overlapping_blocks(series, start, end);
+            if let (Some(first), Some(last)) = (blocks.first(), blocks.last()) {
+                dirty.push((first.min_ts, last.max_ts));
+            }
+            dirty.extend(sst.tombstones().iter().filter(|t| relevant(t)).map(|t| (t.start, t.end)));
+        }
+        if !start.is_multiple_of(resolution) {
+            dirty.push((start, start));
+        }
+        if end % resolution != resolution - 1 {
+            dirty.push((end, end));
+        }
+        let rollup_cutoff = table.cutoff();
+        if rollup_cutoff > start {
+            dirty.push((start, rollup_cutoff));
+        }
+
+        // 扩展到整个分组桶并合并
+        let mut windows: Vec<(Timestamp, Timestamp)> = Vec::new();
+        dirty.sort();
+        for (lo, hi) in dirty {
+            let lo = window.bucket_start(lo.max(start));
+            let hi = window.bucket_start(hi.min(end)).saturating_add(window.width - 1).min(end);
+            match windows.last_mut() {
+                Some((_, last_hi)) if lo <= last_hi.saturating_add(1) => *last_hi = (*last_hi).max(hi),
+                _ => windows.push((lo, hi)),
+            }
+        }
+
+        // 不在这些桶内的汇总桶直接使用，这些桶内读原始数据
+        let block = |bucket: Timestamp, stats| Sample::Block { min_ts: bucket, max_ts: bucket + resolution - 1, stats, sketch: None };
+        let mut samples = Vec::new();
+        let mut summarized = 0;
+        let mut buckets = table.buckets(series, start, end)?.into_iter().peekable();
+        for (lo, hi) in windows {
+            while let Some((bucket, stats)) = buckets.next_if(|&(b, _)| b < lo) {
+                samples.push(block(bucket, stats));
+                summarized += 1;
+            }
+            while buckets.next_if(|&(b, _)| b <= hi).is_some() {}
+            for sample in self.samples(series, lo.max(start), hi, aggs, |h| fits_window(window, h))? {
+                samples.push(sample?);
+            }
+        }
+        for (bucket, stats) in buckets {
+            samples.push(block(bucket, stats));
+            summarized += 1;
+        }
+
+        debug!("分组聚合序列{}使用{}秒汇总表的 {} 个桶", series, resolution, summarized);
+        Ok(samples)
+    }
+
+    /// 聚合的输入：能用块统计或块草图的块不解码，其余的块和MemTable中的数据逐点给出
+    fn samples<F: Fn(&BlockHandle) -> bool>(
+        &self,
+        series: &SeriesKey,
+        start: Timestamp,
+        end: Timestamp,
+        aggs: &[Aggregation],
+        fits: F,
+    ) -> Result<SampleIter> {
+        // 所有聚合函数都能用块统计时直接用块统计；其中有分位数时还要合并块草图
+        let block_stats = aggs.iter().all(|agg| agg.uses_block_stats() || agg.uses_block_sketch());
+        let block_sketch = aggs.iter().any(Aggregation::uses_block_sketch);
+        let snapshot = self.snapshot(series, start, end);
+        let (start, end) = (snapshot.start, snapshot.end);
+        let mut blocks = Vec::with_capacity(snapshot.sstables.len());
+        let mut summarized = Vec::new();
+        for (i, sst) in snapshot.sstables.iter().enumerate() {
+            let mut decode = Vec::new();
+            for handle in sst.overlapping_blocks(series, start, end) {
+                let covered = handle.min_ts >= start
+                    && handle.max_ts <= end
+                    && fits(handle)
+                    && snapshot.is_exclusive(i, handle);
+                let summary = match handle.stats {
+                    Some(stats) if covered && block_stats && !block_sketch => Some((stats, None)),
+                    Some(stats) if covered && block_stats => {
+                        sst.block_sketch(handle)?.map(|sketch| (stats, Some(Box::new(sketch))))
+                    }
+                    _ => None,
+                };
+                match summary {
+                    Some((stats, sketch)) => {
+                        summarized.push(Sample::Block { min_ts: handle.min_ts, max_ts: handle.max_ts, stats, sketch });
+                    }
+                    None => decode.push(*handle),
+                }
+            }
+            blocks.push(decode);
+        }
+        if !summarized.is_empty() {
+            debug!("聚合序列{}区间[{}, {}]时 {} 个块直接使用块统计", series, start, end, summarized.len());
+        }
+        summarized.sort_by_key(|b| b.min_ts());
+        Ok(SampleIter::new(snapshot.merge(blocks, Order::Ascending)?, summarized))
+    }
+
+    /// 取得查询开始时各数据来源的快照，起点按保留策略截断
+    fn snapshot(&self, series: &SeriesKey, start: Timestamp, end: Timestamp) -> ReadSnapshot {
+        // 过期数据即使还没被清理也不返回
+        let start = start.max(self.retention.cutoff());
+        let relevant = |t: &&Tombstone| t.applies_to(series) && t.start <= end && t.end >= start;
+        let mut snapshot = ReadSnapshot {
+            series: series.clone(),
+            start,
+            end,
+            memtables: Vec::new(),
+            sstables: Vec::new(),
+        };
+        if start > end {
+            return snapshot;
+        }
+
+        // 从新到旧：MemTable、正在刷盘的不可变MemTable、SSTable
         {
             let mem = self.memtable.lock().unwrap();
-            for (&ts, &val) in mem.range(start..=end) {
-                result.push((ts, val));
+            snapshot.memtables.push((
+                mem.range(series, start, end).collect(),
+                mem.tombstones().iter().filter(relevant).cloned().collect(),
+            ));
+        }
+        if let Some(frozen) = self.flusher.immutable() {
+            snapshot.memtables.push((
+                frozen.range(series, start, end).collect(),
+                frozen.tombstones().iter().filter(relevant).cloned().collect(),
+            ));
+        }
+
+        snapshot.sstables = self.sstables_newest_first();
+        snapshot
+    }
+
+    /// 从新到旧排列的SSTable：同一时间戳以序列号大的文件为准，序列号相同时以文件列表中靠后的为准
+    fn sstables_newest_first(&self) -> Vec<Arc<SSTable>> {
+        let mut sstables: Vec<Arc<SSTable>> = self.sstables.lock().unwrap().clone();
+        sstables.sort_by_key(|sst| sst.seq());
+        sstables.reverse();
+        sstables
+    }
+
+    /// 删除指定序列在`[start, end]`内的数据
+    pub fn delete(&self, series: &SeriesKey, start: Timestamp, end: Timestamp) -> Result<()> {
+        self.delete_tombstone(Tombstone { series: Some(series.clone()), start, end })
+    }
+
+    /// 删除所有序列在`[start, end]`内的数据
+    pub fn delete_range(&self, start: Timestamp, end: Timestamp) -> Result<()> {
+        self.delete_tombstone(Tombstone { series: None, start, end })
+    }
+
+    fn delete_tombstone(&self, tombstone: Tombstone) -> Result<()> {
+        if tombstone.start > tombstone.end {
+            return Err(Error::DataError(format!(
+                "删除区间无效: [{}, {}]",
+                tombstone.start, tombstone.end
+            )));
+        }
+        info!("删除数据 series={}, [{}, {}]",
+              tombstone.series.as_ref().map(|s| s.to_string()).unwrap_or_else(|| "*".to_string()),
+              tombstone.start, tombstone.end);
+        let lsn = {
+            let mut mem = self.memtable.lock().unwrap();
+            let lsn = self.wal.append_delete(&tombstone)?;
+            mem.delete(tombstone);
+            lsn
+        };
+        self.wal.commit(lsn)
+    }
+
+    /// WAL的持久化方式，写入返回时已按该方式确认
+    pub fn wal_sync_mode(&self) -> WalSyncMode {
+        self.wal.sync_mode()
+    }
+
+    /// 立即把MemTable刷盘为SSTable，不论是否达到阈值
+    pub fn flush(&self) -> Result<()> {
+        self.flusher.flush()?;
+        Ok(())
+    }
+
+    /// 按配置的压缩策略执行一轮压缩，没有可合并的文件时返回false
+    pub fn compact(&self) -> Result<bool> {
+        self.compactor.compact_once()
+    }
+
+    /// 立即把新刷盘的SSTable汇总到各降采样表，有汇总表写入新数据时返回true
+    pub fn roll_up(&self) -> Result<bool> {
+        self.roller.run_once()
+    }
+
+    /// 注册连续查询，定义立即持久化，首次运行从上一个完整区间开始
+    pub fn register_continuous_query(&self, query: ContinuousQuery) -> Result<()> {
+        info!("注册连续查询 {}: 每{}秒计算 {} 的 {} 写入 {}, 分组标签 {:?}",
+              query.name, query.interval.as_secs(), query.selector, query.aggregation, query.target, query.group_by);
+        self.continuous.register(query)
+    }
+
+    /// 删除连续查询，不存在时返回false
+    pub fn drop_continuous_query(&self, name: &str) -> Result<bool> {
+        self.continuous.drop_query(name)
+    }
+
+    /// 已注册的连续查询及其进度
+    pub fn continuous_queries(&self) -> Vec<ContinuousQueryState> {
+        self.continuous.list()
+    }
+
+    /// 启动连续查询的调度线程，数据库被释放后线程退出
+    pub fn start_continuous_queries(db: &Arc<SimpleTSDB>) {
+        let db = Arc::downgrade(db);
+        thread::spawn(move || loop {
+            thread::sleep(Duration::from_secs(1));
+            let Some(db) = db.upgrade() else {
+                break;
+            };
+            if let Err(e) = db.run_continuous_queries() {
+                error!("连续查询运行失败: {:?}", e);
+            }
+        });
+    }
+
+    /// 以当前时间运行所有到期的连续查询，返回写入的数据点数
+    pub fn run_continuous_queries(&self) -> Result<usize> {
+        self.run_continuous_queries_at(chrono::Utc::now().timestamp() as Timestamp)
+    }
+
+    /// 以`now`为当前时间运行连续查询：计算水位线到最后一个完整区间之间的所有区间，写入后推进水位线
+    ///
+    /// 水位线之前的区间不再重新计算，之后写入这些区间的迟到数据不会反映到结果中。
+    pub fn run_continuous_queries_at(&self, now: Timestamp) -> Result<usize> {
+        let mut written = 0;
+        for state in self.continuous.list() {
+            let query = &state.query;
+            let interval = query.interval.as_secs();
+            let end = now - now % interval;
+            let start = if state.watermark == 0 { end.saturating_sub(interval) } else { state.watermark };
+            if start >= end {
+                continue;
+            }
+            match self.run_continuous_query(query, start, end) {
+                Ok(points) => {
+                    self.continuous.set_watermark(&query.name, end)?;
+                    debug!("连续查询 {} 计算了[{}, {}), 写入 {} 个点", query.name, start, end, points);
+                    written += points;
+                }
+                // 单个查询失败不影响其他查询，水位线不变，下次重试
+                Err(e) => error!("连续查询 {} 运行失败: {:?}", query.name, e),
             }
         }
+        Ok(written)
+    }
 
-        // 查询SSTable
-        let sstables = self.sstables.lock().unwrap();
-        for sst in sstables.iter() {
-            if sst.may_contain(start, end) {
-                let mut res = sst.query(start, end)?;
-                result.append(&mut res);
+    fn run_continuous_query(&self, query: &ContinuousQuery, start: Timestamp, end: Timestamp) -> Result<usize> {
+        let matchers = query.validate()?;
+        let window = Window::new(query.interval.as_secs(), 0)?;
+        let agg = query.aggregation;
+
+        // 按结果序列分组：不分组时每个源序列单独一组，分组时分组标签的值相同的序列在同一组
+        let mut groups: BTreeMap<SeriesKey, Vec<SeriesKey>> = BTreeMap::new();
+        for series in self.select_series(&matchers)? {
+            // 结果序列不作为自己的输入
+            if series.metric() == query.target {
+                continue;
+            }
+            groups.entry(query.target_series(&series)?).or_default().push(series);
+        }
+
+        let mut written = 0;
+        for (target, members) in groups {
+            let rows = match members.as_slice() {
+                [series] => self.aggregate_window(series, start, end - 1, agg, window, Fill::None)?,
+                _ => {
+                    // 各序列的输入按时间合并后一起累加，校验已保证聚合结果与顺序无关
+                    let mut samples = Vec::new();
+                    for series in &members {
+                        for sample in self.samples(series, start, end - 1, &[agg], |h| fits_window(window, h))? {
+                            samples.push(sample?);
+                        }
+                    }
+                    samples.sort_by_key(|s| s.min_ts());
+                    aggregate::aggregate_windows(samples.into_iter().map(Ok), start, end - 1, window, agg, Fill::None)?
+                }
+            };
+            let points: Vec<(Timestamp, Value)> = rows.into_iter().filter_map(|(ts, v)| Some((ts, v?))).collect();
+            if !points.is_empty() {
+                self.batch_put(&target, &points)?;
+                written += points.len();
             }
         }
+        Ok(written)
+    }
+
+    /// 立即删除数据已全部过期的SSTable，返回删除的文件数
+    pub fn enforce_retention(&self) -> Result<usize> {
+        self.compactor.expire_once()
+    }
 
-        // 合并结果，去重
-        result.sort_by_key(|&(ts, _)| ts);
-        result.dedup_by_key(|&mut (ts, _)| ts);
+    /// 通过倒排索引选出满足所有标签匹配器的序列
+    pub fn select_series(&self, matchers: &[Matcher]) -> Result<Vec<SeriesKey>> {
+        let series: Vec<SeriesKey> = self.index.select(matchers).into_iter().map(|(_, s)| s).collect();
+        debug!("标签匹配选出{}个序列", series.len());
+        Ok(series)
+    }
 
-        info!("查询区间[{}, {}]返回{}条数据", start, end, result.len());
+    /// 查询满足标签匹配器的所有序列的区间数据，区间内没有数据的序列不返回
+    pub fn query_series(
+        &self,
+        matchers: &[Matcher],
+        start: Timestamp,
+        end: Timestamp,
+    ) -> Result<Vec<SeriesPoints>> {
+        let mut result = Vec::new();
+        for series in self.select_series(matchers)? {
+            let points = self.query(&series, start, end)?;
+            if !points.is_empty() {
+                result.push((series, points));
+            }
+        }
         Ok(result)
     }
     
     /// 获取压缩和存储统计信息
     pub fn get_stats(&self) -> Result<DbStats> {
-        let sstables = self.sstables.lock().unwrap();
-        let mut total_files = 0;
-        let mut total_size = 0;
-        
-        for entry in std::fs::read_dir(&self.sstable_dir)? {
-            let entry = entry?;
-            if entry.path().extension().and_then(|s| s.to_str()) == Some("db") {
-                total_files += 1;
-                total_size += entry.metadata()?.len();
-            }
-        }
+        let (total_files, total_size) = {
+            let sstables = self.sstables.lock().unwrap();
+            (sstables.len(), sstables.iter().map(|sst| sst.file_size()).sum())
+        };
         
-        let mem_size = {
+        let (series_count, mem_size) = {
             let mem = self.memtable.lock().unwrap();
-            mem.len()
+            (mem.series_count(), mem.len())
         };
         
         Ok(DbStats {
             sstable_count: total_files,
             total_disk_size: total_size,
+            series_count: self.index.series_count(),
+            memtable_series: series_count,
             memtable_records: mem_size,
+            compaction: self.compactor.stats(),
+            wal_recovery: self.wal_recovery.clone(),
         })
     }
 }
 
+/// 块是否整块落在同一个分组桶内，只有这样的块才能用块统计
+fn fits_window(window: Window, handle: &BlockHandle) -> bool {
+    window.bucket_start(handle.min_ts) == window.bucket_start(handle.max_ts)
+}
+
+/// MemTable中区间内的数据，以及与查询相关的墓碑
+type MemSnapshot = (Vec<(Timestamp, Value)>, Vec<Tombstone>);
+
+/// 查询开始时各数据来源的快照，从新到旧排列
+struct ReadSnapshot {
+    series: SeriesKey,
+    start: Timestamp,
+    end: Timestamp,
+    /// MemTable和不可变MemTable
+    memtables: Vec<MemSnapshot>,
+    sstables: Vec<Arc<SSTable>>,
+}
+
+impl ReadSnapshot {
+    /// 构建归并迭代器，`blocks`是每个SSTable中需要解码的块
+    fn merge(self, blocks: Vec<Vec<BlockHandle>>, order: Order) -> Result<QueryIter> {
+        let mut iter = QueryIter::with_order(self.series, self.start, self.end, order);
+        for (mut points, tombstones) in self.memtables {
+            if order == Order::Descending {
+                points.reverse();
+            }
+            iter.push_source(Box::new(points.into_iter().map(Ok)), &tombstones)?;
+        }
+        for (sst, handles) in self.sstables.iter().zip(blocks) {
+            let source: PointSource = match order {
+                Order::Ascending => Box::new(sst.iter_blocks(handles, self.start, self.end)),
+                Order::Descending => Box::new(sst.iter_blocks_rev(handles, self.start, self.end)),
+            };
+            iter.push_source(source, sst.tombstones())?;
+        }
+        Ok(iter)
+    }
+
+    /// 第`idx`个SSTable的块是否独占它的时间范围：其他来源在该范围内没有数据，也没有更新的墓碑屏蔽它
+    fn is_exclusive(&self, idx: usize, handle: &BlockHandle) -> bool {
+        let (lo, hi) = (handle.min_ts, handle.max_ts);
+        let masks = |t: &Tombstone| t.applies_to(&self.series) && t.start <= hi && t.end >= lo;
+        let memtables_clear = self.memtables.iter().all(|(points, tombstones)| {
+            let i = points.partition_point(|&(ts, _)| ts < lo);
+            points.get(i).is_none_or(|&(ts, _)| ts > hi) && !tombstones.iter().any(masks)
+        });
+        memtables_clear
+            && self.sstables.iter().enumerate().all(|(j, sst)| {
+                j == idx
+                    || (sst.overlapping_blocks(&self.series, lo, hi).is_empty()
+                        && (j > idx || !sst.tombstones().iter().any(masks)))
+            })
+    }
+}
+
+/// 查询选项，默认按时间升序返回全部数据
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QueryOptions {
+    pub order: Order,
+    /// 跳过的数据点数
+    pub offset: usize,
+    /// 最多返回的数据点数，None表示不限
+    pub limit: Option<usize>,
+}
+
 pub struct DbConfig {
     pub sstable_dir: String,
-    pub wal_path: String,
+    /// WAL段文件所在的目录
+    pub wal_dir: String,
+    /// WAL的持久化方式
+    pub wal_sync: WalSyncMode,
+    pub index_path: String,
     pub memtable_size_threshold: usize,
+    /// SSTable中每个Gorilla块的数据点数
+    pub block_points: usize,
+    /// 后台压缩策略
+    pub compaction: CompactionStrategy,
+    /// 数据保留时长，None表示永久保留
+    pub retention: Option<Duration>,
+    /// 降采样规则，分组聚合时自动选用满足桶宽的最粗分辨率
+    pub rollups: Vec<RollupRule>,
+    /// 连续查询定义和进度的保存路径
+    pub continuous_query_path: String,
 }
 
 impl Default for DbConfig {
     fn default() -> Self {
         DbConfig {
             sstable_dir: "./data/sstable".to_string(),
-            wal_path: "./data/wal.log".to_string(),
+            wal_dir: "./data/wal".to_string(),
+            wal_sync: WalSyncMode::default(),
+            index_path: "./data/series.idx".to_string(),
             memtable_size_threshold: 1000,
+            block_points: DEFAULT_BLOCK_POINTS,
+            compaction: CompactionStrategy::default(),
+            retention: None,
+            rollups: Vec::new(),
+            continuous_query_path: "./data/continuous_queries".to_string(),
         }
     }
 }
@@ -181,6 +925,602 @@ impl Default for DbConfig {
 pub struct DbStats {
     pub sstable_count: usize,
     pub total_disk_size: u64,
+    pub series_count: usize,
+    pub memtable_series: usize,
     pub memtable_records: usize,
+    pub compaction: CompactionStats,
+    /// 启动时WAL的回放结果
+    pub wal_recovery: WalRecovery,
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(dir: &std::path::Path) -> DbConfig {
+        DbConfig {
+            sstable_dir: dir.join("sstable").to_string_lossy().into_owned(),
+            wal_dir: dir.join("wal").to_string_lossy().into_owned(),
+            index_path: dir.join("series.idx").to_string_lossy().into_owned(),
+            continuous_query_path: dir.join("continuous_queries").to_string_lossy().into_owned(),
+            compaction: CompactionStrategy::Disabled,
+            ..Default::default()
+        }
+    }
+
+    /// 损坏SSTable中指定序列与区间有交集的第一个块的数据，块的校验和不再匹配
+    fn corrupt_first_block(path: &std::path::Path, series: &SeriesKey, start: Timestamp, end: Timestamp) {
+        use std::io::{Seek, SeekFrom, Write};
+        let offset = SSTable::open(path.to_path_buf()).unwrap().overlapping_blocks(series, start, end)[0].offset;
+        let mut file = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+        file.seek(SeekFrom::Start(offset as u64 + 6)).unwrap();
+        file.write_all(&[0xff]).unwrap();
+    }
+
+    #[test]
+    fn test_flush_rotates_wal() {
+        let dir = tempfile::tempdir().unwrap();
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+        let wal_segments = || std::fs::read_dir(dir.path().join("wal")).unwrap().count();
+        {
+            let db = SimpleTSDB::open(config(dir.path())).unwrap();
+            db.put(&cpu, 1, 1.0).unwrap();
+            db.put(&cpu, 2, 2.0).unwrap();
+            db.flush().unwrap();
+
+            // 刷盘后封存的段被删除，之后的写入进入新段
+            assert_eq!(wal_segments(), 1);
+            db.put(&cpu, 3, 3.0).unwrap();
+            assert_eq!(db.get_stats().unwrap().memtable_records, 1);
+        }
+
+        // 重启后已刷盘的数据来自SSTable，刷盘后的写入从WAL回放
+        let db = SimpleTSDB::open(config(dir.path())).unwrap();
+        assert_eq!(db.get_stats().unwrap().wal_recovery.recovered, 1);
+        assert_eq!(db.query(&cpu, 0, 10).unwrap(), vec![(1, 1.0), (2, 2.0), (3, 3.0)]);
+    }
+
+    #[test]
+    fn test_consecutive_flushes() {
+        let dir = tempfile::tempdir().unwrap();
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+        {
+            let db = SimpleTSDB::open(config(dir.path())).unwrap();
+            for ts in 0..3 {
+                db.put(&cpu, ts, ts as f64).unwrap();
+                db.flush().unwrap();
+            }
+            assert_eq!(db.get_stats().unwrap().sstable_count, 3);
+        }
+
+        // 同一秒内的多次刷盘各自生成一个完整的文件，没有临时文件残留
+        let mut names: Vec<String> = std::fs::read_dir(dir.path().join("sstable"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["MANIFEST", "sstable-000001.db", "sstable-000002.db", "sstable-000003.db"]);
+
+        let db = SimpleTSDB::open(config(dir.path())).unwrap();
+        assert_eq!(db.query(&cpu, 0, 10).unwrap(), vec![(0, 0.0), (1, 1.0), (2, 2.0)]);
+    }
+
+    #[test]
+    fn test_delete_masks_flushed_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+        let mem = SeriesKey::parse("mem,host=a").unwrap();
+        {
+            let db = SimpleTSDB::open(config(dir.path())).unwrap();
+            let points: Vec<(Timestamp, Value)> = (1..=10).map(|ts| (ts, ts as f64)).collect();
+            db.batch_put(&cpu, &points).unwrap();
+            db.batch_put(&mem, &points).unwrap();
+            db.flush().unwrap();
+
+            // 墓碑在MemTable中，屏蔽已刷盘的数据
+            db.delete(&cpu, 3, 5).unwrap();
+            db.delete_range(9, 100).unwrap();
+            db.put(&cpu, 4, 40.0).unwrap();
+            assert!(db.delete(&cpu, 5, 3).is_err());
+
+            assert_eq!(db.query(&cpu, 0, 100).unwrap().iter().map(|&(ts, _)| ts).collect::<Vec<_>>(),
+                       vec![1, 2, 4, 6, 7, 8]);
+            assert_eq!(db.query(&cpu, 4, 4).unwrap(), vec![(4, 40.0)]);
+            assert_eq!(db.query(&mem, 0, 100).unwrap().len(), 8);
+        }
+
+        // 重启后从WAL恢复墓碑
+        let db = SimpleTSDB::open(config(dir.path())).unwrap();
+        assert_eq!(db.query(&cpu, 0, 100).unwrap().iter().map(|&(ts, _)| ts).collect::<Vec<_>>(),
+                   vec![1, 2, 4, 6, 7, 8]);
+        assert_eq!(db.query(&mem, 8, 100).unwrap(), vec![(8, 8.0)]);
+    }
+
+    #[test]
+    fn test_query_iter_merges_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+        let db = SimpleTSDB::open(DbConfig { block_points: 4, ..config(dir.path()) }).unwrap();
+
+        let points: Vec<(Timestamp, Value)> = (0..20).map(|ts| (ts, ts as f64)).collect();
+        db.batch_put(&cpu, &points).unwrap();
+        db.flush().unwrap();
+        db.batch_put(&cpu, &[(5, 50.0), (10, 100.0)]).unwrap();
+        db.flush().unwrap();
+        db.put(&cpu, 10, 1000.0).unwrap();
+        db.delete(&cpu, 12, 13).unwrap();
+
+        let mut iter = db.query_iter(&cpu, 3, 15).unwrap();
+        assert_eq!(iter.next().unwrap().unwrap(), (3, 3.0));
+
+        // 迭代过程中的刷盘不影响已创建的迭代器
+        db.flush().unwrap();
+        let rest: Vec<_> = iter.collect::<Result<_>>().unwrap();
+        assert_eq!(rest.iter().map(|&(ts, _)| ts).collect::<Vec<_>>(), vec![4, 5, 6, 7, 8, 9, 10, 11, 14, 15]);
+        assert_eq!(rest[1], (5, 50.0));
+        assert_eq!(rest[6], (10, 1000.0));
+        assert_eq!(db.query(&cpu, 3, 15).unwrap()[1..], rest[..]);
+    }
+
+    #[test]
+    fn test_overwrite_across_flushes() {
+        let dir = tempfile::tempdir().unwrap();
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+        let compaction = CompactionStrategy::SizeTiered { min_files: 2, max_files: 8, small_file_bytes: 1 << 20 };
+        {
+            let db = SimpleTSDB::open(config(dir.path())).unwrap();
+            for value in [1.0, 2.0, 3.0] {
+                db.put(&cpu, 10, value).unwrap();
+                db.put(&cpu, value as u64, value).unwrap();
+                db.flush().unwrap();
+            }
+            assert_eq!(db.query(&cpu, 10, 10).unwrap(), vec![(10, 3.0)]);
+
+            // MemTable中的值比所有SSTable都新
+            db.put(&cpu, 10, 4.0).unwrap();
+            assert_eq!(db.query(&cpu, 10, 10).unwrap(), vec![(10, 4.0)]);
+        }
+
+        // 重启后按序列号恢复新旧顺序，从WAL恢复的MemTable仍然最新
+        let db = SimpleTSDB::open(DbConfig { compaction, ..config(dir.path()) }).unwrap();
+        assert_eq!(db.query(&cpu, 10, 10).unwrap(), vec![(10, 4.0)]);
+        db.flush().unwrap();
+        db.put(&cpu, 10, 5.0).unwrap();
+
+        // 压缩后输出沿用最大的序列号，之后刷盘的文件仍然更新
+        assert!(db.compact().unwrap());
+        assert_eq!(db.get_stats().unwrap().sstable_count, 1);
+        assert_eq!(db.query(&cpu, 0, 10).unwrap(), vec![(1, 1.0), (2, 2.0), (3, 3.0), (10, 5.0)]);
+        db.flush().unwrap();
+        db.put(&cpu, 10, 6.0).unwrap();
+        db.flush().unwrap();
+        assert!(db.compact().unwrap());
+        assert_eq!(db.query(&cpu, 10, 10).unwrap(), vec![(10, 6.0)]);
+    }
+
+    #[test]
+    fn test_time_window_split_keeps_tombstone_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+        let compaction = CompactionStrategy::TimeWindow {
+            window: Duration::from_secs(100),
+            active_windows: 1,
+            min_files: 4,
+            max_files: 8,
+        };
+        let db = SimpleTSDB::open(DbConfig { compaction, ..config(dir.path()) }).unwrap();
+        db.put(&cpu, 150, 1.0).unwrap();
+        db.flush().unwrap();
+        // 第2个文件删除[5, 250]后又写入了窗口0和窗口2的数据，跨越窗口需要拆分
+        db.delete(&cpu, 5, 250).unwrap();
+        db.put(&cpu, 10, 2.0).unwrap();
+        db.put(&cpu, 250, 2.0).unwrap();
+        db.flush().unwrap();
+        db.put(&cpu, 400, 3.0).unwrap();
+        db.flush().unwrap();
+        let expected = vec![(10, 2.0), (250, 2.0), (400, 3.0)];
+        assert_eq!(db.query(&cpu, 0, 1000).unwrap(), expected);
+
+        // 拆分后的墓碑不能屏蔽同一次合并中其它窗口的数据
+        assert!(db.compact().unwrap());
+        while db.compact().unwrap() {}
+        assert_eq!(db.query(&cpu, 0, 1000).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_seq_after_expiry() {
+        let dir = tempfile::tempdir().unwrap();
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+        let expiring = || DbConfig { retention: Some(Duration::from_secs(3600)), ..config(dir.path()) };
+        {
+            let db = SimpleTSDB::open(expiring()).unwrap();
+            for ts in 1..=3 {
+                db.put(&cpu, ts, ts as f64).unwrap();
+                db.flush().unwrap();
+            }
+            assert_eq!(db.enforce_retention().unwrap(), 3);
+            assert_eq!(db.get_stats().unwrap().sstable_count, 0);
+        }
+
+        // 所有文件都已删除，重启后序列号仍从清单记录的最大值之后继续
+        let db = SimpleTSDB::open(expiring()).unwrap();
+        assert_eq!(db.memtable.lock().unwrap().seq(), 4);
+        let now = chrono::Utc::now().timestamp() as Timestamp;
+        db.put(&cpu, now, 1.0).unwrap();
+        db.flush().unwrap();
+        assert_eq!(db.sstables.lock().unwrap()[0].seq(), 4);
+        drop(db);
+        let db = SimpleTSDB::open(expiring()).unwrap();
+        assert_eq!(db.memtable.lock().unwrap().seq(), 5);
+    }
+
+    #[test]
+    fn test_open_baseline_data_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        // 基线版本的数据目录：没有清单，SSTable按创建时间命名
+        let sstable_dir = dir.path().join("sstable");
+        std::fs::create_dir_all(&sstable_dir).unwrap();
+        std::fs::write(sstable_dir.join("sstable-1622000100.db"), crate::sstable::tests::BASELINE_FILE).unwrap();
+
+        let legacy = SeriesKey::legacy();
+        {
+            let db = SimpleTSDB::open(config(dir.path())).unwrap();
+            assert_eq!(
+                db.query(&legacy, 0, u64::MAX).unwrap(),
+                vec![(1622000000, 1.5), (1622000010, 2.5), (1622000020, 2.5)]
+            );
+            // 新写入的数据比基线文件新
+            db.put(&legacy, 1622000010, 3.0).unwrap();
+            db.flush().unwrap();
+        }
+
+        let db = SimpleTSDB::open(config(dir.path())).unwrap();
+        assert_eq!(
+            db.query(&legacy, 0, u64::MAX).unwrap(),
+            vec![(1622000000, 1.5), (1622000010, 3.0), (1622000020, 2.5)]
+        );
+    }
+
+    #[test]
+    fn test_query_order_and_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+        let db = SimpleTSDB::open(DbConfig { block_points: 4, ..config(dir.path()) }).unwrap();
+        let points: Vec<(Timestamp, Value)> = (1..=12).map(|ts| (ts, ts as f64)).collect();
+        db.batch_put(&cpu, &points).unwrap();
+        db.flush().unwrap();
+        db.put(&cpu, 10, 100.0).unwrap();
+        db.put(&cpu, 13, 13.0).unwrap();
+
+        // 损坏第一个块[1, 4]，倒序取前几个点时不会解码到它
+        corrupt_first_block(&dir.path().join("sstable").join("sstable-000001.db"), &cpu, 1, 4);
+
+        let desc = |offset, limit| QueryOptions { order: Order::Descending, offset, limit };
+        assert_eq!(db.query_with(&cpu, 0, 100, desc(0, Some(3))).unwrap(), vec![(13, 13.0), (12, 12.0), (11, 11.0)]);
+        assert_eq!(db.query_with(&cpu, 0, 11, desc(1, Some(2))).unwrap(), vec![(10, 100.0), (9, 9.0)]);
+        assert_eq!(db.query_with(&cpu, 0, 100, desc(0, Some(8))).unwrap().last(), Some(&(6, 6.0)));
+        assert!(db.query_with(&cpu, 0, 100, desc(0, None)).is_err());
+
+        let asc = QueryOptions { offset: 1, limit: Some(2), ..QueryOptions::default() };
+        assert_eq!(db.query_with(&cpu, 5, 100, asc).unwrap(), vec![(6, 6.0), (7, 7.0)]);
+    }
+
+    #[test]
+    fn test_point_lookup() {
+        let dir = tempfile::tempdir().unwrap();
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+        let db = SimpleTSDB::open(DbConfig { block_points: 4, ..config(dir.path()) }).unwrap();
+        let points: Vec<(Timestamp, Value)> = (1..=12).map(|ts| (ts, ts as f64)).collect();
+        db.batch_put(&cpu, &points).unwrap();
+        db.flush().unwrap();
+        db.delete(&cpu, 1, 3).unwrap();
+        db.flush().unwrap();
+        db.put(&cpu, 6, 60.0).unwrap();
+        db.delete(&cpu, 9, 12).unwrap();
+
+        assert_eq!(db.get(&cpu, 5).unwrap(), Some(5.0));
+        assert_eq!(db.get(&cpu, 6).unwrap(), Some(60.0));
+        assert_eq!(db.get(&cpu, 2).unwrap(), None);
+        assert_eq!(db.get(&cpu, 10).unwrap(), None);
+        assert_eq!(db.get(&cpu, 13).unwrap(), None);
+
+        // 最后一个块被整块删除，向前找到上一个块
+        assert_eq!(db.last(&cpu).unwrap(), Some((8, 8.0)));
+        assert_eq!(db.last_before(&cpu, 6).unwrap(), Some((6, 60.0)));
+        assert_eq!(db.last_before(&cpu, 3).unwrap(), None);
+        assert_eq!(db.first_after(&cpu, 0).unwrap(), Some((4, 4.0)));
+        assert_eq!(db.first_after(&cpu, 6).unwrap(), Some((6, 60.0)));
+        assert_eq!(db.first_after(&cpu, 9).unwrap(), None);
+        assert_eq!(db.last(&SeriesKey::parse("cpu,host=b").unwrap()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let dir = tempfile::tempdir().unwrap();
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+        let db = SimpleTSDB::open(DbConfig { block_points: 4, ..config(dir.path()) }).unwrap();
+
+        let points: Vec<(Timestamp, Value)> = (1..=10).map(|ts| (ts, ts as f64)).collect();
+        db.batch_put(&cpu, &points).unwrap();
+        db.flush().unwrap();
+        db.put(&cpu, 10, 100.0).unwrap();
+
+        assert_eq!(db.aggregate(&cpu, 0, 100, Aggregation::Count).unwrap(), Some(10.0));
+        assert_eq!(db.aggregate(&cpu, 0, 100, Aggregation::Sum).unwrap(), Some(145.0));
+        assert_eq!(db.aggregate(&cpu, 3, 5, Aggregation::Avg).unwrap(), Some(4.0));
+        assert_eq!(db.aggregate(&cpu, 0, 100, Aggregation::Max).unwrap(), Some(100.0));
+        assert_eq!(db.aggregate(&cpu, 2, 100, Aggregation::First).unwrap(), Some(2.0));
+        assert_eq!(db.aggregate(&cpu, 0, 100, Aggregation::Last).unwrap(), Some(100.0));
+        assert_eq!(db.aggregate(&cpu, 20, 30, Aggregation::Min).unwrap(), None);
+        assert_eq!(db.aggregate(&cpu, 0, 100, Aggregation::ExactQuantile(0.5)).unwrap(), Some(5.5));
+        assert_eq!(db.aggregate(&cpu, 0, 100, Aggregation::Quantile(1.0)).unwrap(), Some(100.0));
+    }
+
+    #[test]
+    fn test_aggregate_window() {
+        let dir = tempfile::tempdir().unwrap();
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+        let db = SimpleTSDB::open(config(dir.path())).unwrap();
+
+        let points: Vec<(Timestamp, Value)> = (0..30).filter(|ts| !(10..20).contains(ts)).map(|ts| (ts, ts as f64)).collect();
+        db.batch_put(&cpu, &points).unwrap();
+        db.flush().unwrap();
+
+        let window = Window::new(10, 0).unwrap();
+        assert_eq!(db.aggregate_window(&cpu, 0, 29, Aggregation::Max, window, Fill::Null).unwrap(),
+                   vec![(0, Some(9.0)), (10, None), (20, Some(29.0))]);
+        assert_eq!(db.aggregate_window(&cpu, 5, 29, Aggregation::Count, window, Fill::None).unwrap(),
+                   vec![(0, Some(5.0)), (20, Some(10.0))]);
+    }
+
+    #[test]
+    fn test_rate_across_reset() {
+        let dir = tempfile::tempdir().unwrap();
+        let requests = SeriesKey::parse("requests,host=a").unwrap();
+        let db = SimpleTSDB::open(DbConfig { block_points: 4, ..config(dir.path()) }).unwrap();
+
+        // 每秒加1的计数器在20秒时重置，重置前的数据已刷盘
+        let points: Vec<(Timestamp, Value)> = (0..20).map(|ts| (ts, ts as f64)).collect();
+        db.batch_put(&requests, &points).unwrap();
+        db.flush().unwrap();
+        let points: Vec<(Timestamp, Value)> = (20..40).map(|ts| (ts, (ts - 20) as f64)).collect();
+        db.batch_put(&requests, &points).unwrap();
+
+        let rate = db.aggregate(&requests, 0, 39, Aggregation::Rate).unwrap().unwrap();
+        assert!((rate - 38.0 / 39.0).abs() < 1e-12);
+        assert_eq!(db.aggregate(&requests, 0, 39, Aggregation::Irate).unwrap(), Some(1.0));
+        assert_eq!(db.aggregate(&requests, 0, 39, Aggregation::Delta).unwrap(), Some(19.0 * 40.0 / 39.0));
+        assert_eq!(db.aggregate(&requests, 15, 25, Aggregation::NonNegativeDerivative).unwrap(), None);
+
+        let window = Window::new(10, 0).unwrap();
+        assert_eq!(db.aggregate_window(&requests, 0, 39, Aggregation::Rate, window, Fill::None).unwrap(),
+                   vec![(0, Some(1.0)), (10, Some(1.0)), (20, Some(1.0)), (30, Some(1.0))]);
+    }
+
+    #[test]
+    fn test_aggregate_block_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+        let db = SimpleTSDB::open(DbConfig { block_points: 4, ..config(dir.path()) }).unwrap();
+        let points: Vec<(Timestamp, Value)> = (1..=12).map(|ts| (ts, ts as f64)).collect();
+        db.batch_put(&cpu, &points).unwrap();
+        db.flush().unwrap();
+
+        // 损坏中间的块[5, 8]，只要不解码它，聚合仍能得到结果
+        corrupt_first_block(&dir.path().join("sstable").join("sstable-000001.db"), &cpu, 5, 8);
+
+        assert!(db.query(&cpu, 5, 8).is_err());
+        assert_eq!(db.aggregate(&cpu, 5, 8, Aggregation::Sum).unwrap(), Some(26.0));
+        assert_eq!(db.aggregate(&cpu, 3, 10, Aggregation::Sum).unwrap(), Some(52.0));
+        assert_eq!(db.aggregate(&cpu, 0, 100, Aggregation::First).unwrap(), Some(1.0));
+        assert_eq!(db.aggregate(&cpu, 0, 100, Aggregation::Last).unwrap(), Some(12.0));
+        assert_eq!(db.aggregate(&cpu, 0, 100, Aggregation::Count).unwrap(), Some(12.0));
+        let window = Window::new(4, 1).unwrap();
+        assert_eq!(db.aggregate_window(&cpu, 1, 12, Aggregation::Sum, window, Fill::None).unwrap(),
+                   vec![(1, Some(10.0)), (5, Some(26.0)), (9, Some(42.0))]);
+
+        // 分位数合并块草图
+        let median = db.aggregate(&cpu, 1, 12, Aggregation::Quantile(0.5)).unwrap().unwrap();
+        assert!((median - 6.0).abs() <= 0.06);
+
+        // 多个聚合函数一次读取，都能用块统计时仍不解码
+        let results = db.aggregate_many(&cpu, 1, 12, &[Aggregation::Sum, Aggregation::Max, Aggregation::Quantile(0.5)]).unwrap();
+        assert_eq!(results[..2], [Some(78.0), Some(12.0)]);
+        assert!((results[2].unwrap() - 6.0).abs() <= 0.06);
+        assert_eq!(db.aggregate_window_many(&cpu, 1, 12, &[Aggregation::Min, Aggregation::Count], window, Fill::None).unwrap(),
+                   vec![(1, vec![Some(1.0), Some(4.0)]), (5, vec![Some(5.0), Some(4.0)]), (9, vec![Some(9.0), Some(4.0)])]);
+        assert!(db.aggregate_many(&cpu, 5, 8, &[Aggregation::Sum, Aggregation::Stddev]).is_err());
+
+        // 标准差、精确分位数、跨桶的块和被其他来源覆盖的块都要解码
+        assert!(db.aggregate(&cpu, 5, 8, Aggregation::Stddev).is_err());
+        assert!(db.aggregate(&cpu, 5, 8, Aggregation::ExactQuantile(0.5)).is_err());
+        assert!(db.aggregate_window(&cpu, 1, 12, Aggregation::Sum, Window::new(4, 0).unwrap(), Fill::None).is_err());
+        db.put(&cpu, 6, 60.0).unwrap();
+        assert!(db.aggregate(&cpu, 5, 8, Aggregation::Sum).is_err());
+    }
+
+    #[test]
+    fn test_aggregate_window_rollup() {
+        let dir = tempfile::tempdir().unwrap();
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+        let rollups = vec![RollupRule { resolution: Duration::from_secs(10), retention: None }];
+        let db = SimpleTSDB::open(DbConfig { block_points: 4, rollups, ..config(dir.path()) }).unwrap();
+        let points: Vec<(Timestamp, Value)> = (0..20).map(|i| (i * 3, (i * 3) as f64)).collect();
+        db.batch_put(&cpu, &points).unwrap();
+        db.flush().unwrap();
+        assert!(db.roll_up().unwrap());
+
+        // 损坏跨越分组桶的块[12, 21]，它既不能用块统计，也不能解码
+        corrupt_first_block(&dir.path().join("sstable").join("sstable-000001.db"), &cpu, 12, 12);
+        assert!(db.query(&cpu, 0, 59).is_err());
+
+        let window = Window::new(20, 0).unwrap();
+        assert_eq!(db.aggregate_window(&cpu, 0, 59, Aggregation::Sum, window, Fill::None).unwrap(),
+                   vec![(0, Some(63.0)), (20, Some(210.0)), (40, Some(297.0))]);
+
+        // 还没有汇总的写入所在的桶读原始数据，其余的桶仍用汇总表
+        db.put(&cpu, 50, 1000.0).unwrap();
+        assert_eq!(db.aggregate_window(&cpu, 0, 59, Aggregation::Count, window, Fill::None).unwrap(),
+                   vec![(0, Some(7.0)), (20, Some(7.0)), (40, Some(7.0))]);
+        assert_eq!(db.aggregate_window(&cpu, 0, 59, Aggregation::Max, window, Fill::None).unwrap()[2],
+                   (40, Some(1000.0)));
+
+        // 不能整除分辨率的桶宽和标准差不使用汇总表
+        assert!(db.aggregate_window(&cpu, 0, 59, Aggregation::Sum, Window::new(15, 0).unwrap(), Fill::None).is_err());
+        assert!(db.aggregate_window(&cpu, 0, 59, Aggregation::Stddev, window, Fill::None).is_err());
+    }
+
+    #[test]
+    fn test_rollup_after_expiry() {
+        let dir = tempfile::tempdir().unwrap();
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+        let expiring = || DbConfig {
+            retention: Some(Duration::from_secs(3600)),
+            rollups: vec![RollupRule { resolution: Duration::from_secs(10), retention: None }],
+            ..config(dir.path())
+        };
+        {
+            let db = SimpleTSDB::open(expiring()).unwrap();
+            for ts in 1..=3 {
+                db.put(&cpu, ts, ts as f64).unwrap();
+                db.flush().unwrap();
+            }
+            assert!(db.roll_up().unwrap());
+            assert_eq!(db.enforce_retention().unwrap(), 3);
+        }
+
+        // 重启后新刷盘的文件序列号大于汇总进度，会被汇总，分组聚合不会误用旧的汇总结果
+        let db = SimpleTSDB::open(expiring()).unwrap();
+        assert_eq!(db.roller.tables()[0].processed_seq(), 3);
+        let now = chrono::Utc::now().timestamp() as Timestamp;
+        let bucket = now - now % 10 - 10;
+        db.batch_put(&cpu, &[(bucket, 1.0), (bucket + 1, 2.0)]).unwrap();
+        db.flush().unwrap();
+        assert!(db.roll_up().unwrap());
+        assert_eq!(db.roller.tables()[0].processed_seq(), 4);
+        let window = Window::new(10, 0).unwrap();
+        assert_eq!(db.aggregate_window(&cpu, bucket, bucket + 9, Aggregation::Sum, window, Fill::None).unwrap(),
+                   vec![(bucket, Some(3.0))]);
+    }
+
+    #[test]
+    fn test_continuous_query() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = SeriesKey::parse("latency,host=a").unwrap();
+        let b = SeriesKey::parse("latency,host=b").unwrap();
+        let query = ContinuousQuery {
+            name: "latency_max".to_string(),
+            selector: "latency".to_string(),
+            aggregation: Aggregation::Max,
+            interval: Duration::from_secs(60),
+            target: "latency_max_1m".to_string(),
+            group_by: Vec::new(),
+        };
+        {
+            let db = SimpleTSDB::open(config(dir.path())).unwrap();
+            db.register_continuous_query(query.clone()).unwrap();
+            for ts in 0..180 {
+                db.put(&a, ts, ts as f64).unwrap();
+                db.put(&b, ts, -(ts as f64)).unwrap();
+            }
+
+            // 首次运行只计算上一个完整区间[60, 120)
+            assert_eq!(db.run_continuous_queries_at(150).unwrap(), 2);
+            assert_eq!(db.run_continuous_queries_at(170).unwrap(), 0);
+        }
+
+        // 重启后从保存的水位线继续
+        let db = SimpleTSDB::open(config(dir.path())).unwrap();
+        assert_eq!(db.continuous_queries()[0].watermark, 120);
+        assert_eq!(db.run_continuous_queries_at(250).unwrap(), 2);
+        let target = SeriesKey::parse("latency_max_1m,host=a").unwrap();
+        assert_eq!(db.query(&target, 0, 1000).unwrap(), vec![(60, 119.0), (120, 179.0)]);
+        let target = SeriesKey::parse("latency_max_1m,host=b").unwrap();
+        assert_eq!(db.query(&target, 0, 1000).unwrap(), vec![(60, -60.0), (120, -120.0)]);
+
+        assert!(db.drop_continuous_query("latency_max").unwrap());
+        assert_eq!(db.run_continuous_queries_at(1000).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_continuous_query_group_by() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = SimpleTSDB::open(config(dir.path())).unwrap();
+        let series = ["requests,host=a,path=/x", "requests,host=a,path=/y", "requests,host=b,path=/x", "requests,path=/z"]
+            .map(|s| SeriesKey::parse(s).unwrap());
+        for ts in 0..120 {
+            for (i, s) in series.iter().enumerate() {
+                db.put(s, ts, (i + 1) as f64).unwrap();
+            }
+            // 前半部分数据在SSTable中，可以直接使用块统计
+            if ts == 89 {
+                db.flush().unwrap();
+            }
+        }
+
+        let query = ContinuousQuery {
+            name: "requests_by_host".to_string(),
+            selector: "requests".to_string(),
+            aggregation: Aggregation::Sum,
+            interval: Duration::from_secs(60),
+            target: "requests_1m".to_string(),
+            group_by: vec!["host".to_string()],
+        };
+        db.register_continuous_query(query.clone()).unwrap();
+        db.register_continuous_query(ContinuousQuery {
+            name: "requests_avg".to_string(),
+            aggregation: Aggregation::Avg,
+            target: "requests_avg_1m".to_string(),
+            ..query
+        })
+        .unwrap();
+
+        // host=a的两个序列合并为一个结果序列，没有host标签的序列单独成组
+        assert_eq!(db.run_continuous_queries_at(125).unwrap(), 6);
+        let sum = |s: &str| db.query(&SeriesKey::parse(s).unwrap(), 0, 1000).unwrap();
+        assert_eq!(sum("requests_1m,host=a"), vec![(60, 60.0 * 3.0)]);
+        assert_eq!(sum("requests_1m,host=b"), vec![(60, 60.0 * 3.0)]);
+        assert_eq!(sum("requests_1m"), vec![(60, 60.0 * 4.0)]);
+        assert_eq!(sum("requests_avg_1m,host=a"), vec![(60, 1.5)]);
+        assert_eq!(db.select_series(&Matcher::parse_selector("requests_1m").unwrap()).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_query_language() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = SimpleTSDB::open(config(dir.path())).unwrap();
+        let a = SeriesKey::parse("cpu,host=a").unwrap();
+        let b = SeriesKey::parse("cpu,host=b").unwrap();
+        for ts in 0..120 {
+            db.put(&a, ts, ts as f64).unwrap();
+        }
+        db.flush().unwrap();
+        db.put(&b, 30, 5.0).unwrap();
+
+        let result = crate::ql::query(&db, "SELECT value FROM cpu WHERE host = 'a' AND time >= 10 \
+                                             ORDER BY time DESC LIMIT 2 OFFSET 1", 1000).unwrap();
+        assert_eq!(result.columns, vec!["value"]);
+        assert_eq!(result.series.len(), 1);
+        assert_eq!(result.series[0].series, a);
+        let rows: Vec<_> = result.series[0].rows.iter().map(|r| (r.timestamp, r.values[0])).collect();
+        assert_eq!(rows, vec![(118, Some(118.0)), (117, Some(117.0))]);
+
+        // 每个序列分别分桶，多个聚合函数按桶合并为一行
+        let result = crate::ql::query(&db, "SELECT max(value), count(value) FROM cpu \
+                                             WHERE time > now() - 2m GROUP BY time(1m) FILL(null)", 120).unwrap();
+        assert_eq!(result.columns, vec!["max", "count"]);
+        let mut series: Vec<_> = result.series.iter().map(|s| (s.series.to_string(), s.rows.clone())).collect();
+        series.sort_by(|x, y| x.0.cmp(&y.0));
+        let row = |timestamp, max, count| crate::ql::Row { timestamp, values: vec![max, count] };
+        assert_eq!(series[0].1, vec![row(0, Some(59.0), Some(59.0)), row(60, Some(119.0), Some(60.0)), row(120, None, None)]);
+        assert_eq!(series[1].1, vec![row(0, Some(5.0), Some(1.0)), row(60, None, None), row(120, None, None)]);
+
+        let result = crate::ql::query(&db, "SELECT sum(value) FROM cpu WHERE host = 'b' AND time > 100", 1000).unwrap();
+        assert!(result.series.is_empty());
+        assert!(matches!(
+            crate::ql::query(&db, "SELECT max(value) FROM cpu WHERE host = 'a' OR host = 'b'", 1000),
+            Err(Error::QueryError { position: 44, .. })
+        ));
+    }
+}
@@ -2,19 +2,26 @@ use thiserror::Error as ErrorMacro;
 use std::io;
 
 #[derive(ErrorMacro, Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Error {
     #[error("IO error: {0}")]
     IoError(#[from] io::Error),
-    
+
     #[error("Data error: {0}")]
     DataError(String),
-    
+
     #[error("Compression error: {0}")]
     CompressionError(String),
-    
+
     #[error("Memory map error: {0}")]
     MemMapError(String),
+
+    #[error("Series error: {0}")]
+    SeriesError(String),
+
+    /// 查询语句错误，position为出错处在语句中的字符偏移（从0开始）
+    #[error("Query error at offset {position}: {message}")]
+    QueryError { position: usize, message: String },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
-
@@ -0,0 +1,109 @@
+use std::sync::{Arc, Mutex};
+
+use log::{error, info};
+
+use crate::error::{Error, Result};
+use crate::manifest::{Manifest, VersionEdit};
+use crate::memtable::MemTable;
+use crate::sstable::SSTable;
+use crate::wal::Wal;
+
+/// 刷盘器：把MemTable冻结为只读的不可变MemTable，写成SSTable后再删除对应的WAL段
+///
+/// 冻结和切换WAL段在MemTable的锁内完成，之后的写入进入新的MemTable和新的段；
+/// 写SSTable时不持有MemTable的锁，查询仍然能读到不可变MemTable中的数据。
+pub struct Flusher {
+    memtable: Arc<Mutex<MemTable>>,
+    immutable: Mutex<Option<Frozen>>,
+    wal: Arc<Wal>,
+    sstables: Arc<Mutex<Vec<Arc<SSTable>>>>,
+    manifest: Arc<Manifest>,
+    block_points: usize,
+    running: Mutex<()>, // 同一时间只允许一个刷盘任务
+}
+
+/// 等待写入SSTable的不可变MemTable，以及它的数据所在的最后一个WAL段
+#[derive(Clone)]
+struct Frozen {
+    memtable: Arc<MemTable>,
+    sealed_segment: u64,
+}
+
+impl Flusher {
+    pub fn new(
+        memtable: Arc<Mutex<MemTable>>,
+        wal: Arc<Wal>,
+        sstables: Arc<Mutex<Vec<Arc<SSTable>>>>,
+        manifest: Arc<Manifest>,
+        block_points: usize,
+    ) -> Self {
+        Flusher {
+            memtable,
+            immutable: Mutex::new(None),
+            wal,
+            sstables,
+            manifest,
+            block_points,
+            running: Mutex::new(()),
+        }
+    }
+
+    /// 正在刷盘的不可变MemTable，它比MemTable旧、比所有SSTable新
+    pub fn immutable(&self) -> Option<Arc<MemTable>> {
+        self.immutable.lock().unwrap().as_ref().map(|f| Arc::clone(&f.memtable))
+    }
+
+    /// 冻结当前MemTable并写成SSTable，MemTable为空时返回false
+    pub fn flush(&self) -> Result<bool> {
+        let _running = self.running.lock().unwrap();
+
+        // 上一次刷盘失败留下的不可变MemTable要先写完，保持新旧顺序
+        let pending = self.immutable.lock().unwrap().clone();
+        if let Some(frozen) = pending {
+            info!("重试写入上次刷盘失败的MemTable");
+            self.write(frozen)?;
+        }
+
+        let frozen = {
+            let mut mem = self.memtable.lock().unwrap();
+            if mem.is_empty() {
+                return Ok(false);
+            }
+            let sealed_segment = self.wal.rotate()?;
+            // 新的MemTable比冻结的MemTable新一个序列号
+            let next = MemTable::with_seq(mem.seq() + 1);
+            let frozen = Frozen {
+                memtable: Arc::new(std::mem::replace(&mut *mem, next)),
+                sealed_segment,
+            };
+            *self.immutable.lock().unwrap() = Some(frozen.clone());
+            frozen
+        };
+        self.write(frozen)?;
+        Ok(true)
+    }
+
+    fn write(&self, frozen: Frozen) -> Result<()> {
+        let path = self.manifest.new_file_path();
+        let sst = SSTable::create(path, &frozen.memtable, self.block_points)?;
+
+        // 记入清单后新文件才算生效，之后才能删除对应的WAL段；同时记下它的序列号
+        let name = sst
+            .file_name()
+            .ok_or_else(|| Error::DataError(format!("无效的SSTable路径: {:?}", sst.path)))?;
+        let edit = VersionEdit { last_seq: Some(sst.seq()), ..VersionEdit::add(name) };
+        self.manifest.log_and_apply(&edit)?;
+
+        // 先加入文件列表再丢弃不可变MemTable，查询始终能看到这部分数据
+        self.sstables.lock().unwrap().push(Arc::new(sst));
+        *self.immutable.lock().unwrap() = None;
+
+        // SSTable已落盘并记入清单，封存的段不再需要；删除失败只会在重启时多回放一次
+        if let Err(e) = self.wal.remove_sealed(frozen.sealed_segment) {
+            error!("删除已封存的WAL段失败: {:?}", e);
+        }
+        info!("刷盘完成: {} 个序列, {} 条数据, WAL段 {} 及之前的段已删除",
+              frozen.memtable.series_count(), frozen.memtable.len(), frozen.sealed_segment);
+        Ok(())
+    }
+}
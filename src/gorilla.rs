@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::io::{self, Read, Write};
 
 /// 按位写入数据的工具类
@@ -16,12 +17,18 @@ impl<W: Write> BitWriter<W> {
         }
     }
 
-    /// 写入指定位数的比特
+    /// 写入指定位数的比特（最多64位）
     pub fn write_bits(&mut self, value: u64, bits: u8) -> io::Result<()> {
         if bits == 0 {
             return Ok(());
         }
 
+        // 缓冲区中最多残留7位，超过32位时分两次写入避免移位溢出
+        if bits > 32 {
+            self.write_bits(value, 32)?;
+            return self.write_bits(value >> 32, bits - 32);
+        }
+
         // 添加到缓冲区
         self.buffer |= (value & ((1 << bits) - 1)) << self.bits_in_buffer;
         self.bits_in_buffer += bits;
@@ -80,27 +87,25 @@ impl<R: Read> BitReader<R> {
         }
     }
 
-    /// 读取指定位数的比特
+    /// 读取指定位数的比特（最多64位）
     pub fn read_bits(&mut self, bits: u8) -> io::Result<u64> {
         if bits == 0 {
             return Ok(0);
         }
 
-        // 确保缓冲区有足够的位
+        // 与写入对称，超过32位时分两次读取
+        if bits > 32 {
+            let low = self.read_bits(32)?;
+            let high = self.read_bits(bits - 32)?;
+            return Ok(low | (high << 32));
+        }
+
+        // 确保缓冲区有足够的位，数据不足时返回UnexpectedEof
         while self.bits_in_buffer < bits {
             let mut byte = [0u8; 1];
-            match self.reader.read_exact(&mut byte) {
-                Ok(()) => {
-                    self.buffer |= (byte[0] as u64) << self.bits_in_buffer;
-                    self.bits_in_buffer += 8;
-                }
-                Err(e) => {
-                    if self.bits_in_buffer == 0 {
-                        return Err(e);
-                    }
-                    break; // 读不到更多数据但缓冲区仍有数据
-                }
-            }
+            self.reader.read_exact(&mut byte)?;
+            self.buffer |= (byte[0] as u64) << self.bits_in_buffer;
+            self.bits_in_buffer += 8;
         }
 
         // 提取需要的位
@@ -134,6 +139,9 @@ impl<R: Read> BitReader<R> {
 }
 
 /// Gorilla编码器实现
+///
+/// 比特流与基线版本不同：前缀按读取顺序写入，大范围的delta-of-delta保存完整的64位。
+/// 基线版本写出的数据块用[`TimeSeriesBlock::decompress_baseline`]读取。
 pub struct GorillaEncoder<W: Write> {
     bit_writer: BitWriter<W>,
     first_timestamp: u64,
@@ -175,25 +183,26 @@ impl<W: Write> GorillaEncoder<W> {
         let delta_of_delta = delta - self.prev_delta;
         
         // 根据delta-of-delta大小选择不同的编码
+        // 注意BitWriter先输出低位，前缀10/110/1110/1111按读取顺序写作0b01/0b011/0b0111/0b1111
         if delta_of_delta == 0 {
             // 不变，用1位表示
             self.bit_writer.write_bits(0, 1)?;
-        } else if delta_of_delta >= -63 && delta_of_delta <= 64 {
+        } else if (-64..=63).contains(&delta_of_delta) {
             // 小范围变化，用9位表示
-            self.bit_writer.write_bits(0b10, 2)?;
+            self.bit_writer.write_bits(0b01, 2)?;
             self.bit_writer.write_bits((delta_of_delta & 0x7F) as u64, 7)?;
-        } else if delta_of_delta >= -255 && delta_of_delta <= 256 {
+        } else if (-256..=255).contains(&delta_of_delta) {
             // 中等范围变化，用12位表示
-            self.bit_writer.write_bits(0b110, 3)?;
+            self.bit_writer.write_bits(0b011, 3)?;
             self.bit_writer.write_bits((delta_of_delta & 0x1FF) as u64, 9)?;
-        } else if delta_of_delta >= -2047 && delta_of_delta <= 2048 {
+        } else if (-2048..=2047).contains(&delta_of_delta) {
             // 较大范围变化，用16位表示
-            self.bit_writer.write_bits(0b1110, 4)?;
+            self.bit_writer.write_bits(0b0111, 4)?;
             self.bit_writer.write_bits((delta_of_delta & 0xFFF) as u64, 12)?;
         } else {
-            // 大范围变化，用36位表示
+            // 大范围变化，用68位表示
             self.bit_writer.write_bits(0b1111, 4)?;
-            self.bit_writer.write_bits((delta_of_delta & 0xFFFFFFFF) as u64, 32)?;
+            self.bit_writer.write_bits(delta_of_delta as u64, 64)?;
         }
 
         self.prev_delta = delta;
@@ -208,8 +217,8 @@ impl<W: Write> GorillaEncoder<W> {
             // 值相同，使用1位0表示
             self.bit_writer.write_bits(0, 1)?;
         } else {
-            // 值不同
-            let leading_zeros = xor.leading_zeros() as u8;
+            // 值不同，前导零只有5位存储空间，最多记录31个
+            let leading_zeros = (xor.leading_zeros() as u8).min(31);
             let trailing_zeros = xor.trailing_zeros() as u8;
             
             // 计算有意义的位
@@ -221,8 +230,8 @@ impl<W: Write> GorillaEncoder<W> {
             // 写入前导零数量（5位）
             self.bit_writer.write_bits(leading_zeros as u64, 5)?;
             
-            // 写入有意义位的数量（6位）
-            self.bit_writer.write_bits(significant_bits as u64, 6)?;
+            // 写入有意义位的数量（6位），64位时记为0
+            self.bit_writer.write_bits((significant_bits & 0x3F) as u64, 6)?;
             
             // 写入有意义的位
             let meaningful_bits = xor >> trailing_zeros;
@@ -335,14 +344,8 @@ impl<R: Read> GorillaDecoder<R> {
                                         };
                                     }
                                     Ok(true) => {
-                                        // 1111前缀，大范围变化
-                                        let bits = self.bit_reader.read_bits(32)? as i64;
-                                        // 32位有符号数，需要处理符号扩展
-                                        delta_of_delta = if (bits & 0x80000000) != 0 {
-                                            bits | !0xFFFFFFFF
-                                        } else {
-                                            bits
-                                        };
+                                        // 1111前缀，大范围变化，完整的64位有符号数
+                                        delta_of_delta = self.bit_reader.read_bits(64)? as i64;
                                     }
                                     Err(e) => {
                                         if e.kind() == io::ErrorKind::UnexpectedEof {
@@ -387,12 +390,15 @@ impl<R: Read> GorillaDecoder<R> {
         match self.bit_reader.read_bit() {
             Ok(false) => {
                 // 值相同，直接返回
-                return Ok(Some((timestamp, self.prev_value)));
+                Ok(Some((timestamp, self.prev_value)))
             }
             Ok(true) => {
                 // 值不同，读取XOR编码
                 let leading_zeros = self.bit_reader.read_bits(5)? as u8;
-                let significant_bits = self.bit_reader.read_bits(6)? as u8;
+                let significant_bits = match self.bit_reader.read_bits(6)? as u8 {
+                    0 => 64,
+                    n => n,
+                };
                 
                 let meaningful_bits = self.bit_reader.read_bits(significant_bits)?;
                 let meaningful_bits_shifted = meaningful_bits << (64 - leading_zeros - significant_bits);
@@ -402,13 +408,13 @@ impl<R: Read> GorillaDecoder<R> {
                 let value = f64::from_bits(value_bits);
                 
                 self.prev_value = value;
-                return Ok(Some((timestamp, value)));
+                Ok(Some((timestamp, value)))
             }
             Err(e) => {
                 if e.kind() == io::ErrorKind::UnexpectedEof {
                     return Ok(None);
                 }
-                return Err(e);
+                Err(e)
             }
         }
     }
@@ -441,6 +447,7 @@ impl<R: Read> GorillaDecoder<R> {
 }
 
 /// 简单时序块，包含多个时序点(时间戳, 值)
+#[derive(Default)]
 pub struct TimeSeriesBlock {
     points: Vec<(u64, f64)>,
 }
@@ -496,7 +503,7 @@ impl TimeSeriesBlock {
         }
         
         // 完成编码
-        let mut writer = encoder.close()?;
+        encoder.close()?;
         
         // 返回压缩后的数据
         Ok(buf)
@@ -513,21 +520,95 @@ impl TimeSeriesBlock {
         }
         
         let len = u32::from_le_bytes(data[0..4].try_into().unwrap()) as usize;
-        let decoder = GorillaDecoder::new(&data[4..])?;
-        
-        // 解压所有点
-        let points = decoder.decode_all()?;
+        if len == 0 {
+            return Ok(TimeSeriesBlock::new());
+        }
+        let mut decoder = GorillaDecoder::new(&data[4..])?;
         
-        if points.len() != len {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("Expected {} points, but got {}", len, points.len()),
-            ));
+        // 按记录的点数解压，末尾字节的填充位不能当作数据点
+        let mut points = Vec::with_capacity(len);
+        while points.len() < len {
+            match decoder.decode()? {
+                Some(point) => points.push(point),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Expected {} points, but got {}", len, points.len()),
+                    ));
+                }
+            }
         }
         
         Ok(TimeSeriesBlock { points })
     }
     
+    /// 按基线版本的比特流解压，`first_ts`和`last_ts`为块中首尾两个点的时间戳
+    ///
+    /// 基线的前缀按`0b10/0b110/0b1110`写入，BitWriter先输出低位，读出的`0`开头的前缀与表示
+    /// delta-of-delta为0的`0`无法逐位区分。这里按点数、首尾时间戳、时间戳严格递增、有效位首尾为1
+    /// 以及比特流长度回溯搜索合法的解析，超过尝试次数仍找不到时报错。前导零超过31个时基线只保存了
+    /// 低5位，这类数值按低5位还原。
+    pub fn decompress_baseline(data: &[u8], first_ts: u64, last_ts: u64) -> io::Result<Self> {
+        if data.len() < 4 {
+            return Err(invalid_data("Data too short to contain block length".to_string()));
+        }
+        let len = u32::from_le_bytes(data[0..4].try_into().unwrap()) as usize;
+        if len == 0 {
+            return Ok(TimeSeriesBlock::new());
+        }
+        let bits = &data[4..];
+        let (Some(ts), Some(value)) = (bits_at(bits, 0, 64), bits_at(bits, 64, 64)) else {
+            return Err(invalid_data("Data too short to contain the first point".to_string()));
+        };
+        // 基线的发布构建写64位字段时移位溢出，首个点被写成全零，之后的数值都无法还原
+        if ts != first_ts {
+            return Err(invalid_data(format!("First timestamp {} does not match {}", ts, first_ts)));
+        }
+
+        let mut stack = vec![BaselineStep { pos: 128, ts, delta: 0, value, next: 0 }];
+        // 已确认无法解析出剩余点的状态，不同的前缀组合可能重新对齐到同一状态
+        let mut dead = HashSet::new();
+        let mut budget = BASELINE_SEARCH_LIMIT;
+        loop {
+            let depth = stack.len();
+            let Some(top) = stack.last_mut() else {
+                break;
+            };
+            if depth == len {
+                // 所有点都已解析，剩下的只能是最后一个字节的填充位
+                if top.ts == last_ts && top.pos.div_ceil(8) == bits.len() {
+                    let points = stack.iter().map(|s| (s.ts, f64::from_bits(s.value))).collect();
+                    return Ok(TimeSeriesBlock { points });
+                }
+                dead.insert((depth, top.state()));
+                stack.pop();
+                continue;
+            }
+            let Some(&prefix) = BASELINE_PREFIXES.get(top.next) else {
+                dead.insert((depth, top.state()));
+                stack.pop();
+                continue;
+            };
+            top.next += 1;
+            budget -= 1;
+            if budget == 0 {
+                return Err(invalid_data(format!("No valid parse for {} points within the search limit", len)));
+            }
+
+            // 后面每个点的时间戳至少加1、至少占2位
+            let remaining = len - depth - 1;
+            if let Some(step) = top.advance(bits, prefix)
+                && step.ts > top.ts
+                && step.ts.checked_add(remaining as u64).is_some_and(|ts| ts <= last_ts)
+                && step.pos + 2 * remaining <= bits.len() * 8
+                && !dead.contains(&(depth + 1, step.state()))
+            {
+                stack.push(step);
+            }
+        }
+        Err(invalid_data(format!("No valid parse for {} points", len)))
+    }
+
     /// 查询给定时间范围的数据点
     pub fn query(&self, start: u64, end: u64) -> Vec<(u64, f64)> {
         self.points
@@ -538,6 +619,83 @@ impl TimeSeriesBlock {
     }
 }
 
+/// 基线比特流的delta-of-delta前缀（按BitWriter写入的值）、前缀位数和数值位数，按出现频率排列
+const BASELINE_PREFIXES: [(u64, usize, usize); 5] = [(0b0, 1, 0), (0b10, 2, 7), (0b110, 3, 9), (0b1110, 4, 12), (0b1111, 4, 32)];
+
+/// 解析基线比特流时最多尝试的前缀次数，避免损坏的数据让搜索长时间运行
+const BASELINE_SEARCH_LIMIT: usize = 1 << 20;
+
+/// 基线比特流中已解析的一个点，`next`为下一个点待尝试的前缀
+struct BaselineStep {
+    pos: usize,
+    ts: u64,
+    delta: i64,
+    value: u64,
+    next: usize,
+}
+
+impl BaselineStep {
+    fn state(&self) -> (usize, u64, i64, u64) {
+        (self.pos, self.ts, self.delta, self.value)
+    }
+
+    /// 按指定前缀解析下一个点，前缀不符或数据不足时返回None
+    fn advance(&self, bits: &[u8], (prefix, prefix_bits, width): (u64, usize, usize)) -> Option<BaselineStep> {
+        if bits_at(bits, self.pos, prefix_bits)? != prefix {
+            return None;
+        }
+        let raw = bits_at(bits, self.pos + prefix_bits, width)?;
+        // 按位宽做符号扩展；基线的三档范围是-63..=64、-255..=256、-2047..=2048，
+        // 扩展后的最小负数实际是正的上界
+        let mut delta_of_delta = if width == 0 { 0 } else { ((raw << (64 - width)) as i64) >> (64 - width) };
+        if width > 0 && width < 32 && delta_of_delta == -(1 << (width - 1)) {
+            delta_of_delta = -delta_of_delta;
+        }
+        let delta = self.delta.checked_add(delta_of_delta)?;
+        let ts = self.ts.checked_add_signed(delta)?;
+        let mut pos = self.pos + prefix_bits + width;
+
+        let value = if bits_at(bits, pos, 1)? == 0 {
+            pos += 1;
+            self.value
+        } else {
+            let leading_zeros = bits_at(bits, pos + 1, 5)? as usize;
+            let significant_bits = match bits_at(bits, pos + 6, 6)? as usize {
+                0 => 64,
+                n => n,
+            };
+            if leading_zeros + significant_bits > 64 {
+                return None;
+            }
+            // 有效位去掉了首尾的零，最高位和最低位都是1
+            let meaningful_bits = bits_at(bits, pos + 12, significant_bits)?;
+            if significant_bits < 64 && (meaningful_bits & 1 == 0 || meaningful_bits >> (significant_bits - 1) == 0) {
+                return None;
+            }
+            pos += 12 + significant_bits;
+            self.value ^ (meaningful_bits << (64 - leading_zeros - significant_bits))
+        };
+        Some(BaselineStep { pos, ts, delta, value, next: 0 })
+    }
+}
+
+/// 从第`pos`位开始按低位在前读取`bits`位（最多64位），超出数据时返回None
+fn bits_at(data: &[u8], pos: usize, bits: usize) -> Option<u64> {
+    if pos + bits > data.len() * 8 {
+        return None;
+    }
+    let mut value = 0u64;
+    for i in 0..bits {
+        let p = pos + i;
+        value |= u64::from((data[p / 8] >> (p % 8)) & 1) << i;
+    }
+    Some(value)
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -598,5 +756,107 @@ mod tests {
         assert_eq!(result[20].0, 1400);
         assert_eq!(result[20].1, 40.0);
     }
+
+    #[test]
+    fn test_irregular_roundtrip() {
+        let mut block = TimeSeriesBlock::new();
+
+        // 覆盖各档delta-of-delta编码以及完全不同的浮点值
+        let mut ts = 1_622_000_000u64;
+        for i in 0..500u64 {
+            ts += match i % 5 {
+                0 => 1,
+                1 => 60,
+                2 => 300,
+                3 => 5_000,
+                _ => 10_000_000_000,
+            };
+            let val = if i % 7 == 0 { f64::NAN } else { (i as f64).sin() * 1e6 - i as f64 };
+            block.add_point(ts, val);
+        }
+
+        let compressed = block.compress().unwrap();
+        let decompressed = TimeSeriesBlock::decompress(&compressed).unwrap();
+        assert_eq!(block.len(), decompressed.len());
+        for (&(orig_ts, orig_val), &(dec_ts, dec_val)) in
+                block.get_points().iter().zip(decompressed.get_points().iter()) {
+            assert_eq!(orig_ts, dec_ts);
+            assert_eq!(orig_val.to_bits(), dec_val.to_bits());
+        }
+    }
+    /// 按基线版本的编码器写出数据块：前缀按0b10/0b110/0b1110/0b1111写入，大范围只保留低32位
+    fn compress_baseline(points: &[(u64, f64)]) -> Vec<u8> {
+        let mut buf = (points.len() as u32).to_le_bytes().to_vec();
+        let mut writer = BitWriter::new(&mut buf);
+        writer.write_bits(points[0].0, 64).unwrap();
+        writer.write_bits(points[0].1.to_bits(), 64).unwrap();
+        let (mut prev_ts, mut prev_delta, mut prev_value) = (points[0].0, 0i64, points[0].1.to_bits());
+        for &(ts, value) in &points[1..] {
+            let delta = ts as i64 - prev_ts as i64;
+            let dod = delta - prev_delta;
+            let (prefix, prefix_bits, width) = match dod {
+                0 => (0b0, 1, 0),
+                -63..=64 => (0b10, 2, 7),
+                -255..=256 => (0b110, 3, 9),
+                -2047..=2048 => (0b1110, 4, 12),
+                _ => (0b1111, 4, 32),
+            };
+            writer.write_bits(prefix, prefix_bits).unwrap();
+            writer.write_bits(dod as u64, width).unwrap();
+            prev_delta = delta;
+            prev_ts = ts;
+
+            let xor = value.to_bits() ^ prev_value;
+            if xor == 0 {
+                writer.write_bits(0, 1).unwrap();
+            } else {
+                let leading_zeros = xor.leading_zeros() as u8;
+                let trailing_zeros = xor.trailing_zeros() as u8;
+                let significant_bits = 64 - leading_zeros - trailing_zeros;
+                writer.write_bits(1, 1).unwrap();
+                writer.write_bits(leading_zeros as u64, 5).unwrap();
+                writer.write_bits(significant_bits as u64, 6).unwrap();
+                writer.write_bits(xor >> trailing_zeros, significant_bits).unwrap();
+            }
+            prev_value = value.to_bits();
+        }
+        writer.flush().unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_baseline_bitstream() {
+        // 覆盖四档前缀、各档的正上界以及重复的数值
+        let mut points = Vec::new();
+        let mut ts = 1_622_000_000u64;
+        for i in 0..300u64 {
+            ts += match i % 7 {
+                0 | 1 => 60,
+                2 => 124,
+                3 => 10,
+                4 => 2_058,
+                5 => 100_000,
+                _ => 60,
+            };
+            let val = if i % 3 == 0 { 25.0 } else { 25.0 + (i % 10) as f64 * 0.5 };
+            points.push((ts, val));
+        }
+
+        let data = compress_baseline(&points);
+        let first = points[0].0;
+        let last = points[points.len() - 1].0;
+        let block = TimeSeriesBlock::decompress_baseline(&data, first, last).unwrap();
+        assert_eq!(block.get_points(), &points[..]);
+
+        // 只有一个点的块
+        let data = compress_baseline(&points[..1]);
+        let block = TimeSeriesBlock::decompress_baseline(&data, first, first).unwrap();
+        assert_eq!(block.get_points(), &points[..1]);
+
+        // 基线发布构建写出的首个点为全零，报错而不是返回错误的数值
+        let mut data = compress_baseline(&points);
+        data[4..20].fill(0);
+        assert!(TimeSeriesBlock::decompress_baseline(&data, first, last).is_err());
+    }
 }
 
@@ -0,0 +1,404 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{BufReader, BufWriter, ErrorKind, Read, Write},
+    path::Path,
+    sync::{Mutex, RwLock},
+};
+
+use log::{debug, info, warn};
+use regex::Regex;
+
+use crate::error::{Error, Result};
+use crate::series::SeriesKey;
+
+/// 序列ID，按注册顺序分配
+pub type SeriesId = u64;
+
+/// 指标名在倒排索引中对应的标签名
+pub const METRIC_LABEL: &str = "__name__";
+
+/// 标签匹配方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchOp {
+    Equal,
+    NotEqual,
+    Regex,
+    NotRegex,
+}
+
+impl MatchOp {
+    fn as_str(&self) -> &'static str {
+        match self {
+            MatchOp::Equal => "=",
+            MatchOp::NotEqual => "!=",
+            MatchOp::Regex => "=~",
+            MatchOp::NotRegex => "!~",
+        }
+    }
+}
+
+/// 标签匹配器，语义与Prometheus一致：
+/// 序列不含该标签时按空字符串匹配，正则表达式总是完整匹配
+#[derive(Debug, Clone)]
+pub struct Matcher {
+    name: String,
+    op: MatchOp,
+    value: String,
+    regex: Option<Regex>,
+}
+
+impl Matcher {
+    pub fn new(name: &str, op: MatchOp, value: &str) -> Result<Self> {
+        let regex = match op {
+            MatchOp::Regex | MatchOp::NotRegex => Some(
+                Regex::new(&format!("^(?:{})$", value))
+                    .map_err(|e| Error::SeriesError(format!("正则表达式 '{}' 无效: {}", value, e)))?,
+            ),
+            _ => None,
+        };
+        Ok(Matcher {
+            name: name.to_string(),
+            op,
+            value: value.to_string(),
+            regex,
+        })
+    }
+
+    /// 解析选择器，如 `cpu{host=~"web-.*",region!="eu"}`
+    ///
+    /// 指标名和花括号部分都可以省略，标签值的引号也可以省略（此时值中不能含逗号）
+    pub fn parse_selector(s: &str) -> Result<Vec<Matcher>> {
+        let s = s.trim();
+        let (metric, body) = match s.find('{') {
+            Some(i) => {
+                let body = s[i + 1..]
+                    .strip_suffix('}')
+                    .ok_or_else(|| Error::SeriesError(format!("选择器 '{}' 缺少 '}}'", s)))?;
+                (s[..i].trim(), body)
+            }
+            None => (s, ""),
+        };
+
+        let mut matchers = Vec::new();
+        if !metric.is_empty() {
+            matchers.push(Matcher::new(METRIC_LABEL, MatchOp::Equal, metric)?);
+        }
+
+        let mut rest = body.trim();
+        while !rest.is_empty() {
+            let op_pos = rest
+                .find(['=', '!'])
+                .ok_or_else(|| Error::SeriesError(format!("匹配器 '{}' 缺少操作符", rest)))?;
+            let name = rest[..op_pos].trim();
+            let tail = &rest[op_pos..];
+            let op = if tail.starts_with("=~") {
+                MatchOp::Regex
+            } else if tail.starts_with("!~") {
+                MatchOp::NotRegex
+            } else if tail.starts_with("!=") {
+                MatchOp::NotEqual
+            } else if tail.starts_with('=') {
+                MatchOp::Equal
+            } else {
+                return Err(Error::SeriesError(format!("匹配器 '{}' 操作符无效", rest)));
+            };
+            rest = rest[op_pos + op.as_str().len()..].trim_start();
+
+            let value;
+            if let Some(quoted) = rest.strip_prefix('"') {
+                let end = quoted
+                    .find('"')
+                    .ok_or_else(|| Error::SeriesError(format!("标签 {} 的值缺少结束引号", name)))?;
+                value = &quoted[..end];
+                rest = quoted[end + 1..].trim_start();
+            } else {
+                let end = rest.find(',').unwrap_or(rest.len());
+                value = rest[..end].trim();
+                rest = &rest[end..];
+                if value.contains(char::is_whitespace) {
+                    return Err(Error::SeriesError(format!("标签 {} 的值含空白，需要加引号", name)));
+                }
+            }
+            if name.is_empty() {
+                return Err(Error::SeriesError("匹配器缺少标签名".to_string()));
+            }
+            matchers.push(Matcher::new(name, op, value)?);
+
+            rest = match rest.strip_prefix(',') {
+                Some(r) => r.trim_start(),
+                None if rest.is_empty() => rest,
+                None => return Err(Error::SeriesError(format!("匹配器之间应以逗号分隔: '{}'", rest))),
+            };
+        }
+
+        Ok(matchers)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn op(&self) -> MatchOp {
+        self.op
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// 判断标签值是否满足匹配条件
+    pub fn matches(&self, value: &str) -> bool {
+        match self.op {
+            MatchOp::Equal => value == self.value,
+            MatchOp::NotEqual => value != self.value,
+            MatchOp::Regex => self.regex.as_ref().is_some_and(|r| r.is_match(value)),
+            MatchOp::NotRegex => !self.regex.as_ref().is_some_and(|r| r.is_match(value)),
+        }
+    }
+
+    /// 判断序列是否满足匹配条件
+    pub fn matches_series(&self, series: &SeriesKey) -> bool {
+        self.matches(label_value(series, &self.name))
+    }
+}
+
+impl fmt::Display for Matcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}\"{}\"", self.name, self.op.as_str(), self.value)
+    }
+}
+
+/// 序列中指定标签的值，指标名对应`__name__`，缺失的标签视为空字符串
+fn label_value<'a>(series: &'a SeriesKey, name: &str) -> &'a str {
+    if name == METRIC_LABEL {
+        series.metric()
+    } else {
+        series.tag(name).unwrap_or("")
+    }
+}
+
+#[derive(Default)]
+struct IndexInner {
+    series: Vec<SeriesKey>,
+    ids: HashMap<SeriesKey, SeriesId>,
+    postings: BTreeMap<(String, String), BTreeSet<SeriesId>>,
+}
+
+impl IndexInner {
+    fn insert(&mut self, series: SeriesKey) -> SeriesId {
+        let id = self.series.len() as SeriesId;
+        self.postings
+            .entry((METRIC_LABEL.to_string(), series.metric().to_string()))
+            .or_default()
+            .insert(id);
+        for (k, v) in series.tags() {
+            self.postings.entry((k.clone(), v.clone())).or_default().insert(id);
+        }
+        self.ids.insert(series.clone(), id);
+        self.series.push(series);
+        id
+    }
+}
+
+/// 倒排索引：标签对 -> 序列ID集合
+///
+/// 序列注册记录追加写入索引文件（`id(u64) | key_len(u16) | series_key`，小端序），
+/// 打开时回放文件重建倒排表。
+pub struct TagIndex {
+    file: Mutex<BufWriter<File>>,
+    inner: RwLock<IndexInner>,
+}
+
+impl TagIndex {
+    pub fn open(path: &str) -> Result<Self> {
+        if let Some(parent) = Path::new(path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut inner = IndexInner::default();
+        let mut valid_len = 0;
+        match File::open(path) {
+            Ok(f) => {
+                let mut reader = BufReader::new(f);
+                while let Some((id, series, len)) = read_entry(&mut reader)? {
+                    if id != inner.series.len() as SeriesId {
+                        return Err(Error::DataError(format!("索引文件中的序列ID {} 不连续", id)));
+                    }
+                    inner.insert(series);
+                    valid_len += len;
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(Error::IoError(e)),
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        // 截掉崩溃时写了一半的末尾记录，避免后续追加的记录错位
+        if file.metadata()?.len() > valid_len {
+            warn!("索引文件末尾记录不完整，已截断到 {} 字节", valid_len);
+            file.set_len(valid_len)?;
+        }
+        info!("倒排索引打开: {}, {} 个序列, {} 个标签对", path, inner.series.len(), inner.postings.len());
+
+        Ok(TagIndex {
+            file: Mutex::new(BufWriter::new(file)),
+            inner: RwLock::new(inner),
+        })
+    }
+
+    /// 获取序列ID，未注册的序列会先写入索引文件
+    pub fn get_or_register(&self, series: &SeriesKey) -> Result<SeriesId> {
+        if let Some(&id) = self.inner.read().unwrap().ids.get(series) {
+            return Ok(id);
+        }
+
+        let mut inner = self.inner.write().unwrap();
+        if let Some(&id) = inner.ids.get(series) {
+            return Ok(id);
+        }
+
+        let id = inner.series.len() as SeriesId;
+        {
+            let mut file = self.file.lock().unwrap();
+            write_entry(&mut *file, id, series)?;
+            file.flush()?;
+        }
+        inner.insert(series.clone());
+        debug!("注册新序列 id={}, series={}", id, series);
+        Ok(id)
+    }
+
+    /// 根据ID获取序列
+    pub fn series(&self, id: SeriesId) -> Option<SeriesKey> {
+        self.inner.read().unwrap().series.get(id as usize).cloned()
+    }
+
+    /// 已注册的序列数量
+    pub fn series_count(&self) -> usize {
+        self.inner.read().unwrap().series.len()
+    }
+
+    /// 选出同时满足所有匹配器的序列，按序列ID排序
+    ///
+    /// 不匹配空字符串的匹配器通过倒排表求交集缩小候选集，
+    /// 其余匹配器（如`!=`）再逐个序列过滤。
+    pub fn select(&self, matchers: &[Matcher]) -> Vec<(SeriesId, SeriesKey)> {
+        let inner = self.inner.read().unwrap();
+
+        let mut candidates: Option<BTreeSet<SeriesId>> = None;
+        for m in matchers.iter().filter(|m| !m.matches("")) {
+            let mut ids = BTreeSet::new();
+            let start = (m.name.clone(), String::new());
+            for ((name, value), postings) in inner.postings.range(start..) {
+                if name != &m.name {
+                    break;
+                }
+                if m.matches(value) {
+                    ids.extend(postings.iter().copied());
+                }
+            }
+            candidates = Some(match candidates {
+                Some(c) => c.intersection(&ids).copied().collect(),
+                None => ids,
+            });
+        }
+
+        let candidates: Box<dyn Iterator<Item = SeriesId>> = match candidates {
+            Some(c) => Box::new(c.into_iter()),
+            None => Box::new(0..inner.series.len() as SeriesId),
+        };
+
+        candidates
+            .map(|id| (id, &inner.series[id as usize]))
+            .filter(|(_, series)| matchers.iter().all(|m| m.matches_series(series)))
+            .map(|(id, series)| (id, series.clone()))
+            .collect()
+    }
+}
+
+fn write_entry<W: Write>(w: &mut W, id: SeriesId, series: &SeriesKey) -> Result<()> {
+    let key = series.to_string();
+    let key_len = u16::try_from(key.len())
+        .map_err(|_| Error::SeriesError(format!("序列键过长: {} 字节", key.len())))?;
+    w.write_all(&id.to_le_bytes())?;
+    w.write_all(&key_len.to_le_bytes())?;
+    w.write_all(key.as_bytes())?;
+    Ok(())
+}
+
+/// 读取一条注册记录及其字节长度，文件结束或末尾记录不完整时返回None
+fn read_entry<R: Read>(r: &mut R) -> Result<Option<(SeriesId, SeriesKey, u64)>> {
+    let mut header = [0u8; 10];
+    let mut key = Vec::new();
+    let read = r.read_exact(&mut header).and_then(|_| {
+        key.resize(u16::from_le_bytes(header[8..10].try_into().unwrap()) as usize, 0);
+        r.read_exact(&mut key)
+    });
+    match read {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(Error::IoError(e)),
+    }
+
+    let id = u64::from_le_bytes(header[0..8].try_into().unwrap());
+    let len = (header.len() + key.len()) as u64;
+    let key = String::from_utf8(key)
+        .map_err(|_| Error::DataError("索引文件中的序列键不是合法的UTF-8".to_string()))?;
+    Ok(Some((id, SeriesKey::parse(&key)?, len)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(index: &TagIndex, selector: &str) -> Vec<String> {
+        let matchers = Matcher::parse_selector(selector).unwrap();
+        index.select(&matchers).into_iter().map(|(_, s)| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_select_and_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("series.idx");
+        let path = path.to_str().unwrap();
+
+        let index = TagIndex::open(path).unwrap();
+        for key in [
+            "cpu,host=web-1,region=us",
+            "cpu,host=web-2,region=eu",
+            "cpu,host=db-1,region=us",
+            "cpu,host=web-3",
+            "mem,host=web-1,region=us",
+        ] {
+            index.get_or_register(&SeriesKey::parse(key).unwrap()).unwrap();
+        }
+        // 重复注册返回同一个ID
+        let id = index.get_or_register(&SeriesKey::parse("cpu,host=db-1,region=us").unwrap()).unwrap();
+        assert_eq!(id, 2);
+
+        assert_eq!(keys(&index, r#"cpu{host=~"web-.*",region!="eu"}"#),
+                   vec!["cpu,host=web-1,region=us", "cpu,host=web-3"]);
+        assert_eq!(keys(&index, "{region=us}").len(), 3);
+        assert_eq!(keys(&index, "cpu{region=}"), vec!["cpu,host=web-3"]);
+        assert_eq!(keys(&index, r#"{__name__=~"cpu|mem",host!~"web-.*"}"#), vec!["cpu,host=db-1,region=us"]);
+        assert!(keys(&index, "disk").is_empty());
+
+        // 重新打开后索引内容保持不变
+        drop(index);
+        let index = TagIndex::open(path).unwrap();
+        assert_eq!(index.series_count(), 5);
+        assert_eq!(keys(&index, "{host=web-1}").len(), 2);
+        let id = index.get_or_register(&SeriesKey::parse("disk,host=web-1").unwrap()).unwrap();
+        assert_eq!(id, 5);
+    }
+
+    #[test]
+    fn test_parse_selector_errors() {
+        assert!(Matcher::parse_selector("cpu{host").is_err());
+        assert!(Matcher::parse_selector("cpu{host=\"a}").is_err());
+        assert!(Matcher::parse_selector("cpu{host=~\"(\"}").is_err());
+        assert!(Matcher::parse_selector("cpu{host=a region=b}").is_err());
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+};
+
+use crate::error::Result;
+use crate::memtable::Tombstone;
+use crate::series::SeriesKey;
+use crate::wal::{Timestamp, Value};
+
+/// 按时间顺序产生数据点的来源，如MemTable区间的拷贝或SSTable的块迭代器
+pub type PointSource = Box<dyn Iterator<Item = Result<(Timestamp, Value)>> + Send>;
+
+/// 查询结果的时间顺序
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Order {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+/// 对多个来源做k路归并的查询迭代器，按时间顺序惰性产生数据点
+///
+/// 来源按从新到旧的顺序加入：同一时间戳只保留最新来源的值，
+/// 较新来源的墓碑屏蔽所有更旧来源中被覆盖的数据。所有来源须按迭代器的顺序产生数据点。
+pub struct QueryIter {
+    series: SeriesKey,
+    start: Timestamp,
+    end: Timestamp,
+    order: Order,
+    sources: Vec<Source>,
+    // 每个来源当前的第一个点，按(排序键, 来源序号)取最小，序号小的来源更新；
+    // 倒序时排序键为时间戳按位取反
+    heap: BinaryHeap<Reverse<(Timestamp, usize)>>,
+    // 已加入的来源中与查询相关的墓碑，作用于之后加入的更旧来源
+    tombstones: Vec<Tombstone>,
+    last_ts: Option<Timestamp>,
+    failed: bool,
+}
+
+struct Source {
+    points: PointSource,
+    head: Option<Value>,
+    masks: Vec<Tombstone>,
+}
+
+impl QueryIter {
+    pub fn new(series: SeriesKey, start: Timestamp, end: Timestamp) -> Self {
+        QueryIter::with_order(series, start, end, Order::Ascending)
+    }
+
+    pub fn with_order(series: SeriesKey, start: Timestamp, end: Timestamp, order: Order) -> Self {
+        QueryIter {
+            series,
+            start,
+            end,
+            order,
+            sources: Vec::new(),
+            heap: BinaryHeap::new(),
+            tombstones: Vec::new(),
+            last_ts: None,
+            failed: false,
+        }
+    }
+
+    /// 加入一个比已有来源都旧的来源，tombstones是该来源自带的墓碑
+    pub fn push_source(&mut self, points: PointSource, tombstones: &[Tombstone]) -> Result<()> {
+        let idx = self.sources.len();
+        self.sources.push(Source { points, head: None, masks: self.tombstones.clone() });
+        self.advance(idx)?;
+
+        let (series, start, end) = (&self.series, self.start, self.end);
+        self.tombstones.extend(
+            tombstones
+                .iter()
+                .filter(|t| t.applies_to(series) && t.start <= end && t.end >= start)
+                .cloned(),
+        );
+        Ok(())
+    }
+
+    /// 取出来源的下一个未被屏蔽的点放入堆中
+    fn advance(&mut self, idx: usize) -> Result<()> {
+        let source = &mut self.sources[idx];
+        source.head = None;
+        for point in source.points.by_ref() {
+            let (ts, value) = point?;
+            if source.masks.iter().any(|t| t.covers(&self.series, ts)) {
+                continue;
+            }
+            source.head = Some(value);
+            let key = match self.order {
+                Order::Ascending => ts,
+                Order::Descending => !ts,
+            };
+            self.heap.push(Reverse((key, idx)));
+            break;
+        }
+        Ok(())
+    }
+}
+
+impl Iterator for QueryIter {
+    type Item = Result<(Timestamp, Value)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        while let Some(Reverse((key, idx))) = self.heap.pop() {
+            let ts = match self.order {
+                Order::Ascending => key,
+                Order::Descending => !key,
+            };
+            let value = self.sources[idx].head.take()?;
+            if let Err(e) = self.advance(idx) {
+                self.failed = true;
+                return Some(Err(e));
+            }
+            // 同一时间戳最先弹出的是最新来源的值，其余的丢弃
+            if self.last_ts == Some(ts) {
+                continue;
+            }
+            self.last_ts = Some(ts);
+            return Some(Ok((ts, value)));
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(points: Vec<(Timestamp, Value)>) -> PointSource {
+        Box::new(points.into_iter().map(Ok))
+    }
+
+    #[test]
+    fn test_merge_newest_wins() {
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+        let mut iter = QueryIter::new(cpu.clone(), 0, 100);
+        iter.push_source(source(vec![(2, 20.0), (5, 50.0)]), &[]).unwrap();
+        let tombstone = Tombstone { series: Some(cpu), start: 7, end: 8 };
+        iter.push_source(source(vec![(1, 1.0), (2, 2.0), (6, 6.0)]), &[tombstone]).unwrap();
+        iter.push_source(source(vec![(2, 0.2), (7, 7.0), (8, 8.0), (9, 9.0)]), &[]).unwrap();
+
+        let points: Vec<_> = iter.collect::<Result<_>>().unwrap();
+        assert_eq!(points, vec![(1, 1.0), (2, 20.0), (5, 50.0), (6, 6.0), (9, 9.0)]);
+    }
+
+    #[test]
+    fn test_merge_descending() {
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+        let mut iter = QueryIter::with_order(cpu.clone(), 0, 100, Order::Descending);
+        iter.push_source(source(vec![(5, 50.0), (2, 20.0)]), &[]).unwrap();
+        let tombstone = Tombstone { series: Some(cpu), start: 7, end: 8 };
+        iter.push_source(source(vec![(6, 6.0), (2, 2.0), (1, 1.0)]), &[tombstone]).unwrap();
+        iter.push_source(source(vec![(9, 9.0), (8, 8.0), (7, 7.0), (2, 0.2)]), &[]).unwrap();
+
+        let points: Vec<_> = iter.collect::<Result<_>>().unwrap();
+        assert_eq!(points, vec![(9, 9.0), (6, 6.0), (5, 50.0), (2, 20.0), (1, 1.0)]);
+    }
+}
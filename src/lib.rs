@@ -0,0 +1,19 @@
+pub mod aggregate;
+pub mod compaction;
+pub mod continuous;
+pub mod db;
+pub mod error;
+pub mod flush;
+pub mod gorilla;
+pub mod index;
+pub mod iter;
+pub mod manifest;
+pub mod memtable;
+pub mod ql;
+pub mod retention;
+pub mod rollup;
+pub mod series;
+pub mod server;
+pub mod sketch;
+pub mod sstable;
+pub mod wal;
@@ -1,15 +1,11 @@
-mod db;
-mod error;
-mod gorilla;
-mod sstable;
-mod wal;
-mod server;
-
 use std::thread;
 use std::time::Duration;
 use log::info;
-use db::{DbConfig, SimpleTSDB};
-use server::TsdbServer;
+use ry_tsdb::db::{DbConfig, SimpleTSDB};
+use ry_tsdb::error;
+use ry_tsdb::index::Matcher;
+use ry_tsdb::series::SeriesKey;
+use ry_tsdb::server::TsdbServer;
 use std::sync::Arc;
 
 #[tokio::main]
@@ -22,8 +18,10 @@ async fn main() -> error::Result<()> {
     // 配置数据库
     let config = DbConfig {
         sstable_dir: "./data/sstable".to_string(),
-        wal_path: "./data/wal.log".to_string(),
+        wal_dir: "./data/wal".to_string(),
+        index_path: "./data/series.idx".to_string(),
         memtable_size_threshold: 1000,
+        ..Default::default()
     };
     
     // 打开数据库
@@ -41,11 +39,19 @@ async fn main() -> error::Result<()> {
     
     // 输出统计信息
     let stats = db.get_stats()?;
-    info!("数据库统计: {} 个SSTable文件, 磁盘占用: {} 字节, MemTable记录数: {}", 
-    stats.sstable_count, stats.total_disk_size, stats.memtable_records);
+    info!("数据库统计: {} 个SSTable文件, 磁盘占用: {} 字节, 序列数: {}, MemTable序列数: {}, MemTable记录数: {}", 
+    stats.sstable_count, stats.total_disk_size, stats.series_count, stats.memtable_series, stats.memtable_records);
+    info!("压缩统计: {} 次压缩, 合并 {} 个文件, 读取 {} 字节, 写入 {} 字节",
+    stats.compaction.compactions, stats.compaction.input_files, stats.compaction.bytes_read, stats.compaction.bytes_written);
+    info!("WAL恢复: 回放 {} 条记录, 丢弃 {} 条记录",
+    stats.wal_recovery.recovered, stats.wal_recovery.discarded);
     
+    // 启动连续查询调度
+    let db = Arc::new(db);
+    SimpleTSDB::start_continuous_queries(&db);
+
     // 创建并启动服务器，监听6364端口
-    let server = TsdbServer::new(Arc::new(db), "127.0.0.1:6364".to_string());
+    let server = TsdbServer::new(db, "127.0.0.1:6364".to_string());
     info!("服务器将在 127.0.0.1:6364 监听请求");
     
     // 启动服务器（这会阻塞主线程）
@@ -59,25 +65,30 @@ fn write_sample_data(db: &SimpleTSDB) -> error::Result<()> {
     
     // 写入一些规律的CPU数据
     info!("写入示例数据...");
+    let cpu = SeriesKey::parse("cpu,host=server01,region=cn")?;
     for i in 0..100 {
-        db.put(base_ts + i * 60, 25.0 + (i as f64 % 10.0))?;
+        db.put(&cpu, base_ts + i * 60, 25.0 + (i as f64 % 10.0))?;
     }
     
     // 批量写入一些内存数据
+    let mem = SeriesKey::parse("mem,host=server01,region=cn")?;
     let mut batch = Vec::new();
     for i in 0..200 {
         batch.push((base_ts + 3000 + i * 30, 8192.0 + (i as f64 * 10.0)));
     }
-    db.batch_put(&batch)?;
+    db.batch_put(&mem, &batch)?;
     
     info!("写入完成，总计 {} 条记录", 100 + batch.len());
     Ok(())
 }
 
 fn query_example(db: &SimpleTSDB, base_ts: u64) -> error::Result<()> {
+    let cpu = SeriesKey::parse("cpu,host=server01,region=cn")?;
+    let mem = SeriesKey::parse("mem,host=server01,region=cn")?;
+
     // 查询CPU数据
     info!("查询CPU数据区间...");
-    let results = db.query(base_ts, base_ts + 3000)?;
+    let results = db.query(&cpu, base_ts, base_ts + 3000)?;
     println!("CPU数据查询结果（前10条）：");
     for (i, (ts, val)) in results.iter().take(10).enumerate() {
         println!("#{}: ts: {}, value: {}", i+1, ts, val);
@@ -85,20 +96,28 @@ fn query_example(db: &SimpleTSDB, base_ts: u64) -> error::Result<()> {
     
     // 查询内存数据
     info!("查询内存数据区间...");
-    let results = db.query(base_ts + 3000, base_ts + 5000)?;
+    let results = db.query(&mem, base_ts + 3000, base_ts + 5000)?;
     println!("\n内存数据查询结果（前10条）：");
     for (i, (ts, val)) in results.iter().take(10).enumerate() {
         println!("#{}: ts: {}, value: {}", i+1, ts, val);
     }
     
-    // 查询混合区间
-    info!("查询混合区间...");
-    let results = db.query(base_ts + 2500, base_ts + 4500)?;
-    println!("\n混合区间查询结果（前10条）：");
+    // 两个序列在时间上重叠，但查询只返回所选序列的数据
+    info!("查询重叠区间...");
+    let results = db.query(&cpu, base_ts + 2500, base_ts + 4500)?;
+    println!("\n重叠区间CPU查询结果（前10条）：");
     for (i, (ts, val)) in results.iter().take(10).enumerate() {
         println!("#{}: ts: {}, value: {}", i+1, ts, val);
     }
     
+    // 按标签选择序列
+    info!("按标签查询序列...");
+    let matchers = Matcher::parse_selector(r#"{host="server01",__name__=~"cpu|mem"}"#)?;
+    println!("\n标签匹配查询结果：");
+    for (series, points) in db.query_series(&matchers, base_ts, base_ts + 5000)? {
+        println!("{}: {} 条数据", series, points.len());
+    }
+    
     Ok(())
 }
 
@@ -0,0 +1,444 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use log::{debug, info, warn};
+
+use crate::error::{Error, Result};
+use crate::wal::{count_frames, decode_frame, write_frame};
+
+const MANIFEST_FILE: &str = "MANIFEST";
+const MANIFEST_TMP_FILE: &str = "MANIFEST.tmp";
+
+/// 版本变更记录类型
+const RECORD_EDIT: u8 = 1;
+/// 版本变更中的操作
+const OP_ADD_FILE: u8 = 1;
+const OP_DELETE_FILE: u8 = 2;
+const OP_REPLACE_FILE: u8 = 3;
+const OP_NEXT_FILE_NUMBER: u8 = 4;
+const OP_LAST_SEQ: u8 = 5;
+
+/// 一次原子的SSTable集合变更，要么整体生效，要么整体不生效
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VersionEdit {
+    /// 新增的文件，依次成为最新的文件
+    pub added: Vec<String>,
+    /// 删除的文件
+    pub deleted: Vec<String>,
+    /// `(旧文件, 新文件)`：新文件取代旧文件在新旧顺序中的位置，用于压缩输出；
+    /// 同一旧文件可以出现多次，对应的新文件按给出的顺序依次排在它原来的位置上
+    pub replaced: Vec<(String, String)>,
+    /// 下一个可分配的文件编号，由清单在写入时填写
+    pub next_file_number: Option<u64>,
+    /// 已分配的最大序列号，只增不减；文件被删除后序列号也不会回退
+    pub last_seq: Option<u64>,
+}
+
+impl VersionEdit {
+    /// 新增一个文件
+    pub fn add(name: impl Into<String>) -> Self {
+        VersionEdit {
+            added: vec![name.into()],
+            ..Default::default()
+        }
+    }
+
+    /// 删除一组文件
+    pub fn delete(names: Vec<String>) -> Self {
+        VersionEdit {
+            deleted: names,
+            ..Default::default()
+        }
+    }
+
+    /// 编码为操作序列，每个操作为`op(u8)`加参数：文件名为`name_len(u16) | name`，编号为u64，均为大端序
+    fn encode(&self) -> Result<Vec<u8>> {
+        let mut body = Vec::new();
+        for (old, new) in &self.replaced {
+            body.push(OP_REPLACE_FILE);
+            encode_name(&mut body, old)?;
+            encode_name(&mut body, new)?;
+        }
+        for name in &self.deleted {
+            body.push(OP_DELETE_FILE);
+            encode_name(&mut body, name)?;
+        }
+        for name in &self.added {
+            body.push(OP_ADD_FILE);
+            encode_name(&mut body, name)?;
+        }
+        if let Some(number) = self.next_file_number {
+            body.push(OP_NEXT_FILE_NUMBER);
+            body.extend_from_slice(&number.to_be_bytes());
+        }
+        if let Some(seq) = self.last_seq {
+            body.push(OP_LAST_SEQ);
+            body.extend_from_slice(&seq.to_be_bytes());
+        }
+        Ok(body)
+    }
+
+    fn decode(mut body: &[u8]) -> Result<Self> {
+        let mut edit = VersionEdit::default();
+        while let Some((&op, rest)) = body.split_first() {
+            body = rest;
+            match op {
+                OP_ADD_FILE => edit.added.push(decode_name(&mut body)?),
+                OP_DELETE_FILE => edit.deleted.push(decode_name(&mut body)?),
+                OP_REPLACE_FILE => {
+                    let old = decode_name(&mut body)?;
+                    edit.replaced.push((old, decode_name(&mut body)?));
+                }
+                OP_NEXT_FILE_NUMBER => edit.next_file_number = Some(decode_u64(&mut body)?),
+                OP_LAST_SEQ => edit.last_seq = Some(decode_u64(&mut body)?),
+                op => return Err(Error::DataError(format!("未知的清单操作: {}", op))),
+            }
+        }
+        Ok(edit)
+    }
+}
+
+fn encode_name(body: &mut Vec<u8>, name: &str) -> Result<()> {
+    let name_len = u16::try_from(name.len())
+        .map_err(|_| Error::DataError(format!("文件名过长: {}", name)))?;
+    body.extend_from_slice(&name_len.to_be_bytes());
+    body.extend_from_slice(name.as_bytes());
+    Ok(())
+}
+
+fn decode_u64(body: &mut &[u8]) -> Result<u64> {
+    if body.len() < 8 {
+        return Err(Error::DataError("清单记录不完整".to_string()));
+    }
+    let value = u64::from_be_bytes(body[..8].try_into().unwrap());
+    *body = &body[8..];
+    Ok(value)
+}
+
+fn decode_name(body: &mut &[u8]) -> Result<String> {
+    let incomplete = || Error::DataError("清单记录不完整".to_string());
+    if body.len() < 2 {
+        return Err(incomplete());
+    }
+    let name_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+    let name = body.get(2..2 + name_len).ok_or_else(incomplete)?;
+    let name = std::str::from_utf8(name)
+        .map_err(|_| Error::DataError("清单中的文件名不是合法的UTF-8".to_string()))?
+        .to_string();
+    *body = &body[2 + name_len..];
+    Ok(name)
+}
+
+/// SSTable清单：记录当前有效的SSTable集合，类似LevelDB的MANIFEST
+///
+/// 清单是`VersionEdit`的追加日志，记录格式与WAL相同（带长度、类型和CRC32C的记录头），
+/// 每次变更写入一条记录并fsync后才算提交。启动时回放清单得到已提交的文件集合（按新旧排序），
+/// 然后把它重写为只含一条快照记录的新清单，避免日志无限增长。
+///
+/// 清单同时负责分配单调递增的文件编号，SSTable命名为`sstable-000001.db`；
+/// 并记录已分配的最大序列号，使所有文件过期删除后重启，新数据的序列号仍大于旧数据。
+pub struct Manifest {
+    dir: PathBuf,
+    file: Mutex<BufWriter<File>>,
+    version: Mutex<Version>,
+    truncated: bool, // 打开时截掉了写了一半的最后一条记录
+}
+
+/// 清单描述的当前状态
+#[derive(Debug, Default)]
+struct Version {
+    files: Vec<String>, // 按新旧排序的有效文件
+    next_file_number: u64,
+    last_seq: u64,
+}
+
+impl Manifest {
+    /// 打开目录下的清单；清单不存在时（如旧版本的数据目录）按文件名顺序接管已有的`*.db`文件
+    pub fn open(dir: &str) -> Result<Self> {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir)?;
+
+        let (mut version, truncated) = match File::open(dir.join(MANIFEST_FILE)) {
+            Ok(mut f) => {
+                let mut buf = Vec::new();
+                f.read_to_end(&mut buf)?;
+                replay(&buf)?
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                let mut names: Vec<String> = fs::read_dir(&dir)?
+                    .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                    .filter(|name| name.ends_with(".db"))
+                    .collect();
+                names.sort();
+                if !names.is_empty() {
+                    info!("清单不存在，接管目录中已有的 {} 个SSTable文件", names.len());
+                }
+                (Version { files: names, next_file_number: 1, last_seq: 0 }, false)
+            }
+            Err(e) => return Err(Error::IoError(e)),
+        };
+
+        // 新分配的编号不能与已有的文件重复（包括没有记录编号的旧清单和旧文件名）
+        let max_used = version.files.iter().filter_map(|name| file_number(name)).max().unwrap_or(0);
+        version.next_file_number = version.next_file_number.max(max_used + 1);
+
+        // 写入快照：先写临时文件再原子替换
+        let tmp_path = dir.join(MANIFEST_TMP_FILE);
+        {
+            let mut tmp = BufWriter::new(File::create(&tmp_path)?);
+            let snapshot = VersionEdit {
+                added: version.files.clone(),
+                next_file_number: Some(version.next_file_number),
+                last_seq: Some(version.last_seq),
+                ..Default::default()
+            };
+            write_frame(&mut tmp, RECORD_EDIT, &snapshot.encode()?)?;
+            tmp.flush()?;
+            tmp.get_ref().sync_all()?;
+        }
+        fs::rename(&tmp_path, dir.join(MANIFEST_FILE))?;
+        sync_dir(&dir)?;
+
+        let file = OpenOptions::new().append(true).open(dir.join(MANIFEST_FILE))?;
+        info!("清单打开: {:?}, {} 个有效SSTable文件, 下一个文件编号 {}, 最大序列号 {}",
+              dir, version.files.len(), version.next_file_number, version.last_seq);
+        Ok(Manifest {
+            dir,
+            file: Mutex::new(BufWriter::new(file)),
+            version: Mutex::new(version),
+            truncated,
+        })
+    }
+
+    /// 当前有效的文件，按新旧排序
+    pub fn live_files(&self) -> Vec<String> {
+        self.version.lock().unwrap().files.clone()
+    }
+
+    /// 清单记录的最大序列号，没有记录时为0
+    pub fn last_seq(&self) -> u64 {
+        self.version.lock().unwrap().last_seq
+    }
+
+    /// 分配一个新的文件编号，返回对应的SSTable路径
+    ///
+    /// 编号随下一次提交的变更写入清单；没有提交就崩溃时，该文件会在重启时作为孤儿文件被删除。
+    pub fn new_file_path(&self) -> PathBuf {
+        let mut version = self.version.lock().unwrap();
+        let number = version.next_file_number;
+        version.next_file_number += 1;
+        self.dir.join(format!("sstable-{:06}.db", number))
+    }
+
+    /// 把一次变更写入清单并fsync，成功返回后变更即已提交
+    pub fn log_and_apply(&self, edit: &VersionEdit) -> Result<()> {
+        let mut file = self.file.lock().unwrap();
+        let mut version = self.version.lock().unwrap();
+        let mut missing = edit.deleted.iter().chain(edit.replaced.iter().map(|(old, _)| old));
+        if let Some(name) = missing.find(|name| !version.files.contains(name)) {
+            return Err(Error::DataError(format!("清单中不存在要删除的文件: {}", name)));
+        }
+
+        let edit = VersionEdit {
+            next_file_number: Some(version.next_file_number),
+            last_seq: Some(version.last_seq.max(edit.last_seq.unwrap_or(0))),
+            ..edit.clone()
+        };
+        write_frame(&mut *file, RECORD_EDIT, &edit.encode()?)?;
+        file.flush()?;
+        file.get_ref().sync_data()?;
+
+        apply(&mut version, &edit);
+        debug!("清单提交变更: 新增 {:?}, 删除 {:?}, 替换 {:?}", edit.added, edit.deleted, edit.replaced);
+        Ok(())
+    }
+
+    /// 删除目录中不在清单里的SSTable文件和临时文件，如刷盘或压缩中途崩溃留下的文件
+    ///
+    /// 打开时截掉过损坏的尾部则不清理：无法确认被截掉的记录里有哪些文件，留到下次正常启动时再清理。
+    pub fn remove_orphans(&self) -> Result<usize> {
+        if self.truncated {
+            warn!("清单 {:?} 打开时截掉了损坏的尾部，本次不清理孤儿文件", self.dir);
+            return Ok(0);
+        }
+        let version = self.version.lock().unwrap();
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            let orphan = match path.extension().and_then(|ext| ext.to_str()) {
+                Some("db") => !version.files.iter().any(|live| live == name),
+                Some("tmp") => true,
+                _ => false,
+            };
+            if orphan {
+                warn!("删除不在清单中的文件: {:?}", path);
+                fs::remove_file(&path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// 按顺序回放清单中的变更，返回回放得到的状态和是否截掉了损坏的尾部
+///
+/// 只有延伸到文件末尾的最后一条记录可以是损坏的，它是崩溃时没有写完、因而没有提交的变更；
+/// 之后还有记录的损坏意味着已提交的变更丢失，返回错误而不是丢弃之后的变更。
+fn replay(buf: &[u8]) -> Result<(Version, bool)> {
+    let mut version = Version::default();
+    let mut pos = 0;
+    while pos < buf.len() {
+        let (record_type, body, size) = match decode_frame(&buf[pos..]) {
+            Ok(frame) => frame,
+            Err(e) if count_frames(&buf[pos..]) == 1 => {
+                warn!("清单最后一条记录在偏移 {} 处不完整，忽略之后的 {} 字节: {}", pos, buf.len() - pos, e);
+                return Ok((version, true));
+            }
+            Err(e) => {
+                return Err(Error::DataError(format!("清单在偏移 {} 处损坏，之后仍有已提交的记录: {}", pos, e)));
+            }
+        };
+        if record_type != RECORD_EDIT {
+            return Err(Error::DataError(format!("未知的清单记录类型: {}", record_type)));
+        }
+        apply(&mut version, &VersionEdit::decode(body)?);
+        pos += size;
+    }
+    Ok((version, false))
+}
+
+fn apply(version: &mut Version, edit: &VersionEdit) {
+    if !edit.replaced.is_empty() {
+        let files = std::mem::take(&mut version.files);
+        for name in files {
+            let mut news = edit.replaced.iter().filter(|(old, _)| *old == name).map(|(_, new)| new.clone()).peekable();
+            if news.peek().is_some() {
+                version.files.extend(news);
+            } else {
+                version.files.push(name);
+            }
+        }
+    }
+    version.files.retain(|name| !edit.deleted.contains(name));
+    version.files.extend(edit.added.iter().cloned());
+    if let Some(number) = edit.next_file_number {
+        version.next_file_number = version.next_file_number.max(number);
+    }
+    if let Some(seq) = edit.last_seq {
+        version.last_seq = version.last_seq.max(seq);
+    }
+}
+
+/// 从`sstable-000001.db`形式的文件名中解析文件编号
+fn file_number(name: &str) -> Option<u64> {
+    name.strip_prefix("sstable-")?.strip_suffix(".db")?.parse().ok()
+}
+
+/// fsync目录，使其中文件的创建、重命名和删除落盘
+pub(crate) fn sync_dir(dir: &Path) -> Result<()> {
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_and_orphans() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_string_lossy().into_owned();
+        for name in ["sstable-1.db", "sstable-2.db"] {
+            fs::write(dir.path().join(name), b"").unwrap();
+        }
+
+        // 没有清单时接管已有文件
+        let manifest = Manifest::open(&path).unwrap();
+        assert_eq!(manifest.live_files(), vec!["sstable-1.db", "sstable-2.db"]);
+        manifest.log_and_apply(&VersionEdit::add("sstable-0.db")).unwrap();
+        manifest.log_and_apply(&VersionEdit::delete(vec!["sstable-1.db".to_string()])).unwrap();
+        assert!(manifest.log_and_apply(&VersionEdit::delete(vec!["sstable-9.db".to_string()])).is_err());
+        drop(manifest);
+
+        // 清单末尾写了一半的变更不生效
+        let mut file = OpenOptions::new().append(true).open(dir.path().join(MANIFEST_FILE)).unwrap();
+        file.write_all(&[1, 2, 3, 4, 0, 0]).unwrap();
+        drop(file);
+
+        let manifest = Manifest::open(&path).unwrap();
+        assert_eq!(manifest.live_files(), vec!["sstable-2.db", "sstable-0.db"]);
+        assert_eq!(manifest.remove_orphans().unwrap(), 0);
+        assert!(dir.path().join("sstable-1.db").exists());
+
+        // 编号从已有文件之后开始分配，替换的文件保持原来的位置
+        let next = manifest.new_file_path();
+        assert_eq!(next, dir.path().join("sstable-000003.db"));
+        let edit = VersionEdit {
+            replaced: vec![("sstable-2.db".to_string(), "sstable-000003.db".to_string())],
+            ..Default::default()
+        };
+        manifest.log_and_apply(&edit).unwrap();
+        drop(manifest);
+        let manifest = Manifest::open(&path).unwrap();
+        assert_eq!(manifest.live_files(), vec!["sstable-000003.db", "sstable-0.db"]);
+        assert_eq!(manifest.new_file_path(), dir.path().join("sstable-000004.db"));
+
+
+        // 不在清单中的SSTable（已删除和被替换的）和临时文件被清理，其它文件保留
+        fs::write(dir.path().join("sstable-000003.db"), b"").unwrap();
+        fs::write(dir.path().join("sstable-000004.db.tmp"), b"").unwrap();
+        assert_eq!(manifest.remove_orphans().unwrap(), 3);
+        assert!(!dir.path().join("sstable-1.db").exists());
+        assert!(!dir.path().join("sstable-2.db").exists());
+        assert!(dir.path().join("sstable-000003.db").exists());
+        assert!(dir.path().join(MANIFEST_FILE).exists());
+
+        // 一个文件被拆分为多个新文件时，新文件依次占据它的位置
+        let edit = VersionEdit {
+            replaced: vec![("sstable-0.db".to_string(), "sstable-000005.db".to_string()),
+                           ("sstable-0.db".to_string(), "sstable-000006.db".to_string())],
+            ..Default::default()
+        };
+        manifest.log_and_apply(&edit).unwrap();
+        manifest.log_and_apply(&VersionEdit::add("sstable-000007.db")).unwrap();
+        drop(manifest);
+        let manifest = Manifest::open(&path).unwrap();
+        assert_eq!(manifest.live_files(),
+                   vec!["sstable-000003.db", "sstable-000005.db", "sstable-000006.db", "sstable-000007.db"]);
+    }
+
+    #[test]
+    fn test_corrupt_middle_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_string_lossy().into_owned();
+        let manifest = Manifest::open(&path).unwrap();
+        for name in ["sstable-000001.db", "sstable-000002.db", "sstable-000003.db"] {
+            fs::write(dir.path().join(name), b"").unwrap();
+            manifest.log_and_apply(&VersionEdit::add(name)).unwrap();
+        }
+        drop(manifest);
+
+        // 翻转第二条新增记录中的一位，之后的记录仍然完好
+        let manifest_path = dir.path().join(MANIFEST_FILE);
+        let mut bytes = fs::read(&manifest_path).unwrap();
+        let mut pos = 0;
+        for _ in 0..2 {
+            pos += decode_frame(&bytes[pos..]).unwrap().2;
+        }
+        bytes[pos + 9 + 1] ^= 0x01; // 跳过9字节的记录头，落在记录体中
+        fs::write(&manifest_path, &bytes).unwrap();
+
+        // 打开失败，已提交的文件都不会被当作孤儿删除
+        assert!(matches!(Manifest::open(&path), Err(Error::DataError(_))));
+        for name in ["sstable-000001.db", "sstable-000002.db", "sstable-000003.db"] {
+            assert!(dir.path().join(name).exists());
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::BTreeMap;
+
+use crate::series::SeriesKey;
+use crate::wal::{Timestamp, Value};
+
+/// 范围删除标记：删除`[start, end]`内的数据，`series`为None时作用于所有序列
+///
+/// 墓碑只屏蔽比它更早写入的数据，之后写入的同一时间戳的数据仍然可见。
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tombstone {
+    pub series: Option<SeriesKey>,
+    pub start: Timestamp,
+    pub end: Timestamp,
+}
+
+impl Tombstone {
+    /// 判断墓碑是否作用于指定序列
+    pub fn applies_to(&self, series: &SeriesKey) -> bool {
+        self.series.as_ref().is_none_or(|s| s == series)
+    }
+
+    /// 判断墓碑是否覆盖指定序列的某个时间戳
+    pub fn covers(&self, series: &SeriesKey, ts: Timestamp) -> bool {
+        ts >= self.start && ts <= self.end && self.applies_to(series)
+    }
+}
+
+/// 内存表：每个序列一条按时间排序的时间线，外加尚未刷盘的删除标记
+///
+/// 序列号决定新旧：序列号大的内存表或SSTable中的数据覆盖序列号小的，刷盘后SSTable沿用内存表的序列号。
+#[derive(Default)]
+pub struct MemTable {
+    series: BTreeMap<SeriesKey, BTreeMap<Timestamp, Value>>,
+    tombstones: Vec<Tombstone>,
+    points: usize,
+    seq: u64,
+}
+
+impl MemTable {
+    pub fn new() -> Self {
+        MemTable::default()
+    }
+
+    /// 创建指定序列号的空内存表
+    pub fn with_seq(seq: u64) -> Self {
+        MemTable { seq, ..MemTable::default() }
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn set_seq(&mut self, seq: u64) {
+        self.seq = seq;
+    }
+
+    /// 写入一个数据点，覆盖同一时间戳的旧值
+    pub fn insert(&mut self, series: &SeriesKey, ts: Timestamp, value: Value) {
+        let points = match self.series.get_mut(series) {
+            Some(points) => points,
+            None => self.series.entry(series.clone()).or_default(),
+        };
+        if points.insert(ts, value).is_none() {
+            self.points += 1;
+        }
+    }
+
+    /// 删除墓碑覆盖的数据点，并记录墓碑用于屏蔽更早的SSTable中的数据
+    pub fn delete(&mut self, tombstone: Tombstone) {
+        for (series, points) in self.series.iter_mut() {
+            if !tombstone.applies_to(series) {
+                continue;
+            }
+            let covered: Vec<Timestamp> = points.range(tombstone.start..=tombstone.end).map(|(&ts, _)| ts).collect();
+            for ts in covered {
+                points.remove(&ts);
+                self.points -= 1;
+            }
+        }
+        self.series.retain(|_, points| !points.is_empty());
+        self.tombstones.push(tombstone);
+    }
+
+    /// 指定序列在区间内的数据点
+    pub fn range(&self, series: &SeriesKey, start: Timestamp, end: Timestamp) -> impl Iterator<Item = (Timestamp, Value)> + '_ {
+        self.series
+            .get(series)
+            .into_iter()
+            .flat_map(move |points| points.range(start..=end).map(|(&ts, &val)| (ts, val)))
+    }
+
+    /// 指定序列在`ts`处的值
+    pub fn get(&self, series: &SeriesKey, ts: Timestamp) -> Option<Value> {
+        self.series.get(series)?.get(&ts).copied()
+    }
+
+    /// 指定序列不晚于`ts`的最后一个数据点
+    pub fn last_before(&self, series: &SeriesKey, ts: Timestamp) -> Option<(Timestamp, Value)> {
+        self.series.get(series)?.range(..=ts).next_back().map(|(&ts, &val)| (ts, val))
+    }
+
+    /// 指定序列不早于`ts`的第一个数据点
+    pub fn first_after(&self, series: &SeriesKey, ts: Timestamp) -> Option<(Timestamp, Value)> {
+        self.series.get(series)?.range(ts..).next().map(|(&ts, &val)| (ts, val))
+    }
+
+    /// 按序列遍历所有数据
+    pub fn iter(&self) -> impl Iterator<Item = (&SeriesKey, &BTreeMap<Timestamp, Value>)> {
+        self.series.iter()
+    }
+
+    pub fn series_keys(&self) -> impl Iterator<Item = &SeriesKey> {
+        self.series.keys()
+    }
+
+    pub fn tombstones(&self) -> &[Tombstone] {
+        &self.tombstones
+    }
+
+    /// 只保留满足条件的墓碑，用于压缩时丢弃已无作用的删除标记
+    pub fn retain_tombstones<F: FnMut(&Tombstone) -> bool>(&mut self, f: F) {
+        self.tombstones.retain(f);
+    }
+
+    /// 数据点总数
+    pub fn len(&self) -> usize {
+        self.points
+    }
+
+    pub fn series_count(&self) -> usize {
+        self.series.len()
+    }
+
+    /// 既没有数据点也没有墓碑
+    pub fn is_empty(&self) -> bool {
+        self.series.is_empty() && self.tombstones.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.series.clear();
+        self.tombstones.clear();
+        self.points = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delete_masks_earlier_writes() {
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+        let mem = SeriesKey::parse("mem,host=a").unwrap();
+        let mut table = MemTable::new();
+        for ts in 1..=10 {
+            table.insert(&cpu, ts, ts as f64);
+            table.insert(&mem, ts, -(ts as f64));
+        }
+        assert_eq!(table.len(), 20);
+
+        table.delete(Tombstone { series: Some(cpu.clone()), start: 3, end: 5 });
+        assert_eq!(table.len(), 17);
+        assert_eq!(table.range(&cpu, 1, 6).map(|(ts, _)| ts).collect::<Vec<_>>(), vec![1, 2, 6]);
+        assert_eq!(table.range(&mem, 3, 5).count(), 3);
+
+        // 删除之后再写入同一时间戳的数据仍然可见
+        table.insert(&cpu, 4, 40.0);
+        assert_eq!(table.range(&cpu, 4, 4).collect::<Vec<_>>(), vec![(4, 40.0)]);
+
+        // 作用于所有序列的删除，删空的序列不再保留
+        table.delete(Tombstone { series: None, start: 0, end: 100 });
+        assert_eq!(table.len(), 0);
+        assert_eq!(table.series_count(), 0);
+        assert_eq!(table.tombstones().len(), 2);
+        assert!(!table.is_empty());
+    }
+}
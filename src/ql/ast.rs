@@ -0,0 +1,92 @@
+use crate::aggregate::Fill;
+use crate::index::MatchOp;
+use crate::iter::Order;
+use crate::wal::Timestamp;
+
+/// 解析后的SELECT语句，各部分记录在语句中的位置，便于规划时报告错误
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectStatement {
+    pub fields: Vec<Field>,
+    /// FROM后的指标名
+    pub from: String,
+    /// WHERE中用AND连接的条件
+    pub conditions: Vec<Condition>,
+    pub group_by: Option<GroupBy>,
+    /// FILL子句及其位置
+    pub fill: Option<(Fill, usize)>,
+    /// ORDER BY time ASC|DESC，省略时为升序
+    pub order: Order,
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+}
+
+/// SELECT后的一列
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    /// 原始值：`value`或`*`
+    Value { position: usize },
+    /// 聚合函数，如`mean(value)`、`percentile(value, 99)`
+    Call {
+        function: String,
+        argument: Option<f64>,
+        position: usize,
+    },
+}
+
+impl Field {
+    pub fn position(&self) -> usize {
+        match *self {
+            Field::Value { position } | Field::Call { position, .. } => position,
+        }
+    }
+}
+
+/// WHERE中的一个条件
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    /// 标签条件，如`host = 'a'`、`host =~ /web-.*/`
+    Tag {
+        key: String,
+        op: MatchOp,
+        value: String,
+        position: usize,
+    },
+    /// 时间条件，如`time > now() - 1h`
+    Time {
+        op: Comparison,
+        value: TimeExpr,
+        position: usize,
+    },
+}
+
+/// 时间条件的比较方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+}
+
+/// 时间表达式：`now()`或unix秒，加上若干时长
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeExpr {
+    pub base: TimeBase,
+    /// 相对基准的偏移秒数
+    pub offset: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeBase {
+    Now,
+    Literal(Timestamp),
+}
+
+/// GROUP BY time(width[, offset])
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GroupBy {
+    pub width: u64,
+    pub offset: u64,
+    pub position: usize,
+}
@@ -0,0 +1,77 @@
+use log::debug;
+
+use crate::db::{QueryOptions, SimpleTSDB};
+use crate::error::Result;
+use crate::iter::Order;
+use crate::ql::planner::{Projection, QueryPlan};
+use crate::series::SeriesKey;
+use crate::wal::{Timestamp, Value};
+
+/// 查询结果中的一行：时间和各列的值，空值为None
+#[derive(Debug, Clone, PartialEq)]
+pub struct Row {
+    pub timestamp: Timestamp,
+    pub values: Vec<Option<Value>>,
+}
+
+/// 一个序列的查询结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeriesRows {
+    pub series: SeriesKey,
+    pub rows: Vec<Row>,
+}
+
+/// 查询结果，没有数据的序列不返回
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryResult {
+    /// 除时间以外各列的名称
+    pub columns: Vec<String>,
+    pub series: Vec<SeriesRows>,
+}
+
+/// 对选出的每个序列执行查询计划
+///
+/// 原始数据的排序、OFFSET和LIMIT下推到存储层的迭代器；聚合结果在每个序列内排序和截取。
+pub fn execute(db: &SimpleTSDB, plan: &QueryPlan) -> Result<QueryResult> {
+    let mut result = QueryResult { columns: plan.columns(), series: Vec::new() };
+    for series in db.select_series(&plan.matchers)? {
+        let rows = match &plan.projection {
+            Projection::Raw => {
+                let options = QueryOptions { order: plan.order, offset: plan.offset, limit: plan.limit };
+                let points = db.query_with(&series, plan.start, plan.end, options)?;
+                points.into_iter().map(|(timestamp, value)| Row { timestamp, values: vec![Some(value)] }).collect()
+            }
+            Projection::Aggregate(aggregations) => {
+                let values = db.aggregate_many(&series, plan.start, plan.end, aggregations)?;
+                let rows = if values.iter().any(Option::is_some) {
+                    vec![Row { timestamp: plan.start, values }]
+                } else {
+                    Vec::new()
+                };
+                page(rows, plan)
+            }
+            Projection::Window { aggregations, window, fill } => {
+                // 所有聚合函数在同一次读取中按桶累加，每个桶一行
+                let rows = db
+                    .aggregate_window_many(&series, plan.start, plan.end, aggregations, *window, *fill)?
+                    .into_iter()
+                    .map(|(timestamp, values)| Row { timestamp, values })
+                    .collect();
+                page(rows, plan)
+            }
+        };
+        if !rows.is_empty() {
+            result.series.push(SeriesRows { series, rows });
+        }
+    }
+    debug!("查询计划 {:?} 返回 {} 个序列", plan.projection, result.series.len());
+    Ok(result)
+}
+
+/// 按计划的顺序排列升序的行，再跳过OFFSET行、最多保留LIMIT行
+fn page(mut rows: Vec<Row>, plan: &QueryPlan) -> Vec<Row> {
+    if plan.order == Order::Descending {
+        rows.reverse();
+    }
+    rows.into_iter().skip(plan.offset).take(plan.limit.unwrap_or(usize::MAX)).collect()
+}
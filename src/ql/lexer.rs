@@ -0,0 +1,197 @@
+use crate::error::Result;
+use crate::ql::error;
+
+/// 词法单元的种类
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    /// 标识符或关键字，双引号括起的标识符可以含任意字符
+    Ident(String),
+    /// 单引号括起的字符串
+    Str(String),
+    /// 斜杠括起的正则表达式
+    Regex(String),
+    Number(f64),
+    /// 带单位的时长，单位为s、m、h、d、w，换算为秒
+    Duration(u64),
+    LParen,
+    RParen,
+    Comma,
+    Star,
+    Plus,
+    Minus,
+    Eq,
+    NotEq,
+    RegexMatch,
+    RegexNotMatch,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    Eof,
+}
+
+/// 词法单元及其在语句中的字符偏移
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub position: usize,
+}
+
+/// 把查询语句切分为词法单元，最后一个总是`Eof`
+pub fn tokenize(input: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let kind = match c {
+            '(' => TokenKind::LParen,
+            ')' => TokenKind::RParen,
+            ',' => TokenKind::Comma,
+            '*' => TokenKind::Star,
+            '+' => TokenKind::Plus,
+            '-' => TokenKind::Minus,
+            '=' if chars.get(i + 1) == Some(&'~') => TokenKind::RegexMatch,
+            '=' => TokenKind::Eq,
+            '!' if chars.get(i + 1) == Some(&'=') => TokenKind::NotEq,
+            '!' if chars.get(i + 1) == Some(&'~') => TokenKind::RegexNotMatch,
+            '<' if chars.get(i + 1) == Some(&'>') => TokenKind::NotEq,
+            '<' if chars.get(i + 1) == Some(&'=') => TokenKind::LtEq,
+            '<' => TokenKind::Lt,
+            '>' if chars.get(i + 1) == Some(&'=') => TokenKind::GtEq,
+            '>' => TokenKind::Gt,
+            '\'' | '"' | '/' => {
+                let (text, end) = quoted(&chars, i)?;
+                i = end;
+                tokens.push(Token {
+                    kind: match c {
+                        '\'' => TokenKind::Str(text),
+                        '"' => TokenKind::Ident(text),
+                        _ => TokenKind::Regex(text),
+                    },
+                    position: start,
+                });
+                continue;
+            }
+            c if c.is_ascii_digit() => {
+                let (kind, end) = number(&chars, i)?;
+                i = end;
+                tokens.push(Token { kind, position: start });
+                continue;
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                while i < chars.len() && (chars[i].is_alphanumeric() || matches!(chars[i], '_' | '.' | ':')) {
+                    i += 1;
+                }
+                tokens.push(Token { kind: TokenKind::Ident(chars[start..i].iter().collect()), position: start });
+                continue;
+            }
+            c => return Err(error(start, format!("无法识别的字符 '{}'", c))),
+        };
+        // 两个字符的操作符
+        i += match kind {
+            TokenKind::RegexMatch | TokenKind::RegexNotMatch | TokenKind::NotEq | TokenKind::LtEq | TokenKind::GtEq => 2,
+            _ => 1,
+        };
+        tokens.push(Token { kind, position: start });
+    }
+    tokens.push(Token { kind: TokenKind::Eof, position: chars.len() });
+    Ok(tokens)
+}
+
+/// 读取以`chars[start]`为界符的内容，反斜杠转义界符和反斜杠本身；正则中的其他转义原样保留
+fn quoted(chars: &[char], start: usize) -> Result<(String, usize)> {
+    let delimiter = chars[start];
+    let mut text = String::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if matches!(chars.get(i + 1), Some(&c) if c == delimiter || c == '\\') => {
+                if delimiter == '/' && chars[i + 1] == '\\' {
+                    text.push('\\');
+                }
+                text.push(chars[i + 1]);
+                i += 2;
+            }
+            c if c == delimiter => return Ok((text, i + 1)),
+            c => {
+                text.push(c);
+                i += 1;
+            }
+        }
+    }
+    Err(error(start, format!("缺少结束的 {}", delimiter)))
+}
+
+/// 读取数字，紧跟单位时为时长
+fn number(chars: &[char], start: usize) -> Result<(TokenKind, usize)> {
+    let mut i = start;
+    while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+        i += 1;
+    }
+    let digits: String = chars[start..i].iter().collect();
+    let unit_start = i;
+    while i < chars.len() && chars[i].is_alphabetic() {
+        i += 1;
+    }
+    let unit: String = chars[unit_start..i].iter().collect();
+    if unit.is_empty() {
+        let value = digits.parse::<f64>().map_err(|_| error(start, format!("数字 '{}' 无效", digits)))?;
+        return Ok((TokenKind::Number(value), i));
+    }
+
+    let seconds = match unit.as_str() {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 7 * 86400,
+        _ => return Err(error(unit_start, format!("未知的时间单位 '{}'，应为s、m、h、d或w", unit))),
+    };
+    let count = digits.parse::<u64>().map_err(|_| error(start, format!("时长 '{}{}' 必须是整数", digits, unit)))?;
+    let duration = count
+        .checked_mul(seconds)
+        .ok_or_else(|| error(start, format!("时长 '{}{}' 过大", digits, unit)))?;
+    Ok((TokenKind::Duration(duration), i))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    #[test]
+    fn test_tokenize() {
+        let kinds: Vec<TokenKind> = tokenize(r#"host!~/web\/.*/ AND time>=now()-1h "my tag"='it\'s'"#)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.kind)
+            .collect();
+        assert_eq!(kinds, vec![
+            TokenKind::Ident("host".to_string()),
+            TokenKind::RegexNotMatch,
+            TokenKind::Regex("web/.*".to_string()),
+            TokenKind::Ident("AND".to_string()),
+            TokenKind::Ident("time".to_string()),
+            TokenKind::GtEq,
+            TokenKind::Ident("now".to_string()),
+            TokenKind::LParen,
+            TokenKind::RParen,
+            TokenKind::Minus,
+            TokenKind::Duration(3600),
+            TokenKind::Ident("my tag".to_string()),
+            TokenKind::Eq,
+            TokenKind::Str("it's".to_string()),
+            TokenKind::Eof,
+        ]);
+
+        assert!(matches!(tokenize("time > 5x"), Err(Error::QueryError { position: 8, .. })));
+        assert!(matches!(tokenize("host = 'a"), Err(Error::QueryError { position: 7, .. })));
+    }
+}
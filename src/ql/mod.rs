@@ -0,0 +1,23 @@
+pub mod ast;
+pub mod executor;
+pub mod lexer;
+pub mod parser;
+pub mod planner;
+
+use crate::db::SimpleTSDB;
+use crate::error::{Error, Result};
+use crate::wal::Timestamp;
+
+pub use executor::{QueryResult, Row, SeriesRows};
+
+/// 构造带位置的查询错误
+pub(crate) fn error(position: usize, message: impl Into<String>) -> Error {
+    Error::QueryError { position, message: message.into() }
+}
+
+/// 解析、规划并执行一条查询语句，`now`为`now()`的取值
+pub fn query(db: &SimpleTSDB, text: &str, now: Timestamp) -> Result<QueryResult> {
+    let stmt = parser::parse(text)?;
+    let plan = planner::plan(&stmt, now)?;
+    executor::execute(db, &plan)
+}
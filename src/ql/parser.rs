@@ -0,0 +1,342 @@
+use crate::aggregate::Fill;
+use crate::error::{Error, Result};
+use crate::index::MatchOp;
+use crate::iter::Order;
+use crate::ql::ast::{Comparison, Condition, Field, GroupBy, SelectStatement, TimeBase, TimeExpr};
+use crate::ql::error;
+use crate::ql::lexer::{Token, TokenKind, tokenize};
+
+/// 解析一条SELECT语句，关键字不区分大小写
+///
+/// ```text
+/// SELECT <field> [, <field>...] FROM <metric>
+///     [WHERE <condition> [AND <condition>...]]
+///     [GROUP BY time(<width>[, <offset>])] [FILL(<mode>)]
+///     [ORDER BY time ASC|DESC] [LIMIT <n>] [OFFSET <n>]
+/// ```
+pub fn parse(input: &str) -> Result<SelectStatement> {
+    let mut parser = Parser { tokens: tokenize(input)?, pos: 0 };
+    parser.select()
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos]
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].clone();
+        if token.kind != TokenKind::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(&self.peek().kind, TokenKind::Ident(s) if s.eq_ignore_ascii_case(keyword))
+    }
+
+    fn accept_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.is_keyword(keyword);
+        if found {
+            self.next();
+        }
+        found
+    }
+
+    /// 读取关键字，返回它的位置
+    fn expect_keyword(&mut self, keyword: &str) -> Result<usize> {
+        if !self.is_keyword(keyword) {
+            return Err(self.unexpected(keyword));
+        }
+        Ok(self.next().position)
+    }
+
+    fn accept(&mut self, kind: &TokenKind) -> bool {
+        let found = &self.peek().kind == kind;
+        if found {
+            self.next();
+        }
+        found
+    }
+
+    fn expect(&mut self, kind: TokenKind, expected: &str) -> Result<()> {
+        if !self.accept(&kind) {
+            return Err(self.unexpected(expected));
+        }
+        Ok(())
+    }
+
+    /// 当前词法单元不符合预期
+    fn unexpected(&self, expected: &str) -> Error {
+        let token = self.peek();
+        let actual = match &token.kind {
+            TokenKind::Ident(s) => format!("'{}'", s),
+            TokenKind::Str(s) => format!("字符串 '{}'", s),
+            TokenKind::Regex(s) => format!("正则表达式 /{}/", s),
+            TokenKind::Number(n) => format!("数字 {}", n),
+            TokenKind::Duration(d) => format!("时长 {}s", d),
+            TokenKind::Eof => "语句结尾".to_string(),
+            kind => format!("{:?}", kind),
+        };
+        error(token.position, format!("应为{}，实际为{}", expected, actual))
+    }
+
+    fn ident(&mut self, expected: &str) -> Result<(String, usize)> {
+        match &self.peek().kind {
+            TokenKind::Ident(s) => {
+                let s = s.clone();
+                Ok((s, self.next().position))
+            }
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+
+    fn number(&mut self, expected: &str) -> Result<f64> {
+        match self.peek().kind {
+            TokenKind::Number(n) => {
+                self.next();
+                Ok(n)
+            }
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+
+    fn integer(&mut self, expected: &str) -> Result<u64> {
+        match self.peek().kind {
+            TokenKind::Number(n) if n.fract() == 0.0 && (0.0..u64::MAX as f64).contains(&n) => {
+                self.next();
+                Ok(n as u64)
+            }
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+
+    fn duration(&mut self, expected: &str) -> Result<u64> {
+        match self.peek().kind {
+            TokenKind::Duration(d) => {
+                self.next();
+                Ok(d)
+            }
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+
+    fn select(&mut self) -> Result<SelectStatement> {
+        self.expect_keyword("SELECT")?;
+        let mut fields = vec![self.field()?];
+        while self.accept(&TokenKind::Comma) {
+            fields.push(self.field()?);
+        }
+
+        self.expect_keyword("FROM")?;
+        let (from, _) = self.ident("指标名")?;
+
+        let mut conditions = Vec::new();
+        if self.accept_keyword("WHERE") {
+            conditions.push(self.condition()?);
+            while self.accept_keyword("AND") {
+                conditions.push(self.condition()?);
+            }
+            if self.is_keyword("OR") {
+                return Err(error(self.peek().position, "不支持OR，条件之间只能用AND连接"));
+            }
+        }
+
+        let mut group_by = None;
+        if self.is_keyword("GROUP") {
+            let position = self.next().position;
+            self.expect_keyword("BY")?;
+            self.expect_keyword("time")?;
+            self.expect(TokenKind::LParen, "'('")?;
+            let width = self.duration("桶宽，如1m")?;
+            let offset = if self.accept(&TokenKind::Comma) { self.duration("偏移，如30s")? } else { 0 };
+            self.expect(TokenKind::RParen, "')'")?;
+            group_by = Some(GroupBy { width, offset, position });
+        }
+
+        let mut fill = None;
+        if self.is_keyword("FILL") {
+            let position = self.next().position;
+            self.expect(TokenKind::LParen, "'('")?;
+            let mode = if self.accept(&TokenKind::Minus) {
+                Fill::Constant(-self.number("数值")?)
+            } else if let TokenKind::Number(n) = self.peek().kind {
+                self.next();
+                Fill::Constant(n)
+            } else {
+                let (name, name_position) = self.ident("填充方式")?;
+                match name.to_lowercase().as_str() {
+                    "none" => Fill::None,
+                    "null" => Fill::Null,
+                    "previous" => Fill::Previous,
+                    "linear" => Fill::Linear,
+                    _ => return Err(error(name_position, format!("未知的填充方式: {}", name))),
+                }
+            };
+            self.expect(TokenKind::RParen, "')'")?;
+            fill = Some((mode, position));
+        }
+
+        let mut order = Order::Ascending;
+        if self.accept_keyword("ORDER") {
+            self.expect_keyword("BY")?;
+            self.expect_keyword("time")?;
+            if self.accept_keyword("DESC") {
+                order = Order::Descending;
+            } else {
+                self.accept_keyword("ASC");
+            }
+        }
+
+        let limit = if self.accept_keyword("LIMIT") { Some(self.integer("非负整数")? as usize) } else { None };
+        let offset = if self.accept_keyword("OFFSET") { Some(self.integer("非负整数")? as usize) } else { None };
+
+        if self.peek().kind != TokenKind::Eof {
+            return Err(self.unexpected("语句结尾"));
+        }
+        Ok(SelectStatement { fields, from, conditions, group_by, fill, order, limit, offset })
+    }
+
+    fn field(&mut self) -> Result<Field> {
+        let position = self.peek().position;
+        if self.accept(&TokenKind::Star) {
+            return Ok(Field::Value { position });
+        }
+        let (name, _) = self.ident("value、*或聚合函数")?;
+        if !self.accept(&TokenKind::LParen) {
+            if name.eq_ignore_ascii_case("value") {
+                return Ok(Field::Value { position });
+            }
+            return Err(error(position, format!("未知的列 '{}'，只能选择value", name)));
+        }
+
+        let (argument, argument_position) = self.ident("value")?;
+        if !argument.eq_ignore_ascii_case("value") {
+            return Err(error(argument_position, format!("聚合函数的参数应为value，实际为'{}'", argument)));
+        }
+        let argument = if self.accept(&TokenKind::Comma) { Some(self.number("数值参数")?) } else { None };
+        self.expect(TokenKind::RParen, "')'")?;
+        Ok(Field::Call { function: name, argument, position })
+    }
+
+    fn condition(&mut self) -> Result<Condition> {
+        let (key, position) = self.ident("条件")?;
+        if key.eq_ignore_ascii_case("time") {
+            let op = match self.peek().kind {
+                TokenKind::Eq => Comparison::Eq,
+                TokenKind::Lt => Comparison::Lt,
+                TokenKind::LtEq => Comparison::LtEq,
+                TokenKind::Gt => Comparison::Gt,
+                TokenKind::GtEq => Comparison::GtEq,
+                _ => return Err(self.unexpected("时间比较操作符=、<、<=、>或>=")),
+            };
+            self.next();
+            let value = self.time_expr()?;
+            return Ok(Condition::Time { op, value, position });
+        }
+        if key.eq_ignore_ascii_case("value") {
+            return Err(error(position, "不支持按值过滤，WHERE中只能有标签条件和时间条件"));
+        }
+
+        let op = match self.peek().kind {
+            TokenKind::Eq => MatchOp::Equal,
+            TokenKind::NotEq => MatchOp::NotEqual,
+            TokenKind::RegexMatch => MatchOp::Regex,
+            TokenKind::RegexNotMatch => MatchOp::NotRegex,
+            _ => return Err(self.unexpected("标签匹配操作符=、!=、=~或!~")),
+        };
+        self.next();
+        let value = match (op, &self.peek().kind) {
+            (MatchOp::Equal | MatchOp::NotEqual, TokenKind::Str(s)) => s.clone(),
+            (MatchOp::Regex | MatchOp::NotRegex, TokenKind::Regex(s)) => s.clone(),
+            (MatchOp::Equal | MatchOp::NotEqual, _) => return Err(self.unexpected("单引号括起的标签值")),
+            _ => return Err(self.unexpected("斜杠括起的正则表达式")),
+        };
+        self.next();
+        Ok(Condition::Tag { key, op, value, position })
+    }
+
+    fn time_expr(&mut self) -> Result<TimeExpr> {
+        let base = match self.peek().kind {
+            TokenKind::Ident(ref s) if s.eq_ignore_ascii_case("now") => {
+                self.next();
+                self.expect(TokenKind::LParen, "'('")?;
+                self.expect(TokenKind::RParen, "')'")?;
+                TimeBase::Now
+            }
+            _ => TimeBase::Literal(self.integer("now()或unix秒数")?),
+        };
+
+        let mut offset: i64 = 0;
+        loop {
+            let sign = if self.accept(&TokenKind::Plus) {
+                1
+            } else if self.accept(&TokenKind::Minus) {
+                -1
+            } else {
+                break;
+            };
+            let position = self.peek().position;
+            let duration = self.duration("时长，如1h")?;
+            offset = i64::try_from(duration)
+                .ok()
+                .and_then(|d| offset.checked_add(sign * d))
+                .ok_or_else(|| error(position, "时间偏移过大"))?;
+        }
+        Ok(TimeExpr { base, offset })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_position(input: &str) -> usize {
+        match parse(input) {
+            Err(Error::QueryError { position, .. }) => position,
+            other => panic!("应当解析失败: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_select() {
+        let stmt = parse(
+            "select mean(value), percentile(value, 99) FROM cpu WHERE host='a' AND region =~ /us-.*/ \
+             AND time > now()-1h GROUP BY time(1m, 30s) FILL(-1) ORDER BY time DESC LIMIT 10 OFFSET 5",
+        )
+        .unwrap();
+        assert_eq!(stmt.fields, vec![
+            Field::Call { function: "mean".to_string(), argument: None, position: 7 },
+            Field::Call { function: "percentile".to_string(), argument: Some(99.0), position: 20 },
+        ]);
+        assert_eq!(stmt.from, "cpu");
+        assert_eq!(stmt.conditions, vec![
+            Condition::Tag { key: "host".to_string(), op: MatchOp::Equal, value: "a".to_string(), position: 57 },
+            Condition::Tag { key: "region".to_string(), op: MatchOp::Regex, value: "us-.*".to_string(), position: 70 },
+            Condition::Time {
+                op: Comparison::Gt,
+                value: TimeExpr { base: TimeBase::Now, offset: -3600 },
+                position: 92,
+            },
+        ]);
+        assert_eq!(stmt.group_by, Some(GroupBy { width: 60, offset: 30, position: 108 }));
+        assert_eq!(stmt.fill.map(|(fill, _)| fill), Some(Fill::Constant(-1.0)));
+        assert_eq!((stmt.order, stmt.limit, stmt.offset), (Order::Descending, Some(10), Some(5)));
+
+        let stmt = parse("SELECT * FROM cpu").unwrap();
+        assert_eq!(stmt.fields, vec![Field::Value { position: 7 }]);
+        assert!(stmt.conditions.is_empty());
+
+        assert_eq!(error_position("SELECT value cpu"), 13);
+        assert_eq!(error_position("SELECT value FROM cpu WHERE host = a"), 35);
+        assert_eq!(error_position("SELECT value FROM cpu WHERE host = 'a' OR host = 'b'"), 39);
+        assert_eq!(error_position("SELECT max(value) FROM cpu GROUP BY time(1m) LIMIT"), 50);
+        assert_eq!(error_position("SELECT max(val) FROM cpu"), 11);
+    }
+}
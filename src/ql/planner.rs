@@ -0,0 +1,194 @@
+use crate::aggregate::{Aggregation, Fill, Window};
+use crate::error::Result;
+use crate::index::{METRIC_LABEL, MatchOp, Matcher};
+use crate::iter::Order;
+use crate::ql::ast::{Comparison, Condition, Field, SelectStatement, TimeBase};
+use crate::ql::error;
+use crate::wal::Timestamp;
+
+/// 逻辑查询计划：选哪些序列、读哪个时间区间、输出什么
+#[derive(Debug, Clone)]
+pub struct QueryPlan {
+    pub matchers: Vec<Matcher>,
+    /// 时间区间`[start, end]`，条件互相矛盾时`start > end`
+    pub start: Timestamp,
+    pub end: Timestamp,
+    pub projection: Projection,
+    pub order: Order,
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+/// 查询输出的内容
+#[derive(Debug, Clone, PartialEq)]
+pub enum Projection {
+    /// 原始数据点
+    Raw,
+    /// 整个区间聚合为一行，时间为区间起点
+    Aggregate(Vec<Aggregation>),
+    /// 按时间分桶聚合，每个桶一行
+    Window {
+        aggregations: Vec<Aggregation>,
+        window: Window,
+        fill: Fill,
+    },
+}
+
+impl QueryPlan {
+    /// 除时间以外各列的名称
+    pub fn columns(&self) -> Vec<String> {
+        match &self.projection {
+            Projection::Raw => vec!["value".to_string()],
+            Projection::Aggregate(aggregations) | Projection::Window { aggregations, .. } => {
+                aggregations.iter().map(|agg| agg.to_string()).collect()
+            }
+        }
+    }
+}
+
+/// 检查语句的语义并生成查询计划，`now`为`now()`的取值
+pub fn plan(stmt: &SelectStatement, now: Timestamp) -> Result<QueryPlan> {
+    // 列：全部是原始值，或全部是聚合函数
+    let mut aggregations = Vec::new();
+    let mut raw = false;
+    for field in &stmt.fields {
+        match field {
+            Field::Value { position } => {
+                if raw || !aggregations.is_empty() {
+                    return Err(error(*position, "原始值只能单独选择一列，不能与聚合函数同时选择"));
+                }
+                raw = true;
+            }
+            Field::Call { function, argument, position } => {
+                if raw {
+                    return Err(error(*position, "聚合函数不能与原始值同时选择"));
+                }
+                aggregations.push(aggregation(function, *argument, *position)?);
+            }
+        }
+    }
+
+    let mut matchers = vec![Matcher::new(METRIC_LABEL, MatchOp::Equal, &stmt.from)?];
+    let (mut start, mut end) = (0i128, Timestamp::MAX as i128);
+    for condition in &stmt.conditions {
+        match condition {
+            Condition::Tag { key, op, value, position } => {
+                matchers.push(Matcher::new(key, *op, value).map_err(|e| error(*position, e.to_string()))?);
+            }
+            Condition::Time { op, value, .. } => {
+                let base = match value.base {
+                    TimeBase::Now => now,
+                    TimeBase::Literal(ts) => ts,
+                };
+                let ts = base as i128 + value.offset as i128;
+                match op {
+                    Comparison::Eq => (start, end) = (start.max(ts), end.min(ts)),
+                    Comparison::Gt => start = start.max(ts + 1),
+                    Comparison::GtEq => start = start.max(ts),
+                    Comparison::Lt => end = end.min(ts - 1),
+                    Comparison::LtEq => end = end.min(ts),
+                }
+            }
+        }
+    }
+    // 分组查询没有给出上界时只到now()，否则补齐空桶时会一直补到时间戳的上限
+    if stmt.group_by.is_some() && end == Timestamp::MAX as i128 {
+        end = now as i128;
+    }
+    let (start, end) = if start <= end && end >= 0 && start <= Timestamp::MAX as i128 {
+        (start.max(0) as Timestamp, end as Timestamp)
+    } else {
+        (1, 0)
+    };
+
+    let projection = match (stmt.group_by, aggregations.is_empty()) {
+        (Some(group_by), true) => return Err(error(group_by.position, "GROUP BY time需要聚合函数")),
+        (Some(group_by), false) => {
+            let window = Window::new(group_by.width, group_by.offset).map_err(|e| error(group_by.position, e.to_string()))?;
+            let fill = stmt.fill.map_or(Fill::None, |(fill, _)| fill);
+            Projection::Window { aggregations, window, fill }
+        }
+        (None, true) => Projection::Raw,
+        (None, false) => Projection::Aggregate(aggregations),
+    };
+    if let (Some((_, position)), false) = (stmt.fill, matches!(projection, Projection::Window { .. })) {
+        return Err(error(position, "FILL只能与GROUP BY time一起使用"));
+    }
+
+    Ok(QueryPlan {
+        matchers,
+        start,
+        end,
+        projection,
+        order: stmt.order,
+        offset: stmt.offset.unwrap_or(0),
+        limit: stmt.limit,
+    })
+}
+
+/// 解析聚合函数：`percentile(value, 99)`、`quantile(value, 0.99)`和`quantile_exact(value, 0.99)`带参数，
+/// 其余函数名按`Aggregation::parse`解析
+fn aggregation(function: &str, argument: Option<f64>, position: usize) -> Result<Aggregation> {
+    let name = function.to_lowercase();
+    let agg = match (name.as_str(), argument) {
+        ("percentile", Some(p)) => Aggregation::Quantile(p / 100.0),
+        ("quantile", Some(q)) => Aggregation::Quantile(q),
+        ("quantile_exact", Some(q)) => Aggregation::ExactQuantile(q),
+        ("percentile" | "quantile" | "quantile_exact", None) => {
+            return Err(error(position, format!("{}需要第二个参数", function)));
+        }
+        (_, Some(_)) => return Err(error(position, format!("{}只接受一个参数", function))),
+        (_, None) => {
+            return Aggregation::parse(&name).map_err(|_| error(position, format!("未知的聚合函数: {}", function)));
+        }
+    };
+    match agg {
+        Aggregation::Quantile(q) | Aggregation::ExactQuantile(q) if (0.0..=1.0).contains(&q) => Ok(agg),
+        _ => Err(error(position, format!("{}的参数超出范围", function))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::ql::parser::parse;
+
+    fn plan_at(input: &str, now: Timestamp) -> Result<QueryPlan> {
+        plan(&parse(input).unwrap(), now)
+    }
+
+    #[test]
+    fn test_plan() {
+        let p = plan_at("SELECT mean(value), p99(value) FROM cpu WHERE time > now() - 1h AND time <= 9000 \
+                         GROUP BY time(1m) FILL(null)", 10000).unwrap();
+        assert_eq!((p.start, p.end), (6401, 9000));
+        assert_eq!(p.columns(), vec!["avg", "quantile(0.99)"]);
+        assert_eq!(p.projection, Projection::Window {
+            aggregations: vec![Aggregation::Avg, Aggregation::Quantile(0.99)],
+            window: Window::new(60, 0).unwrap(),
+            fill: Fill::Null,
+        });
+
+        let p = plan_at("SELECT percentile(value, 95) FROM cpu WHERE host = 'a' AND time >= 100", 0).unwrap();
+        assert_eq!((p.start, p.end), (100, Timestamp::MAX));
+        assert_eq!(p.projection, Projection::Aggregate(vec![Aggregation::Quantile(0.95)]));
+        assert_eq!(p.matchers.len(), 2);
+
+        let p = plan_at("SELECT count(value) FROM cpu WHERE time >= 100 GROUP BY time(10s)", 500).unwrap();
+        assert_eq!((p.start, p.end), (100, 500));
+
+        let p = plan_at("SELECT value FROM cpu WHERE time < 0", 0).unwrap();
+        assert!(p.start > p.end);
+
+        let position = |input| match plan_at(input, 0) {
+            Err(Error::QueryError { position, .. }) => position,
+            other => panic!("应当规划失败: {:?}", other),
+        };
+        assert_eq!(position("SELECT value, max(value) FROM cpu"), 14);
+        assert_eq!(position("SELECT value FROM cpu GROUP BY time(1m)"), 22);
+        assert_eq!(position("SELECT max(value) FROM cpu FILL(0)"), 27);
+        assert_eq!(position("SELECT median(value) FROM cpu"), 7);
+        assert_eq!(position("SELECT max(value) FROM cpu WHERE host =~ /(/"), 33);
+    }
+}
@@ -0,0 +1,37 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::wal::Timestamp;
+
+/// 数据保留策略：时间戳早于 `当前时间 - duration` 的数据视为过期
+///
+/// 时间戳按Unix秒计算，未配置保留时长时数据永不过期。
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RetentionPolicy {
+    duration: Option<Duration>,
+}
+
+impl RetentionPolicy {
+    pub fn new(duration: Option<Duration>) -> Self {
+        RetentionPolicy { duration }
+    }
+
+    pub fn duration(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    /// 当前的过期分界点，小于该时间戳的数据已过期
+    pub fn cutoff(&self) -> Timestamp {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        self.cutoff_at(now)
+    }
+
+    /// 以指定时间为当前时间计算过期分界点
+    pub fn cutoff_at(&self, now: Timestamp) -> Timestamp {
+        match self.duration {
+            Some(d) => now.saturating_sub(d.as_secs()),
+            None => 0,
+        }
+    }
+}
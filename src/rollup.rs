@@ -0,0 +1,418 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::{error, info};
+
+use crate::aggregate::Accumulator;
+use crate::compaction::{CompactionStrategy, Compactor};
+use crate::error::{Error, Result};
+use crate::iter::QueryIter;
+use crate::manifest::{Manifest, VersionEdit, sync_dir};
+use crate::memtable::{MemTable, Tombstone};
+use crate::retention::RetentionPolicy;
+use crate::series::SeriesKey;
+use crate::sstable::{BlockStats, SSTable};
+use crate::wal::{Timestamp, Value};
+
+/// 汇总表中区分各统计量的标签
+const STAT_TAG: &str = "__rollup__";
+/// 每个桶保存的统计量
+const STATS: [&str; 4] = ["count", "sum", "min", "max"];
+/// 记录已汇总到的原始SSTable序列号
+const STATE_FILE: &str = "ROLLUP";
+const STATE_TMP_FILE: &str = "ROLLUP.tmp";
+
+/// 降采样规则：把原始数据按`resolution`分桶，每个桶保存count、sum、min、max
+#[derive(Debug, Clone)]
+pub struct RollupRule {
+    pub resolution: Duration,
+    /// 汇总数据的保留时长，None表示永久保留
+    pub retention: Option<Duration>,
+}
+
+/// 一种分辨率的汇总表，和原始数据一样由清单管理的SSTable组成，放在独立的目录中
+///
+/// 每个原始序列的统计量存为带`__rollup__`标签的四个序列，时间戳为桶的起点。
+/// 后台任务把序列号大于`processed_seq`的原始SSTable所覆盖的桶从原始数据重新计算一遍，
+/// 因此迟到的写入和删除在刷盘后也会反映到汇总表中。
+pub struct RollupTable {
+    resolution: u64,
+    dir: PathBuf,
+    manifest: Arc<Manifest>,
+    sstables: Arc<Mutex<Vec<Arc<SSTable>>>>,
+    compactor: Compactor,
+    retention: RetentionPolicy,
+    block_points: usize,
+    state: Mutex<RollupState>,
+}
+
+struct RollupState {
+    processed_seq: u64, // 序列号不大于它的原始SSTable都已汇总
+    next_seq: u64,      // 下一个汇总文件的序列号
+}
+
+impl RollupTable {
+    /// 打开`base_dir`下该分辨率的汇总表目录，不存在时创建
+    pub fn open(base_dir: &str, rule: &RollupRule, block_points: usize) -> Result<Self> {
+        let resolution = rule.resolution.as_secs();
+        if resolution == 0 {
+            return Err(Error::DataError("降采样的分辨率至少为1秒".to_string()));
+        }
+        let dir = Path::new(base_dir).join(format!("rollup-{}s", resolution));
+        let manifest = Arc::new(Manifest::open(&dir.to_string_lossy())?);
+        manifest.remove_orphans()?;
+        let mut sstables = Vec::new();
+        for name in manifest.live_files() {
+            let sst = SSTable::open(dir.join(&name))
+                .map_err(|e| Error::DataError(format!("加载汇总表文件 {} 失败: {}", name, e)))?;
+            sstables.push(Arc::new(sst));
+        }
+        sstables.sort_by_key(|sst| sst.seq());
+        let next_seq = manifest.last_seq().max(sstables.last().map_or(0, |sst| sst.seq())) + 1;
+
+        let processed_seq = match fs::read_to_string(dir.join(STATE_FILE)) {
+            Ok(s) => s
+                .trim()
+                .parse()
+                .map_err(|_| Error::DataError(format!("汇总表状态文件损坏: {:?}", dir)))?,
+            Err(e) if e.kind() == ErrorKind::NotFound => 0,
+            Err(e) => return Err(Error::IoError(e)),
+        };
+
+        let retention = RetentionPolicy::new(rule.retention);
+        let sstables = Arc::new(Mutex::new(sstables));
+        let compactor = Compactor::new(
+            Arc::clone(&sstables),
+            Arc::clone(&manifest),
+            CompactionStrategy::default(),
+            retention,
+            block_points,
+        );
+        info!("打开{}秒汇总表: {:?}, {} 个文件, 已汇总到原始序列号 {}",
+              resolution, dir, sstables.lock().unwrap().len(), processed_seq);
+        Ok(RollupTable {
+            resolution,
+            dir,
+            manifest,
+            sstables,
+            compactor,
+            retention,
+            block_points,
+            state: Mutex::new(RollupState { processed_seq, next_seq }),
+        })
+    }
+
+    /// 桶宽（秒）
+    pub fn resolution(&self) -> u64 {
+        self.resolution
+    }
+
+    /// 序列号不大于该值的原始SSTable都已反映在汇总表中
+    pub fn processed_seq(&self) -> u64 {
+        self.state.lock().unwrap().processed_seq
+    }
+
+    /// 汇总数据的过期分界点
+    pub fn cutoff(&self) -> Timestamp {
+        self.retention.cutoff()
+    }
+
+    pub fn bucket_start(&self, ts: Timestamp) -> Timestamp {
+        ts - ts % self.resolution
+    }
+
+    /// 读取`[start, end]`内的汇总桶，返回桶起点和统计，按时间排列
+    ///
+    /// 汇总表只有count、sum、min、max，统计中的first和last没有意义。
+    pub fn buckets(&self, series: &SeriesKey, start: Timestamp, end: Timestamp) -> Result<Vec<(Timestamp, BlockStats)>> {
+        let start = start.max(self.cutoff());
+        let mut sstables: Vec<Arc<SSTable>> = self.sstables.lock().unwrap().clone();
+        sstables.sort_by_key(|sst| sst.seq());
+        sstables.reverse();
+
+        let mut buckets: BTreeMap<Timestamp, [Option<Value>; 4]> = BTreeMap::new();
+        for (i, stat) in STATS.iter().enumerate() {
+            let key = stat_series(series, stat)?;
+            for point in merged(&sstables, &key, start, end)? {
+                let (ts, value) = point?;
+                buckets.entry(ts).or_default()[i] = Some(value);
+            }
+        }
+        Ok(buckets
+            .into_iter()
+            .filter_map(|(ts, stats)| match stats {
+                [Some(count), Some(sum), Some(min), Some(max)] => Some((ts, BlockStats {
+                    count: count as u64,
+                    sum,
+                    min,
+                    max,
+                    first: Value::NAN,
+                    last: Value::NAN,
+                })),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// 把序列号大于`processed_seq`的原始SSTable覆盖的桶重新汇总，没有新文件时返回false
+    ///
+    /// `raw`为当前所有原始SSTable，`cutoff`为原始数据的过期分界点，部分过期的桶不再重算。
+    pub fn roll_up(&self, raw: &[Arc<SSTable>], cutoff: Timestamp) -> Result<bool> {
+        let processed_seq = self.processed_seq();
+        let pending: Vec<&Arc<SSTable>> = raw.iter().filter(|sst| sst.seq() > processed_seq).collect();
+        let Some(max_seq) = pending.iter().map(|sst| sst.seq()).max() else {
+            return Ok(false);
+        };
+
+        // 新文件中每个序列的数据范围和墓碑范围
+        let mut ranges: BTreeMap<SeriesKey, Vec<(Timestamp, Timestamp)>> = BTreeMap::new();
+        for sst in &pending {
+            for series in sst.series_keys() {
+                let blocks = sst.overlapping_blocks(series, 0, Timestamp::MAX);
+                if let (Some(first), Some(last)) = (blocks.first(), blocks.last()) {
+                    ranges.entry(series.clone()).or_default().push((first.min_ts, last.max_ts));
+                }
+            }
+            for t in sst.tombstones() {
+                match &t.series {
+                    Some(series) => ranges.entry(series.clone()).or_default().push((t.start, t.end)),
+                    None => {
+                        for series in self.known_series(raw)? {
+                            ranges.entry(series).or_default().push((t.start, t.end));
+                        }
+                    }
+                }
+            }
+        }
+
+        // 按桶对齐后重新计算，先用墓碑清除旧的汇总结果，区间内已没有数据的桶也随之消失
+        let first_bucket = cutoff.div_ceil(self.resolution) * self.resolution;
+        let mut newest_first: Vec<Arc<SSTable>> = raw.to_vec();
+        newest_first.sort_by_key(|sst| sst.seq());
+        newest_first.reverse();
+        let mut output = MemTable::with_seq(self.state.lock().unwrap().next_seq);
+        for (series, spans) in ranges {
+            for (lo, hi) in self.coalesce(spans) {
+                let lo = lo.max(first_bucket);
+                if lo > hi {
+                    continue;
+                }
+                self.recompute(&newest_first, &series, lo, hi, &mut output)?;
+            }
+        }
+
+        if !output.is_empty() {
+            let sst = SSTable::create(self.manifest.new_file_path(), &output, self.block_points)?;
+            let name = sst
+                .file_name()
+                .ok_or_else(|| Error::DataError(format!("无效的SSTable路径: {:?}", sst.path)))?;
+            let edit = VersionEdit { last_seq: Some(sst.seq()), ..VersionEdit::add(name) };
+            self.manifest.log_and_apply(&edit)?;
+            self.sstables.lock().unwrap().push(Arc::new(sst));
+            self.state.lock().unwrap().next_seq += 1;
+        }
+
+        // 汇总文件提交后再记录进度，中途崩溃只会在重启后重算一次
+        save_processed_seq(&self.dir, max_seq)?;
+        self.state.lock().unwrap().processed_seq = max_seq;
+        info!("{}秒汇总表处理了 {} 个原始SSTable, 已汇总到序列号 {}, 写入 {} 个汇总值",
+              self.resolution, pending.len(), max_seq, output.len());
+        Ok(true)
+    }
+
+    /// 合并汇总表的小文件并删除过期的汇总数据
+    pub fn maintain(&self) -> Result<()> {
+        while self.compactor.compact_once()? {}
+        self.compactor.expire_once()?;
+        Ok(())
+    }
+
+    /// 把区间扩展到整桶并合并重叠或相邻的区间
+    fn coalesce(&self, mut spans: Vec<(Timestamp, Timestamp)>) -> Vec<(Timestamp, Timestamp)> {
+        spans.sort();
+        let mut merged: Vec<(Timestamp, Timestamp)> = Vec::new();
+        for (lo, hi) in spans {
+            let lo = self.bucket_start(lo);
+            let hi = self.bucket_start(hi).saturating_add(self.resolution - 1);
+            match merged.last_mut() {
+                Some((_, last_hi)) if lo <= last_hi.saturating_add(1) => *last_hi = (*last_hi).max(hi),
+                _ => merged.push((lo, hi)),
+            }
+        }
+        merged
+    }
+
+    /// 从原始数据重新计算`[lo, hi]`内的桶，写入`output`
+    fn recompute(
+        &self,
+        raw: &[Arc<SSTable>],
+        series: &SeriesKey,
+        lo: Timestamp,
+        hi: Timestamp,
+        output: &mut MemTable,
+    ) -> Result<()> {
+        let keys: Vec<SeriesKey> = STATS.iter().map(|stat| stat_series(series, stat)).collect::<Result<_>>()?;
+        for key in &keys {
+            output.delete(Tombstone { series: Some(key.clone()), start: lo, end: hi });
+        }
+
+        let mut current: Option<(Timestamp, Accumulator)> = None;
+        let flush = |bucket: Timestamp, acc: &Accumulator, output: &mut MemTable| {
+            let stats = [acc.count() as Value, acc.sum(), acc.min(), acc.max()];
+            for (key, value) in keys.iter().zip(stats) {
+                output.insert(key, bucket, value);
+            }
+        };
+        for point in merged(raw, series, lo, hi)? {
+            let (ts, value) = point?;
+            let bucket = self.bucket_start(ts);
+            match &mut current {
+                Some((b, acc)) if *b == bucket => acc.push(ts, value),
+                _ => {
+                    if let Some((b, acc)) = current.take() {
+                        flush(b, &acc, output);
+                    }
+                    let mut acc = Accumulator::new();
+                    acc.push(ts, value);
+                    current = Some((bucket, acc));
+                }
+            }
+        }
+        if let Some((b, acc)) = current {
+            flush(b, &acc, output);
+        }
+        Ok(())
+    }
+
+    /// 原始数据和汇总表中出现过的所有原始序列，用于作用于所有序列的墓碑
+    fn known_series(&self, raw: &[Arc<SSTable>]) -> Result<Vec<SeriesKey>> {
+        let mut series: Vec<SeriesKey> = raw.iter().flat_map(|sst| sst.series_keys().cloned()).collect();
+        for sst in self.sstables.lock().unwrap().iter() {
+            for key in sst.series_keys() {
+                let tags = key.tags().iter().filter(|(k, _)| k != STAT_TAG).map(|(k, v)| (k.as_str(), v.as_str()));
+                series.push(SeriesKey::new(key.metric(), tags)?);
+            }
+        }
+        series.sort();
+        series.dedup();
+        Ok(series)
+    }
+}
+
+/// 原子地保存汇总进度：先写临时文件再替换
+fn save_processed_seq(dir: &Path, seq: u64) -> Result<()> {
+    let tmp_path = dir.join(STATE_TMP_FILE);
+    {
+        let mut tmp = File::create(&tmp_path)?;
+        writeln!(tmp, "{}", seq)?;
+        tmp.sync_all()?;
+    }
+    fs::rename(&tmp_path, dir.join(STATE_FILE))?;
+    sync_dir(dir)
+}
+
+/// 降采样任务：按各汇总表的规则把新刷盘的原始SSTable汇总进去
+pub struct Roller {
+    sstables: Arc<Mutex<Vec<Arc<SSTable>>>>,
+    tables: Vec<Arc<RollupTable>>,
+    retention: RetentionPolicy,
+    running: Mutex<()>, // 同一时间只允许一个降采样任务
+}
+
+impl Roller {
+    pub fn new(sstables: Arc<Mutex<Vec<Arc<SSTable>>>>, tables: Vec<Arc<RollupTable>>, retention: RetentionPolicy) -> Self {
+        Roller {
+            sstables,
+            tables,
+            retention,
+            running: Mutex::new(()),
+        }
+    }
+
+    pub fn tables(&self) -> &[Arc<RollupTable>] {
+        &self.tables
+    }
+
+    /// 对所有汇总表执行一轮降采样和维护，有汇总表写入新数据时返回true
+    pub fn run_once(&self) -> Result<bool> {
+        let _running = self.running.lock().unwrap();
+        let raw: Vec<Arc<SSTable>> = self.sstables.lock().unwrap().clone();
+        let cutoff = self.retention.cutoff();
+        let mut updated = false;
+        for table in &self.tables {
+            updated |= table.roll_up(&raw, cutoff)?;
+            if let Err(e) = table.maintain() {
+                error!("{}秒汇总表维护失败: {:?}", table.resolution(), e);
+            }
+        }
+        Ok(updated)
+    }
+}
+
+/// 原始序列某个统计量对应的汇总序列
+fn stat_series(series: &SeriesKey, stat: &str) -> Result<SeriesKey> {
+    let tags = series
+        .tags()
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.as_str()))
+        .chain([(STAT_TAG, stat)]);
+    SeriesKey::new(series.metric(), tags)
+}
+
+/// 按新旧顺序归并一组SSTable中某个序列的区间数据，`sstables`须从新到旧排列
+fn merged(sstables: &[Arc<SSTable>], series: &SeriesKey, start: Timestamp, end: Timestamp) -> Result<QueryIter> {
+    let mut iter = QueryIter::new(series.clone(), start, end);
+    for sst in sstables {
+        iter.push_source(Box::new(sst.iter(series, start, end)), sst.tombstones())?;
+    }
+    Ok(iter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create(dir: &Path, seq: u64, series: &SeriesKey, points: &[(Timestamp, Value)]) -> Arc<SSTable> {
+        let mut data = MemTable::with_seq(seq);
+        for &(ts, value) in points {
+            data.insert(series, ts, value);
+        }
+        Arc::new(SSTable::create(dir.join(format!("raw-{}.db", seq)), &data, 16).unwrap())
+    }
+
+    #[test]
+    fn test_roll_up_and_late_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().to_string_lossy().into_owned();
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+        let rule = RollupRule { resolution: Duration::from_secs(10), retention: None };
+
+        let mut raw = vec![create(dir.path(), 1, &cpu, &(0..30).map(|ts| (ts, ts as f64)).collect::<Vec<_>>())];
+        let table = RollupTable::open(&base, &rule, 16).unwrap();
+        assert!(table.roll_up(&raw, 0).unwrap());
+        assert!(!table.roll_up(&raw, 0).unwrap());
+
+        let buckets = table.buckets(&cpu, 0, 100).unwrap();
+        assert_eq!(buckets.iter().map(|&(ts, s)| (ts, s.count, s.sum, s.min, s.max)).collect::<Vec<_>>(),
+                   vec![(0, 10, 45.0, 0.0, 9.0), (10, 10, 145.0, 10.0, 19.0), (20, 10, 245.0, 20.0, 29.0)]);
+
+        // 迟到的数据覆盖了桶10中的一个点，只重算受影响的桶
+        raw.push(create(dir.path(), 2, &cpu, &[(15, 100.0)]));
+        assert!(table.roll_up(&raw, 0).unwrap());
+        let buckets = table.buckets(&cpu, 10, 19).unwrap();
+        assert_eq!((buckets[0].1.sum, buckets[0].1.max), (230.0, 100.0));
+
+        // 重新打开后从记录的进度继续
+        drop(table);
+        let table = RollupTable::open(&base, &rule, 16).unwrap();
+        assert_eq!(table.processed_seq(), 2);
+        assert!(!table.roll_up(&raw, 0).unwrap());
+        assert_eq!(table.buckets(&cpu, 0, 100).unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,132 @@
+use std::fmt;
+
+use crate::error::{Error, Result};
+
+/// 时间序列标识：指标名 + 有序的标签集合
+///
+/// 规范化文本形式与InfluxDB行协议一致：`cpu,host=server01,region=us`，
+/// 标签按名称排序，因此同一组标签总是得到同一个序列键。
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SeriesKey {
+    metric: String,
+    tags: Vec<(String, String)>,
+}
+
+impl SeriesKey {
+    /// 根据指标名和标签创建序列键，标签会按名称排序
+    pub fn new<I, K, V>(metric: &str, tags: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        validate_part(metric, "指标名")?;
+
+        let mut tags: Vec<(String, String)> = tags
+            .into_iter()
+            .map(|(k, v)| (k.into(), v.into()))
+            .collect();
+        for (k, v) in &tags {
+            validate_part(k, "标签名")?;
+            validate_part(v, "标签值")?;
+        }
+        tags.sort();
+        if tags.windows(2).any(|w| w[0].0 == w[1].0) {
+            return Err(Error::SeriesError(format!("序列 {} 存在重复的标签名", metric)));
+        }
+
+        Ok(SeriesKey {
+            metric: metric.to_string(),
+            tags,
+        })
+    }
+
+    /// 从规范化文本形式解析序列键，如 `cpu,host=a`
+    pub fn parse(s: &str) -> Result<Self> {
+        let mut parts = s.split(',');
+        let metric = parts.next().unwrap_or("");
+
+        let mut tags = Vec::new();
+        for part in parts {
+            match part.split_once('=') {
+                Some((k, v)) => tags.push((k, v)),
+                None => {
+                    return Err(Error::SeriesError(format!("标签格式错误 '{}'，应为 key=value", part)));
+                }
+            }
+        }
+
+        SeriesKey::new(metric, tags)
+    }
+
+    /// 基线版本没有序列，它写出的SSTable和WAL中的数据都归入这个序列
+    pub fn legacy() -> Self {
+        SeriesKey {
+            metric: "default".to_string(),
+            tags: Vec::new(),
+        }
+    }
+
+    /// 指标名
+    pub fn metric(&self) -> &str {
+        &self.metric
+    }
+
+    /// 按名称排序的标签
+    pub fn tags(&self) -> &[(String, String)] {
+        &self.tags
+    }
+
+    /// 查找指定标签的值
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .binary_search_by(|(k, _)| k.as_str().cmp(name))
+            .ok()
+            .map(|i| self.tags[i].1.as_str())
+    }
+}
+
+impl fmt::Display for SeriesKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.metric)?;
+        for (k, v) in &self.tags {
+            write!(f, ",{}={}", k, v)?;
+        }
+        Ok(())
+    }
+}
+
+/// 指标名、标签名和标签值不能为空，也不能包含分隔符或空白
+fn validate_part(s: &str, what: &str) -> Result<()> {
+    if s.is_empty() {
+        return Err(Error::SeriesError(format!("{}不能为空", what)));
+    }
+    if s.chars().any(|c| c == ',' || c == '=' || c.is_whitespace()) {
+        return Err(Error::SeriesError(format!("{} '{}' 包含非法字符", what, s)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format() {
+        let key = SeriesKey::parse("cpu,region=us,host=a").unwrap();
+        assert_eq!(key.metric(), "cpu");
+        assert_eq!(key.tag("host"), Some("a"));
+        assert_eq!(key.tag("zone"), None);
+        // 标签排序后得到规范化形式
+        assert_eq!(key.to_string(), "cpu,host=a,region=us");
+        assert_eq!(SeriesKey::parse(&key.to_string()).unwrap(), key);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(SeriesKey::parse("").is_err());
+        assert!(SeriesKey::parse("cpu,host").is_err());
+        assert!(SeriesKey::parse("cpu,host=").is_err());
+        assert!(SeriesKey::parse("cpu,host=a,host=b").is_err());
+    }
+}
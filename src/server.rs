@@ -1,11 +1,17 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 use tokio::{
     io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
     net::{TcpListener, TcpStream},
 };
 use log::{info, error, debug};
-use crate::db::SimpleTSDB;
+use crate::aggregate::{Aggregation, Fill, Window};
+use crate::continuous::ContinuousQuery;
+use crate::db::{QueryOptions, SimpleTSDB};
 use crate::error::Result;
+use crate::index::Matcher;
+use crate::iter::Order;
+use crate::ql;
+use crate::series::SeriesKey;
 
 /// TSDB网络服务器，处理TCP连接和命令
 pub struct TsdbServer {
@@ -57,8 +63,14 @@ impl TsdbServer {
         while reader.read_line(&mut line).await? > 0 {
             debug!("收到命令: {}", line.trim());
             
-            // 解析并处理命令
-            let response = Self::process_command(&line, &db).await?;
+            // 解析并处理命令：数据库调用会阻塞（如组提交时等待fsync），放到阻塞线程池中执行，
+            // 不占用异步工作线程
+            let response = {
+                let (cmd, db) = (line.clone(), Arc::clone(&db));
+                tokio::task::spawn_blocking(move || Self::process_command(&cmd, &db))
+                    .await
+                    .map_err(std::io::Error::other)?
+            };
             writer.write_all(response.as_bytes()).await?;
             
             // 清空缓冲区，准备读取下一行
@@ -68,9 +80,22 @@ impl TsdbServer {
         Ok(())
     }
 
-    /// 处理命令并返回响应
-    async fn process_command(cmd: &str, db: &SimpleTSDB) -> Result<String> {
-        let parts: Vec<&str> = cmd.trim().split_whitespace().collect();
+    /// 处理命令并返回响应，会阻塞在数据库调用上
+    ///
+    /// 命令格式错误和数据库错误都回复`ERROR: <原因>`，连接保持打开。
+    fn process_command(cmd: &str, db: &SimpleTSDB) -> String {
+        match Self::execute_command(cmd, db) {
+            Ok(response) => response,
+            Err(e) => {
+                error!("命令执行失败: {:?}", e);
+                format!("ERROR: {}\n", e)
+            }
+        }
+    }
+
+    /// 执行一条命令，数据库错误直接返回，由`process_command`转为错误回复
+    fn execute_command(cmd: &str, db: &SimpleTSDB) -> Result<String> {
+        let parts: Vec<&str> = cmd.split_whitespace().collect();
         
         if parts.is_empty() {
             return Ok("ERROR: 空命令\n".to_string());
@@ -78,41 +103,75 @@ impl TsdbServer {
 
         match parts[0].to_uppercase().as_str() {
             "PUT" => {
-                if parts.len() != 3 {
-                    return Ok("ERROR: 格式错误，应为 PUT <timestamp> <value>\n".to_string());
+                if parts.len() != 4 {
+                    return Ok("ERROR: 格式错误，应为 PUT <series> <timestamp> <value>\n".to_string());
                 }
                 
-                let ts = match parts[1].parse::<u64>() {
+                let series = match SeriesKey::parse(parts[1]) {
+                    Ok(series) => series,
+                    Err(e) => return Ok(format!("ERROR: {}\n", e)),
+                };
+                
+                let ts = match parts[2].parse::<u64>() {
                     Ok(ts) => ts,
                     Err(_) => return Ok("ERROR: 时间戳必须是数字\n".to_string()),
                 };
                 
-                let value = match parts[2].parse::<f64>() {
+                let value = match parts[3].parse::<f64>() {
                     Ok(val) => val,
                     Err(_) => return Ok("ERROR: 值必须是浮点数\n".to_string()),
                 };
                 
                 // 存储数据点
-                db.put(ts, value)?;
-                Ok("OK\n".to_string())
+                db.put(&series, ts, value)?;
+                // 回复中带上确认这次写入的WAL持久化方式
+                Ok(format!("OK {}\n", db.wal_sync_mode()))
             },
             "GET" => {
-                if parts.len() != 3 {
-                    return Ok("ERROR: 格式错误，应为 GET <start_ts> <end_ts>\n".to_string());
+                // GET <series> <start_ts> <end_ts> [ASC|DESC] [LIMIT <n>] [OFFSET <n>]
+                let usage = "ERROR: 格式错误，应为 GET <series> <start_ts> <end_ts> [ASC|DESC] [LIMIT <n>] [OFFSET <n>]\n";
+                if parts.len() < 4 {
+                    return Ok(usage.to_string());
                 }
                 
-                let start = match parts[1].parse::<u64>() {
+                let series = match SeriesKey::parse(parts[1]) {
+                    Ok(series) => series,
+                    Err(e) => return Ok(format!("ERROR: {}\n", e)),
+                };
+                
+                let start = match parts[2].parse::<u64>() {
                     Ok(ts) => ts,
                     Err(_) => return Ok("ERROR: 起始时间戳必须是数字\n".to_string()),
                 };
                 
-                let end = match parts[2].parse::<u64>() {
+                let end = match parts[3].parse::<u64>() {
                     Ok(ts) => ts,
                     Err(_) => return Ok("ERROR: 结束时间戳必须是数字\n".to_string()),
                 };
                 
+                let mut options = QueryOptions::default();
+                let mut rest = parts[4..].iter();
+                while let Some(option) = rest.next() {
+                    match option.to_uppercase().as_str() {
+                        "ASC" => options.order = Order::Ascending,
+                        "DESC" => options.order = Order::Descending,
+                        keyword @ ("LIMIT" | "OFFSET") => {
+                            let n = match rest.next().map(|s| s.parse::<usize>()) {
+                                Some(Ok(n)) => n,
+                                _ => return Ok(format!("ERROR: {} 后必须是非负整数\n", keyword)),
+                            };
+                            if keyword == "LIMIT" {
+                                options.limit = Some(n);
+                            } else {
+                                options.offset = n;
+                            }
+                        }
+                        _ => return Ok(usage.to_string()),
+                    }
+                }
+
                 // 查询数据
-                let results = db.query(start, end)?;
+                let results = db.query_with(&series, start, end, options)?;
                 
                 // 格式化结果
                 let mut response = String::new();
@@ -122,8 +181,391 @@ impl TsdbServer {
                 response.push_str("OK\n");
                 Ok(response)
             },
+            "LAST" => {
+                // LAST <series> [ts]：最新的数据点，或不晚于ts的最后一个数据点
+                if parts.len() != 2 && parts.len() != 3 {
+                    return Ok("ERROR: 格式错误，应为 LAST <series> [timestamp]\n".to_string());
+                }
+
+                let series = match SeriesKey::parse(parts[1]) {
+                    Ok(series) => series,
+                    Err(e) => return Ok(format!("ERROR: {}\n", e)),
+                };
+
+                let point = match parts.get(2).map(|s| s.parse::<u64>()) {
+                    Some(Ok(ts)) => db.last_before(&series, ts)?,
+                    Some(Err(_)) => return Ok("ERROR: 时间戳必须是数字\n".to_string()),
+                    None => db.last(&series)?,
+                };
+                match point {
+                    Some((ts, value)) => Ok(format!("{} {}\nOK\n", ts, value)),
+                    None => Ok("null\nOK\n".to_string()),
+                }
+            },
+            "AT" => {
+                if parts.len() != 3 {
+                    return Ok("ERROR: 格式错误，应为 AT <series> <timestamp>\n".to_string());
+                }
+
+                let series = match SeriesKey::parse(parts[1]) {
+                    Ok(series) => series,
+                    Err(e) => return Ok(format!("ERROR: {}\n", e)),
+                };
+
+                let ts = match parts[2].parse::<u64>() {
+                    Ok(ts) => ts,
+                    Err(_) => return Ok("ERROR: 时间戳必须是数字\n".to_string()),
+                };
+
+                // 该时间戳没有数据时返回null
+                match db.get(&series, ts)? {
+                    Some(value) => Ok(format!("{}\nOK\n", value)),
+                    None => Ok("null\nOK\n".to_string()),
+                }
+            },
+            "AGG" => {
+                if parts.len() != 5 {
+                    return Ok("ERROR: 格式错误，应为 AGG <function> <series> <start_ts> <end_ts>\n".to_string());
+                }
+
+                let agg = match Aggregation::parse(parts[1]) {
+                    Ok(agg) => agg,
+                    Err(e) => return Ok(format!("ERROR: {}\n", e)),
+                };
+
+                let series = match SeriesKey::parse(parts[2]) {
+                    Ok(series) => series,
+                    Err(e) => return Ok(format!("ERROR: {}\n", e)),
+                };
+
+                let start = match parts[3].parse::<u64>() {
+                    Ok(ts) => ts,
+                    Err(_) => return Ok("ERROR: 起始时间戳必须是数字\n".to_string()),
+                };
+
+                let end = match parts[4].parse::<u64>() {
+                    Ok(ts) => ts,
+                    Err(_) => return Ok("ERROR: 结束时间戳必须是数字\n".to_string()),
+                };
+
+                // 区间内没有数据时返回null
+                match db.aggregate(&series, start, end, agg)? {
+                    Some(value) => Ok(format!("{}\nOK\n", value)),
+                    None => Ok("null\nOK\n".to_string()),
+                }
+            },
+            "GROUP" => {
+                // GROUP <function> <series> <start_ts> <end_ts> <width> [offset] [FILL <mode>]
+                let usage = "ERROR: 格式错误，应为 GROUP <function> <series> <start_ts> <end_ts> <width> [offset] [FILL <mode>]\n";
+                let (args, fill) = match parts.iter().position(|p| p.eq_ignore_ascii_case("FILL")) {
+                    Some(i) if i + 2 == parts.len() => match Fill::parse(parts[i + 1]) {
+                        Ok(fill) => (&parts[..i], fill),
+                        Err(e) => return Ok(format!("ERROR: {}\n", e)),
+                    },
+                    Some(_) => return Ok(usage.to_string()),
+                    None => (&parts[..], Fill::None),
+                };
+                if args.len() != 6 && args.len() != 7 {
+                    return Ok(usage.to_string());
+                }
+
+                let agg = match Aggregation::parse(args[1]) {
+                    Ok(agg) => agg,
+                    Err(e) => return Ok(format!("ERROR: {}\n", e)),
+                };
+
+                let series = match SeriesKey::parse(args[2]) {
+                    Ok(series) => series,
+                    Err(e) => return Ok(format!("ERROR: {}\n", e)),
+                };
+
+                let start = match args[3].parse::<u64>() {
+                    Ok(ts) => ts,
+                    Err(_) => return Ok("ERROR: 起始时间戳必须是数字\n".to_string()),
+                };
+
+                let end = match args[4].parse::<u64>() {
+                    Ok(ts) => ts,
+                    Err(_) => return Ok("ERROR: 结束时间戳必须是数字\n".to_string()),
+                };
+
+                let width = match args[5].parse::<u64>() {
+                    Ok(width) => width,
+                    Err(_) => return Ok("ERROR: 桶宽必须是数字\n".to_string()),
+                };
+
+                let offset = match args.get(6).map(|s| s.parse::<u64>()) {
+                    Some(Ok(offset)) => offset,
+                    Some(Err(_)) => return Ok("ERROR: 偏移必须是数字\n".to_string()),
+                    None => 0,
+                };
+
+                let window = match Window::new(width, offset) {
+                    Ok(window) => window,
+                    Err(e) => return Ok(format!("ERROR: {}\n", e)),
+                };
+
+                // 每个桶一行：桶起点和聚合结果，空桶为null
+                let rows = db.aggregate_window(&series, start, end, agg, window, fill)?;
+                let mut response = String::new();
+                for (bucket, value) in rows {
+                    match value {
+                        Some(value) => response.push_str(&format!("{} {}\n", bucket, value)),
+                        None => response.push_str(&format!("{} null\n", bucket)),
+                    }
+                }
+                response.push_str("OK\n");
+                Ok(response)
+            },
+            "DELETE" => {
+                if parts.len() != 4 {
+                    return Ok("ERROR: 格式错误，应为 DELETE <series|*> <start_ts> <end_ts>\n".to_string());
+                }
+
+                let start = match parts[2].parse::<u64>() {
+                    Ok(ts) => ts,
+                    Err(_) => return Ok("ERROR: 起始时间戳必须是数字\n".to_string()),
+                };
+
+                let end = match parts[3].parse::<u64>() {
+                    Ok(ts) => ts,
+                    Err(_) => return Ok("ERROR: 结束时间戳必须是数字\n".to_string()),
+                };
+
+                if start > end {
+                    return Ok("ERROR: 起始时间戳不能大于结束时间戳\n".to_string());
+                }
+
+                // `*` 表示删除所有序列在区间内的数据
+                if parts[1] == "*" {
+                    db.delete_range(start, end)?;
+                } else {
+                    let series = match SeriesKey::parse(parts[1]) {
+                        Ok(series) => series,
+                        Err(e) => return Ok(format!("ERROR: {}\n", e)),
+                    };
+                    db.delete(&series, start, end)?;
+                }
+                Ok(format!("OK {}\n", db.wal_sync_mode()))
+            },
+            "SERIES" => {
+                // 选择器中可能含空格，取命令名之后的整行
+                let selector = cmd.trim()[parts[0].len()..].trim();
+                if selector.is_empty() {
+                    return Ok("ERROR: 格式错误，应为 SERIES <selector>\n".to_string());
+                }
+                
+                let matchers = match Matcher::parse_selector(selector) {
+                    Ok(m) => m,
+                    Err(e) => return Ok(format!("ERROR: {}\n", e)),
+                };
+                
+                let mut response = String::new();
+                for series in db.select_series(&matchers)? {
+                    response.push_str(&format!("{}\n", series));
+                }
+                response.push_str("OK\n");
+                Ok(response)
+            },
+            "QUERY" => {
+                // QUERY SELECT ...，语句中含空格，取命令名之后的整行
+                let text = cmd.trim()[parts[0].len()..].trim();
+                if text.is_empty() {
+                    return Ok("ERROR: 格式错误，应为 QUERY <statement>\n".to_string());
+                }
+
+                let now = chrono::Utc::now().timestamp() as u64;
+                let result = match ql::query(db, text, now) {
+                    Ok(result) => result,
+                    Err(e) => return Ok(format!("ERROR: {}\n", e)),
+                };
+
+                // 先列出列名，再逐个序列输出行：时间和各列的值，空值为null
+                let mut response = format!("COLUMNS time {}\n", result.columns.join(" "));
+                for series in &result.series {
+                    response.push_str(&format!("SERIES {}\n", series.series));
+                    for row in &series.rows {
+                        response.push_str(&row.timestamp.to_string());
+                        for value in &row.values {
+                            match value {
+                                Some(value) => response.push_str(&format!(" {}", value)),
+                                None => response.push_str(" null"),
+                            }
+                        }
+                        response.push('\n');
+                    }
+                }
+                response.push_str("OK\n");
+                Ok(response)
+            },
+            "CQ" => {
+                // CQ CREATE <name> <function> <interval_secs> <target> [BY <tag,...>] <selector> | CQ LIST | CQ DROP <name>
+                let usage = "ERROR: 格式错误，应为 CQ CREATE <name> <function> <interval_secs> <target> [BY <tag,...>] <selector>、CQ LIST 或 CQ DROP <name>\n";
+                match parts.get(1).map(|s| s.to_uppercase()).as_deref() {
+                    Some("CREATE") => {
+                        // 选择器中可能含空格，依次取出前面的字段，剩下的整段是选择器
+                        let (_, rest) = split_word(cmd.trim());
+                        let (_, rest) = split_word(rest);
+                        let (name, rest) = split_word(rest);
+                        let (function, rest) = split_word(rest);
+                        let (interval, rest) = split_word(rest);
+                        let (target, mut rest) = split_word(rest);
+                        let mut group_by = Vec::new();
+                        let (word, after) = split_word(rest);
+                        if word.eq_ignore_ascii_case("BY") {
+                            let (tags, after) = split_word(after);
+                            group_by = tags.split(',').map(str::to_string).collect();
+                            rest = after;
+                        }
+                        if rest.is_empty() {
+                            return Ok(usage.to_string());
+                        }
+
+                        let aggregation = match Aggregation::parse(function) {
+                            Ok(agg) => agg,
+                            Err(e) => return Ok(format!("ERROR: {}\n", e)),
+                        };
+
+                        let interval = match interval.parse::<u64>() {
+                            Ok(secs) => Duration::from_secs(secs),
+                            Err(_) => return Ok("ERROR: 间隔必须是秒数\n".to_string()),
+                        };
+
+                        // 定义无效或名称重复时注册失败，回复错误
+                        db.register_continuous_query(ContinuousQuery {
+                            name: name.to_string(),
+                            selector: rest.to_string(),
+                            aggregation,
+                            interval,
+                            target: target.to_string(),
+                            group_by,
+                        })?;
+                        Ok("OK\n".to_string())
+                    }
+                    Some("LIST") if parts.len() == 2 => {
+                        // 每个查询一行：名称、水位线、聚合函数、间隔、目标指标、分组标签（如有）和选择器
+                        let mut response = String::new();
+                        for state in db.continuous_queries() {
+                            let q = &state.query;
+                            response.push_str(&format!("{} {} {} {} {}", q.name, state.watermark, q.aggregation, q.interval.as_secs(), q.target));
+                            if !q.group_by.is_empty() {
+                                response.push_str(&format!(" BY {}", q.group_by.join(",")));
+                            }
+                            response.push_str(&format!(" {}\n", q.selector));
+                        }
+                        response.push_str("OK\n");
+                        Ok(response)
+                    }
+                    Some("DROP") if parts.len() == 3 => {
+                        if db.drop_continuous_query(parts[2])? {
+                            Ok("OK\n".to_string())
+                        } else {
+                            Ok(format!("ERROR: 连续查询 {} 不存在\n", parts[2]))
+                        }
+                    }
+                    _ => Ok(usage.to_string()),
+                }
+            },
             _ => Ok(format!("ERROR: 未知命令 '{}'\n", parts[0])),
         }
     }
 }
 
+/// 取出开头的一个词，返回该词和之后去掉前导空白的剩余部分
+fn split_word(s: &str) -> (&str, &str) {
+    let s = s.trim_start();
+    match s.find(char::is_whitespace) {
+        Some(i) => (&s[..i], s[i..].trim_start()),
+        None => (s, ""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compaction::CompactionStrategy;
+    use crate::db::DbConfig;
+
+    fn open_db(dir: &std::path::Path) -> SimpleTSDB {
+        SimpleTSDB::open(DbConfig {
+            sstable_dir: dir.join("sstable").to_string_lossy().into_owned(),
+            wal_dir: dir.join("wal").to_string_lossy().into_owned(),
+            index_path: dir.join("series.idx").to_string_lossy().into_owned(),
+            continuous_query_path: dir.join("continuous_queries").to_string_lossy().into_owned(),
+            compaction: CompactionStrategy::Disabled,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_process_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_db(dir.path());
+        let run = |cmd: &str| TsdbServer::process_command(cmd, &db);
+        for ts in 1..=5 {
+            assert_eq!(run(&format!("PUT cpu,host=a {} {}\n", ts, ts)), "OK none\n");
+        }
+        assert!(run("PUT cpu,host=a 1").starts_with("ERROR: 格式错误"));
+
+        assert_eq!(run("GET cpu,host=a 0 10 DESC LIMIT 2 OFFSET 1"), "4 4\n3 3\nOK\n");
+        assert_eq!(run("get cpu,host=a 2 3"), "2 2\n3 3\nOK\n");
+        assert_eq!(run("GET cpu,host=a 0 10 LIMIT x"), "ERROR: LIMIT 后必须是非负整数\n");
+
+        assert_eq!(run("LAST cpu,host=a"), "5 5\nOK\n");
+        assert_eq!(run("LAST cpu,host=a 3"), "3 3\nOK\n");
+        assert_eq!(run("LAST cpu,host=b"), "null\nOK\n");
+        assert_eq!(run("AT cpu,host=a 2"), "2\nOK\n");
+        assert_eq!(run("AT cpu,host=a 9"), "null\nOK\n");
+
+        assert_eq!(run("AGG sum cpu,host=a 0 10"), "15\nOK\n");
+        assert_eq!(run("AGG max cpu,host=a 20 30"), "null\nOK\n");
+        assert!(run("AGG foo cpu,host=a 0 10").starts_with("ERROR: "));
+        assert_eq!(run("GROUP max cpu,host=a 0 14 5 FILL null"), "0 4\n5 5\n10 null\nOK\n");
+        assert!(run("GROUP max cpu,host=a 0 10 0").starts_with("ERROR: "));
+
+        assert_eq!(run("QUERY SELECT max(value), count(value) FROM cpu WHERE time <= 10"),
+                   "COLUMNS time max count\nSERIES cpu,host=a\n0 5 5\nOK\n");
+        assert!(run("QUERY SELECT value FROM cpu WHERE host = 'a' OR host = 'b'")
+            .starts_with("ERROR: Query error at offset 39"));
+
+        assert_eq!(run("DELETE cpu,host=a 5 5"), "OK none\n");
+        assert_eq!(run("LAST cpu,host=a"), "4 4\nOK\n");
+        assert_eq!(run("SERIES cpu"), "cpu,host=a\nOK\n");
+        assert_eq!(run("FOO"), "ERROR: 未知命令 'FOO'\n");
+
+        // 数据库返回的错误（序列键过长）也回复ERROR，之后的命令照常处理
+        assert!(run(&format!("PUT cpu,host={} 1 1", "a".repeat(70000))).starts_with("ERROR: "));
+        assert_eq!(run("AT cpu,host=a 1"), "1\nOK\n");
+    }
+
+    #[test]
+    fn test_continuous_query_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = open_db(dir.path());
+        let run = |cmd: &str| TsdbServer::process_command(cmd, &db);
+
+        assert_eq!(run("CQ CREATE req_sum sum 60 req_1m BY host {__name__=\"req\", path=~\"/a|/b\"}\n"), "OK\n");
+        assert_eq!(run("cq create cpu_max max 10 cpu_max_10s cpu"), "OK\n");
+        assert_eq!(run("CQ LIST"),
+                   "req_sum 0 sum 60 req_1m BY host {__name__=\"req\", path=~\"/a|/b\"}\ncpu_max 0 max 10 cpu_max_10s cpu\nOK\n");
+
+        // 名称重复、聚合函数不能分组、缺少选择器时回复错误
+        assert!(run("CQ CREATE cpu_max max 10 cpu_max_10s cpu").starts_with("ERROR: "));
+        assert!(run("CQ CREATE req_rate rate 60 req_rate BY host req").starts_with("ERROR: "));
+        assert!(run("CQ CREATE req_rate rate 60 req_rate BY host").starts_with("ERROR: 格式错误"));
+        assert!(run("CQ CREATE x sum abc y z").starts_with("ERROR: "));
+
+        // 按host分组后合并同一主机的多个序列
+        for (series, value) in [("req,host=a,path=/a", 1.0), ("req,host=a,path=/b", 2.0), ("req,host=a,path=/c", 4.0)] {
+            assert_eq!(run(&format!("PUT {} 0 {}", series, value)), "OK none\n");
+        }
+        assert_eq!(db.run_continuous_queries_at(60).unwrap(), 1);
+        assert_eq!(run("GET req_1m,host=a 0 100"), "0 3\nOK\n");
+
+        assert_eq!(run("CQ DROP req_sum"), "OK\n");
+        assert_eq!(run("CQ DROP req_sum"), "ERROR: 连续查询 req_sum 不存在\n");
+        assert_eq!(run("CQ LIST"), "cpu_max 60 max 10 cpu_max_10s cpu\nOK\n");
+        assert!(run("CQ").starts_with("ERROR: 格式错误"));
+    }
+}
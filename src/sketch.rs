@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+
+use crate::error::{Error, Result};
+use crate::wal::Value;
+
+/// 分位数的相对误差上限，写入文件的草图都使用这个精度
+pub const RELATIVE_ACCURACY: f64 = 0.01;
+
+/// 绝对值小于该值的数据计入零桶
+const MIN_INDEXABLE: f64 = 1e-9;
+
+/// DDSketch分位数草图：按对数划分的桶计数，任意分位数的相对误差不超过`RELATIVE_ACCURACY`
+///
+/// 桶`k`覆盖`(gamma^(k-1), gamma^k]`，其中`gamma = (1 + a) / (1 - a)`；正负数各有一组桶。
+/// 同精度的草图可以直接按桶相加合并，因此每个块的草图可以预先算好，查询时再合并。
+#[derive(Debug, Clone, PartialEq)]
+pub struct DDSketch {
+    gamma: f64,
+    positive: BTreeMap<i32, u64>,
+    negative: BTreeMap<i32, u64>,
+    zero: u64,
+    count: u64,
+    // 精确的最小最大值，分位数结果不超出这个范围
+    min: Value,
+    max: Value,
+}
+
+impl Default for DDSketch {
+    fn default() -> Self {
+        DDSketch::new()
+    }
+}
+
+impl DDSketch {
+    pub fn new() -> Self {
+        DDSketch {
+            gamma: (1.0 + RELATIVE_ACCURACY) / (1.0 - RELATIVE_ACCURACY),
+            positive: BTreeMap::new(),
+            negative: BTreeMap::new(),
+            zero: 0,
+            count: 0,
+            min: Value::INFINITY,
+            max: Value::NEG_INFINITY,
+        }
+    }
+
+    /// 加入一个值，NaN被忽略
+    pub fn add(&mut self, value: Value) {
+        if value.is_nan() {
+            return;
+        }
+        if value > MIN_INDEXABLE {
+            *self.positive.entry(self.key(value)).or_default() += 1;
+        } else if value < -MIN_INDEXABLE {
+            *self.negative.entry(self.key(-value)).or_default() += 1;
+        } else {
+            self.zero += 1;
+        }
+        self.count += 1;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    /// 合并另一个草图，结果与把两边的数据加入同一个草图相同
+    pub fn merge(&mut self, other: &DDSketch) {
+        for (&key, &n) in &other.positive {
+            *self.positive.entry(key).or_default() += n;
+        }
+        for (&key, &n) in &other.negative {
+            *self.negative.entry(key).or_default() += n;
+        }
+        self.zero += other.zero;
+        self.count += other.count;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// 第`q`分位数（0到1），没有数据时为None
+    pub fn quantile(&self, q: f64) -> Option<Value> {
+        if self.count == 0 || !(0.0..=1.0).contains(&q) {
+            return None;
+        }
+        let rank = (q * (self.count - 1) as f64) as u64;
+        if rank == 0 {
+            return Some(self.min);
+        }
+        if rank == self.count - 1 {
+            return Some(self.max);
+        }
+        let mut seen = 0;
+        // 从小到大：负数桶按绝对值从大到小，零桶，正数桶
+        for (&key, &n) in self.negative.iter().rev() {
+            seen += n;
+            if seen > rank {
+                return Some((-self.value(key)).clamp(self.min, self.max));
+            }
+        }
+        seen += self.zero;
+        if seen > rank {
+            return Some(0.0);
+        }
+        for (&key, &n) in &self.positive {
+            seen += n;
+            if seen > rank {
+                return Some(self.value(key).clamp(self.min, self.max));
+            }
+        }
+        Some(self.max)
+    }
+
+    /// 编码为 `gamma | zero(u64) | min | max | positive_count(u32) | (key(i32) | count(u64))... | negative_count(u32) | ...`
+    pub fn encode(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.gamma.to_le_bytes());
+        buf.extend_from_slice(&self.zero.to_le_bytes());
+        buf.extend_from_slice(&self.min.to_le_bytes());
+        buf.extend_from_slice(&self.max.to_le_bytes());
+        for bins in [&self.positive, &self.negative] {
+            buf.extend_from_slice(&(bins.len() as u32).to_le_bytes());
+            for (&key, &n) in bins {
+                buf.extend_from_slice(&key.to_le_bytes());
+                buf.extend_from_slice(&n.to_le_bytes());
+            }
+        }
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self> {
+        let truncated = || Error::DataError("分位数草图数据不完整".to_string());
+        let mut pos = 0;
+        let mut take = |n: usize| {
+            let bytes = buf.get(pos..pos + n).ok_or_else(truncated)?;
+            pos += n;
+            Ok::<_, Error>(bytes)
+        };
+        let mut sketch = DDSketch::new();
+        let gamma = f64::from_le_bytes(take(8)?.try_into().unwrap());
+        if gamma != sketch.gamma {
+            return Err(Error::DataError(format!("不支持的分位数草图精度: gamma={}", gamma)));
+        }
+        sketch.zero = u64::from_le_bytes(take(8)?.try_into().unwrap());
+        sketch.min = f64::from_le_bytes(take(8)?.try_into().unwrap());
+        sketch.max = f64::from_le_bytes(take(8)?.try_into().unwrap());
+        sketch.count = sketch.zero;
+        for negative in [false, true] {
+            let len = u32::from_le_bytes(take(4)?.try_into().unwrap());
+            for _ in 0..len {
+                let key = i32::from_le_bytes(take(4)?.try_into().unwrap());
+                let n = u64::from_le_bytes(take(8)?.try_into().unwrap());
+                let bins = if negative { &mut sketch.negative } else { &mut sketch.positive };
+                bins.insert(key, n);
+                sketch.count += n;
+            }
+        }
+        if pos != buf.len() {
+            return Err(Error::DataError("分位数草图数据有多余字节".to_string()));
+        }
+        Ok(sketch)
+    }
+
+    fn key(&self, value: Value) -> i32 {
+        (value.ln() / self.gamma.ln()).ceil() as i32
+    }
+
+    /// 桶的代表值，与桶内任意值的相对误差不超过精度
+    fn value(&self, key: i32) -> Value {
+        2.0 * self.gamma.powi(key) / (self.gamma + 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quantile_accuracy_and_merge() {
+        let mut whole = DDSketch::new();
+        let mut parts = [DDSketch::new(), DDSketch::new()];
+        for i in -1000..=10000 {
+            let value = i as Value;
+            whole.add(value);
+            parts[(i & 1) as usize].add(value);
+        }
+        let [mut merged, other] = parts;
+        merged.merge(&other);
+        assert_eq!(merged, whole);
+        assert_eq!(whole.count(), 11001);
+
+        for (q, exact) in [(0.5, 4500.0), (0.95, 9450.0), (0.99, 9890.0), (0.01, -890.0)] {
+            let estimate = whole.quantile(q).unwrap();
+            assert!((estimate - exact).abs() <= exact.abs() * RELATIVE_ACCURACY + 1.0, "q={} {}", q, estimate);
+        }
+        assert_eq!(whole.quantile(0.0), Some(-1000.0));
+        assert_eq!(whole.quantile(1.0), Some(10000.0));
+        assert_eq!(DDSketch::new().quantile(0.5), None);
+
+        let mut buf = Vec::new();
+        whole.encode(&mut buf);
+        assert_eq!(DDSketch::decode(&buf).unwrap(), whole);
+        assert!(DDSketch::decode(&buf[..buf.len() - 1]).is_err());
+    }
+}
@@ -1,120 +1,513 @@
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, HashMap},
     fs::{self, File},
-    io::{self, BufWriter, Read, Write},
+    io::{self, BufWriter, Write},
+    ops::Range,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use log::{debug, info};
 use memmap2::{Mmap, MmapOptions};
 
 use crate::error::{Error, Result};
-use crate::gorilla::TimeSeriesBlock;
+use crate::gorilla::{GorillaDecoder, TimeSeriesBlock};
+use crate::manifest::sync_dir;
+use crate::memtable::{MemTable, Tombstone};
+use crate::series::SeriesKey;
+use crate::sketch::DDSketch;
 use crate::wal::{Timestamp, Value};
 
+/// 每个Gorilla块默认包含的数据点数
+pub const DEFAULT_BLOCK_POINTS: usize = 1024;
+
+/// 当前写入的文件格式版本
+pub const FORMAT_VERSION: u32 = 1;
+
+/// 文件头和文件尾中的魔数
+const MAGIC: &[u8; 8] = b"RYTSDBST";
+/// 文件头：魔数、格式版本
+const HEADER_SIZE: usize = 12;
+/// 文件尾：段目录偏移、最小TS、最大TS、点数、创建时间、编码、填充、格式版本、CRC32C、魔数
+const FOOTER_SIZE: usize = 60;
+/// 基线版本（v0）的文件头：最小TS、最大TS、压缩数据长度
+const V0_HEADER_SIZE: usize = 20;
+
+/// 数据块的编码方式
+const CODEC_GORILLA: u8 = 1;
+
+/// 段编号
+const SECTION_INDEX: u32 = 1;
+const SECTION_TOMBSTONES: u32 = 2;
+const SECTION_SEQUENCE: u32 = 3;
+const SECTION_BLOCK_STATS: u32 = 4;
+const SECTION_BLOCK_SKETCHES: u32 = 5;
+/// 段目录中每个段的条目大小：编号、偏移、长度、CRC32C
+const SECTION_ENTRY_SIZE: usize = 24;
+
+/// 块索引中每个块的条目大小：min_ts、max_ts、offset、len、CRC32C
+const BLOCK_HANDLE_SIZE: usize = 32;
+/// 块统计段中每个块的条目大小：offset、count、min、max、sum、first、last
+const BLOCK_STATS_SIZE: usize = 56;
+
+/// 块内数据的预聚合统计，整块落在聚合区间内时不用解码
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockStats {
+    pub count: u64,
+    pub min: Value,
+    pub max: Value,
+    pub sum: Value,
+    pub first: Value,
+    pub last: Value,
+}
+
+impl BlockStats {
+    fn from_points(points: &[(Timestamp, Value)]) -> Self {
+        let mut stats = BlockStats {
+            count: points.len() as u64,
+            min: points[0].1,
+            max: points[0].1,
+            sum: 0.0,
+            first: points[0].1,
+            last: points[points.len() - 1].1,
+        };
+        for &(_, value) in points {
+            stats.min = stats.min.min(value);
+            stats.max = stats.max.max(value);
+            stats.sum += value;
+        }
+        stats
+    }
+}
+
+/// 稀疏时间索引中的一个块
+#[derive(Debug, Clone, Copy)]
+pub struct BlockHandle {
+    pub min_ts: Timestamp,
+    pub max_ts: Timestamp,
+    pub offset: usize,    // 压缩数据在文件中的偏移
+    pub len: usize,       // 压缩数据长度
+    pub crc: Option<u32>, // 压缩数据的CRC32C，v0文件没有
+    pub stats: Option<BlockStats>, // 预聚合统计，没有块统计段的文件没有
+}
+
 /// SSTable文件结构：使用Gorilla压缩和内存映射实现零拷贝读取
+///
+/// v1文件布局：`文件头 | 数据块... | 段... | 段目录 | 文件尾`，均为小端序。
+/// 文件头为 `magic(8) | version(u32)`；每个序列的数据按时间切分为多个Gorilla块。
+/// 索引段为 `series_count(u32)`，随后每个序列 `key_len(u16) | series_key | block_count(u32) | 块条目...`，
+/// 块条目为 `min_ts | max_ts | offset(u64) | len(u32) | crc32c(u32)`；墓碑段为
+/// `tombstone_count(u32)`，随后每个墓碑 `key_len(u16) | series_key | start | end`（作用于所有序列时key_len为0）；
+/// 序列号段为 `seq(u64)`，没有序列号段的文件序列号为0；块统计段为 `block_count(u32)`，随后每个块
+/// `offset(u64) | count(u64) | min | max | sum | first | last`（f64），按偏移对应到块索引中的块；
+/// 块草图段为 `block_count(u32)`，随后每个块 `offset(u64) | len(u32) | DDSketch编码`，查询时才解码。
+/// 段目录为 `section_count(u32)`，随后每个段 `id(u32) | offset(u64) | len(u64) | crc32c(u32)`；
+/// 文件尾为 `dir_offset | min_ts | max_ts | point_count | created_at | codec(u8) | 填充(3) | version(u32) | crc32c(u32) | magic(8)`，
+/// 其中CRC32C覆盖段目录和它之前的文件尾字段。未知编号的段在读取时忽略，便于以后增加新的段。
+///
+/// 没有魔数的文件按基线版本（v0）的布局读取：`min_ts | max_ts | len(u32) | 数据块`，整个文件只有一个
+/// 使用基线比特流的Gorilla块，没有校验和、墓碑和序列号，数据归入[`SeriesKey::legacy`]序列。
 pub struct SSTable {
     pub path: PathBuf,
     mmap: Option<Mmap>, // 内存映射用于零拷贝
-    min_ts: Timestamp,  // 文件中的最小时间戳
-    max_ts: Timestamp,  // 文件中的最大时间戳
+    version: u32,
+    min_ts: Timestamp,  // 文件中数据点的最小时间戳
+    max_ts: Timestamp,  // 文件中数据点的最大时间戳
+    point_count: u64,
+    created_at: Option<u64>, // 创建时间（unix秒），v0文件没有
+    seq: u64,                // 序列号，来自刷盘的MemTable或压缩的输入文件
+    series: BTreeMap<SeriesKey, Vec<BlockHandle>>,
+    tombstones: Vec<Tombstone>,
+    sketches: HashMap<usize, Range<usize>>, // 块偏移到草图在文件中的位置
+}
+
+/// 从文件中解析出的元数据和索引
+struct Contents {
+    version: u32,
+    min_ts: Timestamp,
+    max_ts: Timestamp,
+    point_count: u64,
+    created_at: Option<u64>,
+    seq: u64,
+    series: BTreeMap<SeriesKey, Vec<BlockHandle>>,
+    tombstones: Vec<Tombstone>,
+    sketches: HashMap<usize, Range<usize>>,
 }
 
 impl SSTable {
-    /// 创建新的SSTable文件，使用Gorilla压缩数据
-    pub fn create(dir: &str, data: &BTreeMap<Timestamp, Value>) -> Result<Self> {
-        fs::create_dir_all(dir)?;
-        let file_id = chrono::Utc::now().timestamp();
-        let path = Path::new(dir).join(format!("sstable-{}.db", file_id));
-        
-        // 获取最小和最大时间戳
-        let min_ts = *data.keys().next().unwrap_or(&0);
-        let max_ts = *data.keys().next_back().unwrap_or(&0);
-        
-        // 将数据转换为TimeSeriesBlock
-        let mut block = TimeSeriesBlock::new();
-        for (&ts, &val) in data.iter() {
-            block.add_point(ts, val);
-        }
-        
-        // 压缩数据
-        let compressed_data = block.compress()?;
-        
-        // 写入文件
-        let mut file = BufWriter::new(File::create(&path)?);
-        
-        // 写入元数据：最小TS、最大TS
-        file.write_all(&min_ts.to_le_bytes())?;
-        file.write_all(&max_ts.to_le_bytes())?;
-        
-        // 写入压缩数据长度和数据
-        let compressed_len = compressed_data.len() as u32;
-        file.write_all(&compressed_len.to_le_bytes())?;
-        file.write_all(&compressed_data)?;
+    /// 在指定路径创建SSTable文件，每个序列按`block_points`个点切分为多个Gorilla块
+    ///
+    /// 先写入`<path>.tmp`并fsync，再重命名为目标文件并fsync目录，
+    /// 因此目标路径上出现的文件总是完整的。
+    pub fn create(path: PathBuf, data: &MemTable, block_points: usize) -> Result<Self> {
+        let dir = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let block_points = block_points.max(1);
+
+        let tmp_path = path.with_extension("db.tmp");
+        let mut file = BufWriter::new(File::create(&tmp_path)?);
+        file.write_all(MAGIC)?;
+        file.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        let mut offset = HEADER_SIZE;
+        let mut index: Vec<(&SeriesKey, Vec<BlockHandle>)> = Vec::new();
+        // 块草图段和数据块一起生成，开头的块数最后填入
+        let mut sketch_section = vec![0; 4];
+        let mut point_count = 0;
+
+        // 写入数据块
+        for (series, points) in data.iter() {
+            if points.is_empty() {
+                continue;
+            }
+
+            let points: Vec<(Timestamp, Value)> = points.iter().map(|(&ts, &val)| (ts, val)).collect();
+            let mut handles = Vec::new();
+            for chunk in points.chunks(block_points) {
+                let mut block = TimeSeriesBlock::new();
+                block.add_points(chunk);
+                let compressed_data = block.compress()?;
+                file.write_all(&compressed_data)?;
+
+                handles.push(BlockHandle {
+                    min_ts: chunk[0].0,
+                    max_ts: chunk[chunk.len() - 1].0,
+                    offset,
+                    len: compressed_data.len(),
+                    crc: Some(crc32c::crc32c(&compressed_data)),
+                    stats: Some(BlockStats::from_points(chunk)),
+                });
+
+                let mut sketch = DDSketch::new();
+                chunk.iter().for_each(|&(_, value)| sketch.add(value));
+                let mut encoded = Vec::new();
+                sketch.encode(&mut encoded);
+                sketch_section.extend_from_slice(&(offset as u64).to_le_bytes());
+                sketch_section.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+                sketch_section.extend_from_slice(&encoded);
+                offset += compressed_data.len();
+            }
+
+            point_count += points.len();
+            index.push((series, handles));
+        }
+        let compressed_size = offset - HEADER_SIZE;
+
+        // 索引段
+        let mut index_section = Vec::new();
+        index_section.extend_from_slice(&(index.len() as u32).to_le_bytes());
+        for (series, handles) in &index {
+            write_key(&mut index_section, &series.to_string())?;
+            index_section.extend_from_slice(&(handles.len() as u32).to_le_bytes());
+            for h in handles {
+                index_section.extend_from_slice(&h.min_ts.to_le_bytes());
+                index_section.extend_from_slice(&h.max_ts.to_le_bytes());
+                index_section.extend_from_slice(&(h.offset as u64).to_le_bytes());
+                index_section.extend_from_slice(&(h.len as u32).to_le_bytes());
+                index_section.extend_from_slice(&h.crc.unwrap_or(0).to_le_bytes());
+            }
+        }
+
+        // 墓碑段
+        let mut tombstone_section = Vec::new();
+        tombstone_section.extend_from_slice(&(data.tombstones().len() as u32).to_le_bytes());
+        for t in data.tombstones() {
+            write_key(&mut tombstone_section, &t.series.as_ref().map(|s| s.to_string()).unwrap_or_default())?;
+            tombstone_section.extend_from_slice(&t.start.to_le_bytes());
+            tombstone_section.extend_from_slice(&t.end.to_le_bytes());
+        }
+
+        // 块统计段
+        let mut stats_section = Vec::new();
+        let blocks: Vec<&BlockHandle> = index.iter().flat_map(|(_, handles)| handles).collect();
+        stats_section.extend_from_slice(&(blocks.len() as u32).to_le_bytes());
+        for h in blocks {
+            let stats = h.stats.expect("新写入的块都有统计");
+            stats_section.extend_from_slice(&(h.offset as u64).to_le_bytes());
+            stats_section.extend_from_slice(&stats.count.to_le_bytes());
+            for field in [stats.min, stats.max, stats.sum, stats.first, stats.last] {
+                stats_section.extend_from_slice(&field.to_le_bytes());
+            }
+        }
+
+        let block_count = index.iter().map(|(_, handles)| handles.len()).sum::<usize>() as u32;
+        sketch_section[..4].copy_from_slice(&block_count.to_le_bytes());
+
+        // 写入各段和段目录
+        let mut directory = Vec::new();
+        let sections = [
+            (SECTION_INDEX, index_section),
+            (SECTION_TOMBSTONES, tombstone_section),
+            (SECTION_SEQUENCE, data.seq().to_le_bytes().to_vec()),
+            (SECTION_BLOCK_STATS, stats_section),
+            (SECTION_BLOCK_SKETCHES, sketch_section),
+        ];
+        directory.extend_from_slice(&(sections.len() as u32).to_le_bytes());
+        for (id, section) in &sections {
+            file.write_all(section)?;
+            directory.extend_from_slice(&id.to_le_bytes());
+            directory.extend_from_slice(&(offset as u64).to_le_bytes());
+            directory.extend_from_slice(&(section.len() as u64).to_le_bytes());
+            directory.extend_from_slice(&crc32c::crc32c(section).to_le_bytes());
+            offset += section.len();
+        }
+        let dir_offset = offset as u64;
+
+        // 文件尾
+        let min_ts = index.iter().map(|(_, h)| h[0].min_ts).min().unwrap_or(0);
+        let max_ts = index.iter().map(|(_, h)| h[h.len() - 1].max_ts).max().unwrap_or(0);
+        let created_at = chrono::Utc::now().timestamp() as u64;
+        let mut footer = directory;
+        for field in [dir_offset, min_ts, max_ts, point_count as u64, created_at] {
+            footer.extend_from_slice(&field.to_le_bytes());
+        }
+        footer.extend_from_slice(&[CODEC_GORILLA, 0, 0, 0]);
+        footer.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        footer.extend_from_slice(&crc32c::crc32c(&footer).to_le_bytes());
+        footer.extend_from_slice(MAGIC);
+        file.write_all(&footer)?;
+
         file.flush()?;
-        
-        let original_size = data.len() * 16; // 每条记录16字节(8字节ts + 8字节value)
+        file.get_ref().sync_all()?;
+        drop(file);
+        fs::rename(&tmp_path, &path)?;
+        sync_dir(&dir)?;
+
+        let original_size = point_count * 16; // 每条记录16字节(8字节ts + 8字节value)
         let compression_ratio = if original_size > 0 {
-            compressed_data.len() as f64 / original_size as f64
+            compressed_size as f64 / original_size as f64
         } else {
             0.0
         };
-        
-        info!("生成压缩SSTable文件: {:?}, 压缩率: {:.2}, 原始大小: {}字节, 压缩后: {}字节", 
-              path, compression_ratio, original_size, compressed_data.len());
-        
-        let sstable = SSTable {
-            path,
-            mmap: None,
-            min_ts,
-            max_ts,
-        };
-        
-        Ok(sstable)
+
+        info!("生成压缩SSTable文件: {:?}, {} 个序列, {} 个墓碑, 压缩率: {:.2}, 原始大小: {}字节, 压缩后: {}字节",
+              path, index.len(), data.tombstones().len(), compression_ratio, original_size, compressed_size);
+
+        SSTable::open(path)
     }
-    
+
     /// 打开现有的SSTable文件，使用内存映射实现零拷贝访问
+    ///
+    /// 只把块索引读入内存，数据块在查询时直接从内存映射中解码。
+    /// 打开时校验文件头、文件尾和各段的CRC32C，数据块的CRC32C在解码时校验。
     pub fn open(path: PathBuf) -> Result<Self> {
         let file = File::open(&path)?;
-        
-        // 读取元数据
-        let mut reader = io::BufReader::new(&file);
-        let mut buf = [0u8; 8];
-        
-        reader.read_exact(&mut buf)?;
-        let min_ts = u64::from_le_bytes(buf);
-        
-        reader.read_exact(&mut buf)?;
-        let max_ts = u64::from_le_bytes(buf);
-        
+
         // 使用内存映射实现零拷贝
         let mmap = unsafe { MmapOptions::new().map(&file)? };
-        
-        info!("打开SSTable文件: {:?}, 时间范围: [{}, {}]", path, min_ts, max_ts);
-        
+        let is_v1 = mmap.len() >= HEADER_SIZE + FOOTER_SIZE
+            && mmap.starts_with(MAGIC)
+            && mmap.ends_with(MAGIC);
+        let contents = if is_v1 {
+            read_v1(&mmap)
+        } else {
+            read_v0(&mmap)
+        }
+        .map_err(|e| Error::DataError(format!("SSTable文件 {:?} 损坏: {}", path, e)))?;
+
+        info!("打开SSTable文件: {:?}, 格式v{}, {} 个序列, {} 个墓碑, {} 个数据点, 时间范围: [{}, {}]",
+              path, contents.version, contents.series.len(), contents.tombstones.len(),
+              contents.point_count, contents.min_ts, contents.max_ts);
+
         Ok(SSTable {
             path,
             mmap: Some(mmap),
-            min_ts,
-            max_ts,
+            version: contents.version,
+            min_ts: contents.min_ts,
+            max_ts: contents.max_ts,
+            point_count: contents.point_count,
+            created_at: contents.created_at,
+            seq: contents.seq,
+            series: contents.series,
+            tombstones: contents.tombstones,
+            sketches: contents.sketches,
         })
     }
-    
+
     /// 判断查询区间是否与当前文件有交集
     pub fn may_contain(&self, start: Timestamp, end: Timestamp) -> bool {
         !(end < self.min_ts || start > self.max_ts)
     }
 
-    /// 查询区间数据，使用零拷贝技术
-    pub fn query(&self, start: Timestamp, end: Timestamp) -> Result<Vec<(Timestamp, Value)>> {
+    /// 数据点和墓碑覆盖的整个时间范围，既没有数据也没有墓碑时返回None
+    pub fn time_span(&self) -> Option<(Timestamp, Timestamp)> {
+        let data = (!self.series.is_empty()).then_some((self.min_ts, self.max_ts));
+        self.tombstones.iter().fold(data, |span, t| match span {
+            Some((lo, hi)) => Some((lo.min(t.start), hi.max(t.end))),
+            None => Some((t.start, t.end)),
+        })
+    }
+
+    /// 判断两个文件的时间范围（包括墓碑的删除范围）是否有交集
+    pub fn overlaps(&self, other: &SSTable) -> bool {
+        match (self.time_span(), other.time_span()) {
+            (Some((lo, hi)), Some((o_lo, o_hi))) => !(o_hi < lo || o_lo > hi),
+            _ => false,
+        }
+    }
+
+    /// 文件名，清单中按文件名记录SSTable
+    pub fn file_name(&self) -> Option<String> {
+        self.path.file_name()?.to_str().map(|name| name.to_string())
+    }
+
+    /// 文件格式版本，旧文件为0
+    pub fn format_version(&self) -> u32 {
+        self.version
+    }
+
+    /// 文件中的数据点总数
+    pub fn point_count(&self) -> u64 {
+        self.point_count
+    }
+
+    /// 文件的创建时间（unix秒），v0文件没有记录
+    pub fn created_at(&self) -> Option<u64> {
+        self.created_at
+    }
+
+    /// 序列号，同一时间戳的数据以序列号大的文件为准
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn min_ts(&self) -> Timestamp {
+        self.min_ts
+    }
+
+    pub fn max_ts(&self) -> Timestamp {
+        self.max_ts
+    }
+
+    /// 文件大小（字节）
+    pub fn file_size(&self) -> u64 {
+        self.mmap.as_ref().map_or(0, |m| m.len() as u64)
+    }
+
+    /// 文件中包含的所有序列
+    pub fn series_keys(&self) -> impl Iterator<Item = &SeriesKey> {
+        self.series.keys()
+    }
+
+    /// 文件中的删除标记，只作用于比本文件更早的数据
+    pub fn tombstones(&self) -> &[Tombstone] {
+        &self.tombstones
+    }
+
+    /// 判断文件中是否可能有被墓碑覆盖的数据
+    pub fn may_contain_tombstone(&self, tombstone: &Tombstone) -> bool {
+        if !self.may_contain(tombstone.start, tombstone.end) {
+            return false;
+        }
+        match &tombstone.series {
+            Some(series) => !self.overlapping_blocks(series, tombstone.start, tombstone.end).is_empty(),
+            None => true,
+        }
+    }
+
+    /// 指定序列中与查询区间有交集的块，在稀疏索引上二分查找
+    pub fn overlapping_blocks(&self, series: &SeriesKey, start: Timestamp, end: Timestamp) -> &[BlockHandle] {
+        let handles = match self.series.get(series) {
+            Some(h) => h.as_slice(),
+            None => return &[],
+        };
+        // 同一序列的块按时间有序且互不重叠
+        let first = handles.partition_point(|h| h.max_ts < start);
+        let last = handles.partition_point(|h| h.min_ts <= end);
+        if first >= last {
+            return &[];
+        }
+        &handles[first..last]
+    }
+
+    /// 查询指定序列的区间数据，只解码有交集的块
+    pub fn query(&self, series: &SeriesKey, start: Timestamp, end: Timestamp) -> Result<Vec<(Timestamp, Value)>> {
         let mut results = Vec::new();
-        
-        // 检查区间是否有交集
-        if !self.may_contain(start, end) {
-            return Ok(results);
+        let blocks = self.overlapping_blocks(series, start, end);
+        for handle in blocks {
+            self.decode_block(handle, start, end, &mut results)?;
+        }
+
+        debug!("SSTable查询 {:?} series={} 解码 {} 个块, 返回 {} 条数据",
+               self.path, series, blocks.len(), results.len());
+        Ok(results)
+    }
+
+    /// 按时间顺序逐块惰性解码指定序列的区间数据，迭代器持有SSTable的引用，不受文件列表变化影响
+    pub fn iter(self: &Arc<Self>, series: &SeriesKey, start: Timestamp, end: Timestamp) -> BlockIter {
+        self.iter_blocks(self.overlapping_blocks(series, start, end).to_vec(), start, end)
+    }
+
+    /// 只解码给定的块，块须属于同一序列并按时间排列，用于跳过已用统计信息聚合的块
+    pub fn iter_blocks(self: &Arc<Self>, handles: Vec<BlockHandle>, start: Timestamp, end: Timestamp) -> BlockIter {
+        BlockIter {
+            sst: Arc::clone(self),
+            handles: handles.into_iter(),
+            block: None,
+            start,
+            end,
+            done: false,
+        }
+    }
+
+    /// 按时间倒序逐块解码指定的块，块须属于同一序列并按时间排列
+    ///
+    /// Gorilla块只能顺序解码，从最后一个块开始每次解码一整块再倒序给出。
+    pub fn iter_blocks_rev(self: &Arc<Self>, handles: Vec<BlockHandle>, start: Timestamp, end: Timestamp) -> ReverseBlockIter {
+        ReverseBlockIter {
+            sst: Arc::clone(self),
+            handles: handles.into_iter(),
+            points: Vec::new(),
+            start,
+            end,
+            done: false,
+        }
+    }
+
+    /// 直接从内存映射解码一个块，只保留区间内的点，超过区间终点后提前结束
+    fn decode_block(
+        &self,
+        handle: &BlockHandle,
+        start: Timestamp,
+        end: Timestamp,
+        out: &mut Vec<(Timestamp, Value)>,
+    ) -> Result<()> {
+        // 零拷贝方式访问压缩数据 - 直接从内存映射中读取，不复制
+        let data = self.block_data(handle)?;
+        if self.version == 0 {
+            let block = TimeSeriesBlock::decompress_baseline(data, handle.min_ts, handle.max_ts).map_err(corrupted)?;
+            out.extend(block.query(start, end));
+            return Ok(());
+        }
+
+        // 块以点数开头，按点数解码，避免把末尾填充位当作数据
+        let count = u32::from_le_bytes(data[0..4].try_into().unwrap()) as usize;
+        if count == 0 {
+            return Ok(());
         }
-        
-        // 懒加载mmap，如果没有初始化，则进行加载
+        let mut decoder = GorillaDecoder::new(&data[4..]).map_err(corrupted)?;
+        for _ in 0..count {
+            match decoder.decode().map_err(corrupted)? {
+                Some((ts, _)) if ts < start => continue,
+                Some((ts, _)) if ts > end => break,
+                Some(point) => out.push(point),
+                None => return Err(count_mismatch()),
+            }
+        }
+        Ok(())
+    }
+
+    /// 块的分位数草图，没有块草图段的文件返回None
+    pub fn block_sketch(&self, handle: &BlockHandle) -> Result<Option<DDSketch>> {
+        let (Some(mmap), Some(range)) = (&self.mmap, self.sketches.get(&handle.offset)) else {
+            return Ok(None);
+        };
+        DDSketch::decode(&mmap[range.clone()])
+            .map(Some)
+            .map_err(|e| Error::DataError(format!("SSTable {:?} 偏移 {} 处的块草图损坏: {}", self.path, handle.offset, e)))
+    }
+
+    /// 取出一个块的压缩数据并校验CRC32C
+    fn block_data(&self, handle: &BlockHandle) -> Result<&[u8]> {
         let mmap = match &self.mmap {
             Some(m) => m,
             None => {
@@ -123,34 +516,613 @@ impl SSTable {
                 ));
             }
         };
-        
-        if mmap.len() < 20 {
-            return Err(Error::DataError("SSTable文件格式错误".to_string()));
-        }
-        
-        // 读取元数据和压缩数据
-        let _min_ts = u64::from_le_bytes(mmap[0..8].try_into().unwrap());
-        let _max_ts = u64::from_le_bytes(mmap[8..16].try_into().unwrap());
-        let compressed_len = u32::from_le_bytes(mmap[16..20].try_into().unwrap()) as usize;
-        
-        if 20 + compressed_len > mmap.len() {
-            return Err(Error::DataError("压缩数据长度超出文件大小".to_string()));
-        }
-        
-        // 零拷贝方式访问压缩数据 - 直接从内存映射中读取，不复制
-        let compressed_data = &mmap[20..20 + compressed_len];
-        
-        // 解压并查询
-        let block = match TimeSeriesBlock::decompress(compressed_data) {
-            Ok(b) => b,
-            Err(e) => return Err(Error::CompressionError(format!("解压失败: {}", e))),
+
+        let data = &mmap[handle.offset..handle.offset + handle.len];
+        if handle.crc.is_some_and(|crc| crc != crc32c::crc32c(data)) {
+            return Err(Error::DataError(format!(
+                "SSTable {:?} 偏移 {} 处的数据块校验和不匹配",
+                self.path, handle.offset
+            )));
+        }
+        if data.len() < 4 {
+            return Err(Error::CompressionError("压缩块长度不足".to_string()));
+        }
+        Ok(data)
+    }
+}
+
+/// 单个SSTable中一个序列的区间数据迭代器，每次只解码一个块
+pub struct BlockIter {
+    sst: Arc<SSTable>,
+    handles: std::vec::IntoIter<BlockHandle>,
+    block: Option<OpenBlock>,
+    start: Timestamp,
+    end: Timestamp,
+    done: bool,
+}
+
+/// 正在解码的块
+enum OpenBlock {
+    /// 逐点解码，remaining为块中尚未解码的点数
+    Stream {
+        decoder: GorillaDecoder<BlockReader>,
+        remaining: usize,
+    },
+    /// 基线比特流只能整块解码，这里是区间内的点
+    Decoded(std::vec::IntoIter<(Timestamp, Value)>),
+}
+
+/// 从内存映射中读取一个块的压缩数据，持有SSTable的引用以便迭代器独立于文件列表存在
+struct BlockReader {
+    sst: Arc<SSTable>,
+    pos: usize,
+    end: usize,
+}
+
+impl io::Read for BlockReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = match &self.sst.mmap {
+            Some(m) => &m[self.pos..self.end],
+            None => &[][..],
         };
-        
-        // 查询指定区间
-        results = block.query(start, end);
-        
-        debug!("SSTable查询 {:?} 返回 {} 条数据", self.path, results.len());
-        Ok(results)
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        self.pos += n;
+        Ok(n)
     }
 }
 
+impl BlockIter {
+    /// 打开下一个块，校验失败时返回错误
+    fn open_block(&self, handle: &BlockHandle) -> Result<OpenBlock> {
+        if self.sst.version == 0 {
+            let mut points = Vec::new();
+            self.sst.decode_block(handle, self.start, self.end, &mut points)?;
+            return Ok(OpenBlock::Decoded(points.into_iter()));
+        }
+        let data = self.sst.block_data(handle)?;
+        let remaining = u32::from_le_bytes(data[0..4].try_into().unwrap()) as usize;
+        let reader = BlockReader {
+            sst: Arc::clone(&self.sst),
+            pos: handle.offset + 4,
+            end: handle.offset + handle.len,
+        };
+        let decoder = GorillaDecoder::new(reader).map_err(corrupted)?;
+        Ok(OpenBlock::Stream { decoder, remaining })
+    }
+
+    fn next_point(&mut self) -> Result<Option<(Timestamp, Value)>> {
+        loop {
+            let Some(block) = &mut self.block else {
+                let Some(handle) = self.handles.next() else {
+                    return Ok(None);
+                };
+                if handle.min_ts > self.end {
+                    return Ok(None);
+                }
+                self.block = Some(self.open_block(&handle)?);
+                continue;
+            };
+            let next = match block {
+                OpenBlock::Stream { remaining: 0, .. } => None,
+                OpenBlock::Stream { decoder, remaining } => {
+                    *remaining -= 1;
+                    Some(decoder.decode().map_err(corrupted)?.ok_or_else(count_mismatch)?)
+                }
+                OpenBlock::Decoded(points) => points.next(),
+            };
+            match next {
+                None => self.block = None,
+                Some((ts, _)) if ts < self.start => continue,
+                // 块按时间排列且互不重叠，超过终点后后面的块也不用再读
+                Some((ts, _)) if ts > self.end => return Ok(None),
+                Some(point) => return Ok(Some(point)),
+            }
+        }
+    }
+}
+
+impl Iterator for BlockIter {
+    type Item = Result<(Timestamp, Value)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let next = self.next_point().transpose();
+        // 读完或出错后不再继续解码
+        if !matches!(next, Some(Ok(_))) {
+            self.done = true;
+            self.block = None;
+        }
+        next
+    }
+}
+
+/// 单个SSTable中一个序列的倒序迭代器，每次只解码一个块
+pub struct ReverseBlockIter {
+    sst: Arc<SSTable>,
+    handles: std::vec::IntoIter<BlockHandle>,
+    // 当前块中区间内尚未给出的点，按时间升序，从末尾取出
+    points: Vec<(Timestamp, Value)>,
+    start: Timestamp,
+    end: Timestamp,
+    done: bool,
+}
+
+impl Iterator for ReverseBlockIter {
+    type Item = Result<(Timestamp, Value)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        loop {
+            if let Some(point) = self.points.pop() {
+                return Some(Ok(point));
+            }
+            match self.handles.next_back() {
+                Some(handle) if handle.max_ts >= self.start => {
+                    if let Err(e) = self.sst.decode_block(&handle, self.start, self.end, &mut self.points) {
+                        self.done = true;
+                        return Some(Err(e));
+                    }
+                }
+                _ => {
+                    self.done = true;
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+fn corrupted(e: io::Error) -> Error {
+    Error::CompressionError(format!("解压失败: {}", e))
+}
+
+fn count_mismatch() -> Error {
+    Error::CompressionError("压缩块点数与记录不符".to_string())
+}
+
+fn write_key<W: Write>(w: &mut W, key: &str) -> Result<()> {
+    let key_len = u16::try_from(key.len())
+        .map_err(|_| Error::SeriesError(format!("序列键过长: {} 字节", key.len())))?;
+    w.write_all(&key_len.to_le_bytes())?;
+    w.write_all(key.as_bytes())?;
+    Ok(())
+}
+
+/// 读取`key_len(u16) | key`，超出`limit`时返回None
+fn read_key<'a>(buf: &'a [u8], pos: &mut usize, limit: usize) -> Option<&'a str> {
+    if *pos + 2 > limit {
+        return None;
+    }
+    let key_len = u16::from_le_bytes(buf[*pos..*pos + 2].try_into().unwrap()) as usize;
+    if *pos + 2 + key_len > limit {
+        return None;
+    }
+    let key = std::str::from_utf8(&buf[*pos + 2..*pos + 2 + key_len]).ok()?;
+    *pos += 2 + key_len;
+    Some(key)
+}
+
+fn truncated() -> Error {
+    Error::DataError("SSTable索引超出范围".to_string())
+}
+
+/// `start`之后`count`个`size`字节的条目的结束位置，溢出时返回None，调用方按越界处理
+fn checked_end(start: usize, count: usize, size: usize) -> Option<usize> {
+    count.checked_mul(size)?.checked_add(start)
+}
+
+/// 按v1布局读取：校验文件头、文件尾和段目录，再解析各段
+fn read_v1(mmap: &[u8]) -> Result<Contents> {
+    let version = read_u32(mmap, MAGIC.len());
+    if version == 0 || version > FORMAT_VERSION {
+        return Err(Error::DataError(format!("不支持的SSTable格式版本: {}", version)));
+    }
+
+    let footer = mmap.len() - FOOTER_SIZE;
+    let dir_offset = read_u64(mmap, footer) as usize;
+    let min_ts = read_u64(mmap, footer + 8);
+    let max_ts = read_u64(mmap, footer + 16);
+    let point_count = read_u64(mmap, footer + 24);
+    let created_at = read_u64(mmap, footer + 32);
+    let codec = mmap[footer + 40];
+    let footer_version = read_u32(mmap, footer + 44);
+    let crc = read_u32(mmap, footer + 48);
+    if dir_offset < HEADER_SIZE || dir_offset > footer {
+        return Err(Error::DataError(format!("段目录偏移无效: {}", dir_offset)));
+    }
+    if crc32c::crc32c(&mmap[dir_offset..footer + 48]) != crc {
+        return Err(Error::DataError("文件尾校验和不匹配".to_string()));
+    }
+    if footer_version != version {
+        return Err(Error::DataError(format!("文件头版本 {} 与文件尾版本 {} 不一致", version, footer_version)));
+    }
+    if codec != CODEC_GORILLA {
+        return Err(Error::DataError(format!("未知的数据块编码: {}", codec)));
+    }
+
+    // 读取段目录并校验每个段
+    if dir_offset + 4 > footer {
+        return Err(truncated());
+    }
+    let section_count = read_u32(mmap, dir_offset) as usize;
+    if checked_end(dir_offset + 4, section_count, SECTION_ENTRY_SIZE).is_none_or(|end| end > footer) {
+        return Err(truncated());
+    }
+    let mut sections = BTreeMap::new();
+    let mut section_offsets = HashMap::new();
+    let mut data_end = dir_offset;
+    for i in 0..section_count {
+        let entry = dir_offset + 4 + i * SECTION_ENTRY_SIZE;
+        let id = read_u32(mmap, entry);
+        let offset = read_u64(mmap, entry + 4) as usize;
+        let len = read_u64(mmap, entry + 12) as usize;
+        if offset < HEADER_SIZE || offset.checked_add(len).is_none_or(|end| end > dir_offset) {
+            return Err(Error::DataError(format!("段 {} 超出文件范围", id)));
+        }
+        let section = &mmap[offset..offset + len];
+        if crc32c::crc32c(section) != read_u32(mmap, entry + 20) {
+            return Err(Error::DataError(format!("段 {} 校验和不匹配", id)));
+        }
+        data_end = data_end.min(offset);
+        sections.insert(id, section);
+        section_offsets.insert(id, offset);
+    }
+
+    let index = sections
+        .get(&SECTION_INDEX)
+        .ok_or_else(|| Error::DataError("缺少索引段".to_string()))?;
+    let mut series = read_index(index, data_end)?;
+    if let Some(section) = sections.get(&SECTION_BLOCK_STATS) {
+        read_block_stats(section, &mut series)?;
+    }
+    let tombstones = match sections.get(&SECTION_TOMBSTONES) {
+        Some(section) => read_tombstones(section)?,
+        None => Vec::new(),
+    };
+    let seq = match sections.get(&SECTION_SEQUENCE) {
+        Some(section) if section.len() == 8 => read_u64(section, 0),
+        Some(_) => return Err(Error::DataError("序列号段长度无效".to_string())),
+        None => 0,
+    };
+    let sketches = match sections.get(&SECTION_BLOCK_SKETCHES) {
+        Some(section) => read_block_sketches(section, section_offsets[&SECTION_BLOCK_SKETCHES])?,
+        None => HashMap::new(),
+    };
+
+    Ok(Contents {
+        version,
+        min_ts,
+        max_ts,
+        point_count,
+        created_at: Some(created_at),
+        seq,
+        series,
+        tombstones,
+        sketches,
+    })
+}
+
+/// 按基线版本的布局读取：文件头之后是唯一的数据块，数据块以点数开头
+fn read_v0(mmap: &[u8]) -> Result<Contents> {
+    if mmap.len() < V0_HEADER_SIZE {
+        return Err(Error::DataError("文件过短".to_string()));
+    }
+    let min_ts = read_u64(mmap, 0);
+    let max_ts = read_u64(mmap, 8);
+    let len = read_u32(mmap, 16) as usize;
+    if len < 4 || V0_HEADER_SIZE + len > mmap.len() {
+        return Err(Error::DataError("压缩数据长度超出文件大小".to_string()));
+    }
+
+    let point_count = read_u32(mmap, V0_HEADER_SIZE) as u64;
+    let mut series = BTreeMap::new();
+    if point_count > 0 {
+        if len < 12 {
+            return Err(Error::CompressionError("压缩块长度不足".to_string()));
+        }
+        // 基线的发布构建写64位字段时移位溢出，首个时间戳和数值被写成零，所有数值都无法还原
+        if read_u64(mmap, V0_HEADER_SIZE + 4) != min_ts {
+            return Err(Error::DataError(format!(
+                "首个数据点与文件头的最小时间戳 {} 不一致，基线版本写入时丢失了首个数据点，无法恢复",
+                min_ts
+            )));
+        }
+        let handle = BlockHandle { min_ts, max_ts, offset: V0_HEADER_SIZE, len, crc: None, stats: None };
+        series.insert(SeriesKey::legacy(), vec![handle]);
+    }
+
+    Ok(Contents {
+        version: 0,
+        min_ts,
+        max_ts,
+        point_count,
+        created_at: None,
+        seq: 0,
+        series,
+        tombstones: Vec::new(),
+        sketches: HashMap::new(),
+    })
+}
+
+/// 解析索引，块的数据必须位于`data_end`之前
+fn read_index(buf: &[u8], data_end: usize) -> Result<BTreeMap<SeriesKey, Vec<BlockHandle>>> {
+    let limit = buf.len();
+    if limit < 4 {
+        return Err(truncated());
+    }
+    let series_count = read_u32(buf, 0) as usize;
+    let mut pos = 4;
+    let mut series = BTreeMap::new();
+    for _ in 0..series_count {
+        let key = read_key(buf, &mut pos, limit).ok_or_else(truncated)?;
+        let key = SeriesKey::parse(key)?;
+
+        if pos + 4 > limit {
+            return Err(truncated());
+        }
+        let block_count = read_u32(buf, pos) as usize;
+        pos += 4;
+        if checked_end(pos, block_count, BLOCK_HANDLE_SIZE).is_none_or(|end| end > limit) {
+            return Err(truncated());
+        }
+
+        let mut handles = Vec::with_capacity(block_count);
+        for _ in 0..block_count {
+            let handle = BlockHandle {
+                min_ts: read_u64(buf, pos),
+                max_ts: read_u64(buf, pos + 8),
+                offset: read_u64(buf, pos + 16) as usize,
+                len: read_u32(buf, pos + 24) as usize,
+                crc: Some(read_u32(buf, pos + 28)),
+                stats: None,
+            };
+            if handle.offset.checked_add(handle.len).is_none_or(|end| end > data_end) {
+                return Err(Error::DataError("压缩数据长度超出文件大小".to_string()));
+            }
+            handles.push(handle);
+            pos += BLOCK_HANDLE_SIZE;
+        }
+        series.insert(key, handles);
+    }
+    Ok(series)
+}
+
+/// 读取块统计段，按偏移把统计挂到对应的块上
+fn read_block_stats(buf: &[u8], series: &mut BTreeMap<SeriesKey, Vec<BlockHandle>>) -> Result<()> {
+    if buf.len() < 4 {
+        return Err(truncated());
+    }
+    let count = read_u32(buf, 0) as usize;
+    if checked_end(4, count, BLOCK_STATS_SIZE).is_none_or(|end| end > buf.len()) {
+        return Err(truncated());
+    }
+    let mut stats = HashMap::with_capacity(count);
+    for i in 0..count {
+        let pos = 4 + i * BLOCK_STATS_SIZE;
+        let field = |n: usize| f64::from_bits(read_u64(buf, pos + 16 + n * 8));
+        stats.insert(read_u64(buf, pos) as usize, BlockStats {
+            count: read_u64(buf, pos + 8),
+            min: field(0),
+            max: field(1),
+            sum: field(2),
+            first: field(3),
+            last: field(4),
+        });
+    }
+    for handle in series.values_mut().flatten() {
+        handle.stats = stats.get(&handle.offset).copied();
+    }
+    Ok(())
+}
+
+/// 读取块草图段，返回块偏移到草图在文件中位置的映射，`base`是段在文件中的偏移
+fn read_block_sketches(buf: &[u8], base: usize) -> Result<HashMap<usize, Range<usize>>> {
+    if buf.len() < 4 {
+        return Err(truncated());
+    }
+    let count = read_u32(buf, 0) as usize;
+    let mut pos = 4;
+    let mut sketches = HashMap::with_capacity(count.min(buf.len() / 12));
+    for _ in 0..count {
+        if pos + 12 > buf.len() {
+            return Err(truncated());
+        }
+        let offset = read_u64(buf, pos) as usize;
+        let len = read_u32(buf, pos + 8) as usize;
+        pos += 12;
+        if pos + len > buf.len() {
+            return Err(truncated());
+        }
+        sketches.insert(offset, base + pos..base + pos + len);
+        pos += len;
+    }
+    Ok(sketches)
+}
+
+fn read_tombstones(buf: &[u8]) -> Result<Vec<Tombstone>> {
+    let limit = buf.len();
+    if limit < 4 {
+        return Err(truncated());
+    }
+    let count = read_u32(buf, 0) as usize;
+    let mut pos = 4;
+    let mut tombstones = Vec::with_capacity(count.min(limit / 18));
+    for _ in 0..count {
+        let key = read_key(buf, &mut pos, limit).ok_or_else(truncated)?;
+        let series = if key.is_empty() { None } else { Some(SeriesKey::parse(key)?) };
+        if pos + 16 > limit {
+            return Err(truncated());
+        }
+        tombstones.push(Tombstone {
+            series,
+            start: read_u64(buf, pos),
+            end: read_u64(buf, pos + 8),
+        });
+        pos += 16;
+    }
+    Ok(tombstones)
+}
+
+fn read_u64(buf: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(buf[pos..pos + 8].try_into().unwrap())
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    #[test]
+    fn test_series_isolation() {
+        let dir = tempfile::tempdir().unwrap();
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+        let mem = SeriesKey::parse("mem,host=a").unwrap();
+
+        let mut data = MemTable::new();
+        for i in 0..100 {
+            data.insert(&cpu, 1000 + i * 10, i as f64);
+            data.insert(&mem, 1005 + i * 10, -(i as f64));
+        }
+        data.delete(Tombstone { series: Some(mem.clone()), start: 0, end: 999 });
+        data.delete(Tombstone { series: None, start: 5000, end: 6000 });
+
+        let sst = SSTable::create(dir.path().join("sstable-1.db"), &data, DEFAULT_BLOCK_POINTS).unwrap();
+        assert_eq!(sst.series_keys().count(), 2);
+
+        // 重新打开后两个序列互不干扰
+        let sst = SSTable::open(sst.path.clone()).unwrap();
+        assert_eq!(sst.tombstones().len(), 2);
+        assert_eq!(sst.tombstones()[0].series, Some(mem.clone()));
+        assert_eq!(sst.tombstones()[1].series, None);
+        let result = sst.query(&cpu, 1200, 1400).unwrap();
+        assert_eq!(result.len(), 21);
+        assert!(result.iter().all(|&(ts, v)| ts % 10 == 0 && v >= 0.0));
+
+        let result = sst.query(&mem, 0, u64::MAX).unwrap();
+        assert_eq!(result.len(), 100);
+        assert_eq!(result[0], (1005, 0.0));
+
+        let unknown = SeriesKey::parse("disk,host=a").unwrap();
+        assert!(sst.query(&unknown, 0, u64::MAX).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_multi_block_query() {
+        let dir = tempfile::tempdir().unwrap();
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+
+        let mut data = MemTable::new();
+        for i in 0..1000 {
+            data.insert(&cpu, 1000 + i * 10, i as f64);
+        }
+
+        // 每块100个点，共10块
+        let sst = SSTable::create(dir.path().join("sstable-1.db"), &data, 100).unwrap();
+        assert_eq!(sst.overlapping_blocks(&cpu, 0, u64::MAX).len(), 10);
+
+        // 跨越块边界的区间只涉及相邻的两个块
+        assert_eq!(sst.overlapping_blocks(&cpu, 1990, 2010).len(), 2);
+        let result = sst.query(&cpu, 1990, 2010).unwrap();
+        assert_eq!(result, vec![(1990, 99.0), (2000, 100.0), (2010, 101.0)]);
+
+        // 落在块之间空隙或数据范围之外的区间不解码任何块
+        assert!(sst.overlapping_blocks(&cpu, 1991, 1999).is_empty());
+        assert!(sst.overlapping_blocks(&cpu, 0, 999).is_empty());
+        assert!(sst.overlapping_blocks(&cpu, 11000, 12000).is_empty());
+
+        let result = sst.query(&cpu, 0, u64::MAX).unwrap();
+        assert_eq!(result.len(), 1000);
+        assert!(result.windows(2).all(|w| w[0].0 < w[1].0));
+    }
+
+    /// 基线版本写出的文件：`min_ts | max_ts | len | 点数 | Gorilla比特流`，
+    /// 数据为(1622000000, 1.5)、(1622000010, 2.5)、(1622000020, 2.5)
+    pub(crate) const BASELINE_FILE: [u8; 45] = [
+        128, 193, 173, 96, 0, 0, 0, 0, 148, 193, 173, 96, 0, 0, 0, 0, 25, 0, 0, 0, 3, 0, 0, 0,
+        128, 193, 173, 96, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 248, 63, 42, 134, 230, 255, 3,
+    ];
+
+    #[test]
+    fn test_read_baseline() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sstable-1622000000.db");
+        fs::write(&path, BASELINE_FILE).unwrap();
+
+        let legacy = SeriesKey::legacy();
+        let points = vec![(1622000000, 1.5), (1622000010, 2.5), (1622000020, 2.5)];
+        let sst = Arc::new(SSTable::open(path.clone()).unwrap());
+        assert_eq!(sst.format_version(), 0);
+        assert_eq!(sst.point_count(), 3);
+        assert_eq!(sst.created_at(), None);
+        assert_eq!(sst.seq(), 0);
+        assert!(sst.tombstones().is_empty());
+        assert_eq!(sst.series_keys().collect::<Vec<_>>(), vec![&legacy]);
+        assert_eq!(sst.query(&legacy, 0, u64::MAX).unwrap(), points);
+        let tail: Vec<_> = sst.iter(&legacy, 1622000005, u64::MAX).collect::<Result<_>>().unwrap();
+        assert_eq!(tail, points[1..]);
+        drop(sst);
+
+        // 压缩数据长度超出文件
+        fs::write(&path, &BASELINE_FILE[..40]).unwrap();
+        assert!(matches!(SSTable::open(path.clone()), Err(Error::DataError(_))));
+
+        // 基线的发布构建把首个数据点写成了零，数值无法恢复
+        let mut bytes = BASELINE_FILE;
+        bytes[24..40].fill(0);
+        fs::write(&path, bytes).unwrap();
+        assert!(matches!(SSTable::open(path), Err(Error::DataError(_))));
+    }
+
+    #[test]
+    fn test_checksums() {
+        let dir = tempfile::tempdir().unwrap();
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+        let mut data = MemTable::new();
+        for i in 0..200 {
+            data.insert(&cpu, 1000 + i * 10, i as f64);
+        }
+        let path = dir.path().join("sstable-1.db");
+        let sst = SSTable::create(path.clone(), &data, 100).unwrap();
+        assert_eq!(sst.format_version(), FORMAT_VERSION);
+        assert_eq!(sst.point_count(), 200);
+        assert!(sst.created_at().is_some());
+        let second_block = sst.overlapping_blocks(&cpu, 2000, 2000)[0];
+        drop(sst);
+        let original = fs::read(&path).unwrap();
+
+        // 数据块损坏时文件仍能打开，但查询到该块时报错，其它块不受影响
+        let mut bytes = original.clone();
+        bytes[second_block.offset + 8] ^= 0x10;
+        fs::write(&path, &bytes).unwrap();
+        let sst = SSTable::open(path.clone()).unwrap();
+        assert_eq!(sst.query(&cpu, 1000, 1990).unwrap().len(), 100);
+        assert!(sst.query(&cpu, 2000, 2000).is_err());
+        drop(sst);
+
+        // 索引段或文件尾损坏时拒绝打开
+        let dir_offset = read_u64(&original, original.len() - FOOTER_SIZE) as usize;
+        for pos in [dir_offset - 5, original.len() - FOOTER_SIZE + 8] {
+            let mut bytes = original.clone();
+            bytes[pos] ^= 0x01;
+            fs::write(&path, &bytes).unwrap();
+            assert!(SSTable::open(path.clone()).is_err());
+        }
+
+        // 段长度加偏移溢出时报错而不是崩溃，重新计算文件尾校验和使损坏只在段目录中
+        let mut bytes = original.clone();
+        let footer = bytes.len() - FOOTER_SIZE;
+        bytes[dir_offset + 4 + 12..dir_offset + 4 + 20].copy_from_slice(&u64::MAX.to_le_bytes());
+        let crc = crc32c::crc32c(&bytes[dir_offset..footer + 48]);
+        bytes[footer + 48..footer + 52].copy_from_slice(&crc.to_le_bytes());
+        fs::write(&path, &bytes).unwrap();
+        assert!(matches!(SSTable::open(path.clone()), Err(Error::DataError(_))));
+    }
+}
@@ -1,95 +1,655 @@
 use std::{
-    collections::BTreeMap,
     fs::{self, File, OpenOptions},
-    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
-    path::Path,
-    sync::Mutex,
+    io::{BufWriter, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Condvar, Mutex},
+    time::Duration,
 };
 
 use crate::error::{Error, Result};
-use log::{debug, error, info};
+use crate::manifest::sync_dir;
+use crate::memtable::{MemTable, Tombstone};
+use crate::series::SeriesKey;
+use log::{debug, info, warn};
 
 pub type Timestamp = u64;
 pub type Value = f64;
+/// 日志序列号，每条记录递增1
+pub type Lsn = u64;
+
+/// 写入记录类型
+const RECORD_PUT: u8 = 0;
+/// 范围删除记录类型
+const RECORD_DELETE: u8 = 1;
+
+/// 记录头：`crc32c(u32) | len(u32) | type(u8)`
+const HEADER_SIZE: usize = 9;
+/// 记录体的最大长度，超过时认为长度字段已损坏
+const MAX_BODY_SIZE: usize = 64 << 20;
+
+/// 基线版本的WAL文件，与段目录位于同一目录下
+const LEGACY_FILE: &str = "wal.log";
+/// 基线版本WAL的记录：`ts(u64) | value(f64)`，大端序，没有记录头
+const LEGACY_RECORD_SIZE: usize = 16;
+
+/// WAL回放结果
+#[derive(Debug, Clone, Default)]
+pub struct WalRecovery {
+    /// 校验通过并回放的记录数
+    pub recovered: usize,
+    /// 损坏的尾部中丢弃的记录数（按记录头中的长度估算）
+    pub discarded: usize,
+    /// 从文件尾部截掉的字节数
+    pub discarded_bytes: u64,
+}
+
+/// WAL的持久化方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WalSyncMode {
+    /// 只写入操作系统缓存，不主动fsync，进程崩溃不丢数据但断电可能丢
+    #[default]
+    None,
+    /// 每次写入后立即fdatasync
+    PerWrite,
+    /// 后台线程每隔固定时间fdatasync一次，最多丢失一个间隔内的写入
+    Interval(Duration),
+    /// 组提交：并发的写入者共享一次fdatasync，写入在落盘后才返回
+    GroupCommit,
+}
+
+impl std::fmt::Display for WalSyncMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WalSyncMode::None => write!(f, "none"),
+            WalSyncMode::PerWrite => write!(f, "fsync-per-write"),
+            WalSyncMode::Interval(d) => write!(f, "fsync-every-{}ms", d.as_millis()),
+            WalSyncMode::GroupCommit => write!(f, "group-commit"),
+        }
+    }
+}
 
 /// 写前日志，确保写入操作的持久化
+///
+/// 日志由目录下编号递增的段文件`wal-000001.log`组成，写入总是追加到最新的活跃段。
+/// 刷盘时先封存活跃段并切换到新段，等MemTable写成SSTable后再删除封存的段，
+/// 这样刷盘过程中的写入和崩溃都不会丢数据。
+///
+/// 每条记录带有记录头 `crc32c(u32) | len(u32) | type(u8)`，随后是`len`字节的记录体
+/// `key_len(u16) | series_key | a(u64) | b(u64)`，均为大端序；CRC32C覆盖长度、类型和记录体。
+/// 写入记录的`a | b`为`ts | value`；删除记录为`start | end`，作用于所有序列时`key_len`为0。
+///
+/// 崩溃可能留下写了一半的记录，加载时从第一条校验失败的记录处截断最新的段；
+/// 之后还有写入的段已经封存，其中的损坏不是写了一半的记录，加载时报错。
+///
+/// 追加记录返回它的LSN，调用方在释放其它锁之后调用`commit`，按`WalSyncMode`等待记录落盘。
+///
+/// 段目录旁边的基线版本`wal.log`在打开时转写到活跃段中后删除，其中的数据归入[`SeriesKey::legacy`]序列。
 pub struct Wal {
-    file: Mutex<BufWriter<File>>,
-    path: String,
+    dir: PathBuf,
+    mode: WalSyncMode,
+    active: Mutex<Segment>,
+    sync: Mutex<SyncState>,
+    synced: Condvar,
+}
+
+/// 正在写入的段
+struct Segment {
+    id: u64,
+    file: BufWriter<File>,
+    last_lsn: Lsn, // 已写入的最后一条记录的LSN，跨段连续
+}
+
+/// 组提交的状态：同一时间只有一个写入者（leader）执行fdatasync，其余写入者等待
+#[derive(Default)]
+struct SyncState {
+    synced_lsn: Lsn,
+    syncing: bool,
+    syncs: u64,
 }
 
 impl Wal {
-    pub fn open(path: &str) -> Result<Self> {
-        fs::create_dir_all(Path::new(path).parent().unwrap())?;
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(path)?;
-        info!("WAL 打开: {}", path);
-        Ok(Wal {
-            file: Mutex::new(BufWriter::new(file)),
-            path: path.to_string(),
-        })
+    /// 打开WAL目录，已有的段保留待回放，写入从一个新段开始
+    pub fn open(dir: &str, mode: WalSyncMode) -> Result<Self> {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(&dir)?;
+        let id = list_segments(&dir)?.last().map_or(1, |&(id, _)| id + 1);
+        let active = Segment::create(&dir, id, 0)?;
+        info!("WAL 打开: {:?}, 活跃段 {}, 持久化方式 {}", dir, id, mode);
+        let wal = Wal {
+            dir,
+            mode,
+            active: Mutex::new(active),
+            sync: Mutex::new(SyncState::default()),
+            synced: Condvar::new(),
+        };
+        wal.import_legacy()?;
+        Ok(wal)
     }
 
-    pub fn append(&self, ts: Timestamp, value: Value) -> Result<()> {
-        let mut file = self.file.lock().unwrap();
-        file.write_all(&ts.to_be_bytes())?;
-        file.write_all(&value.to_be_bytes())?;
-        file.flush()?;
-        debug!("WAL 追加写入 ts={}, value={}", ts, value);
-        Ok(())
-    }
+    /// 把基线版本的`wal.log`转写到活跃段并落盘，然后删除它
+    ///
+    /// 转写在打开时完成，此时还没有新的写入；删除前崩溃时下次打开会再转写一遍，重复的点写入相同的值。
+    /// 末尾不足一条记录的字节与基线版本加载时一样丢弃。
+    fn import_legacy(&self) -> Result<()> {
+        let path = self.dir.with_file_name(LEGACY_FILE);
+        let buf = match fs::read(&path) {
+            Ok(buf) => buf,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(Error::IoError(e)),
+        };
+
+        let points: Vec<(Timestamp, Value)> = buf
+            .chunks_exact(LEGACY_RECORD_SIZE)
+            .map(|r| {
+                let ts = Timestamp::from_be_bytes(r[0..8].try_into().unwrap());
+                let value = Value::from_be_bytes(r[8..16].try_into().unwrap());
+                (ts, value)
+            })
+            .collect();
+        let partial = buf.len() % LEGACY_RECORD_SIZE;
+        if partial > 0 {
+            warn!("基线版本的WAL {:?} 末尾有 {} 字节不完整的记录，已丢弃", path, partial);
+        }
 
-    pub fn batch_append(&self, data: &[(Timestamp, Value)]) -> Result<()> {
-        let mut file = self.file.lock().unwrap();
-        for &(ts, value) in data {
-            file.write_all(&ts.to_be_bytes())?;
-            file.write_all(&value.to_be_bytes())?;
+        let legacy = SeriesKey::legacy();
+        if !points.is_empty() {
+            self.batch_append(&legacy, &points)?;
+            self.sync()?;
+        }
+        fs::remove_file(&path)?;
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => sync_dir(parent)?,
+            _ => sync_dir(Path::new("."))?,
         }
-        file.flush()?;
-        debug!("WAL 批量写入 {} 条数据", data.len());
+        info!("基线版本的WAL {:?} 转写完成，{} 条数据归入序列 {}", path, points.len(), legacy);
         Ok(())
     }
 
-    pub fn load(&self) -> Result<BTreeMap<Timestamp, Value>> {
-        let mut map = BTreeMap::new();
-        let file = match File::open(&self.path) {
-            Ok(f) => f,
-            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                info!("WAL文件不存在，创建新的数据库");
-                return Ok(map);
+    pub fn sync_mode(&self) -> WalSyncMode {
+        self.mode
+    }
+
+    pub fn append(&self, series: &SeriesKey, ts: Timestamp, value: Value) -> Result<Lsn> {
+        let key = series.to_string();
+        let lsn = self.write(|file| write_record(file, RECORD_PUT, &key, ts, value.to_bits()).map(|_| 1))?;
+        debug!("WAL 追加写入 series={}, ts={}, value={}", key, ts, value);
+        Ok(lsn)
+    }
+
+    pub fn batch_append(&self, series: &SeriesKey, data: &[(Timestamp, Value)]) -> Result<Lsn> {
+        let key = series.to_string();
+        let lsn = self.write(|file| {
+            for &(ts, value) in data {
+                write_record(file, RECORD_PUT, &key, ts, value.to_bits())?;
             }
-            Err(e) => return Err(Error::IoError(e)),
-        };
-        
-        let mut reader = BufReader::new(file);
-        let mut buf = [0u8; 16];
+            Ok(data.len() as u64)
+        })?;
+        debug!("WAL 批量写入 series={}, {} 条数据", key, data.len());
+        Ok(lsn)
+    }
+
+    /// 追加范围删除记录
+    pub fn append_delete(&self, tombstone: &Tombstone) -> Result<Lsn> {
+        let key = tombstone.series.as_ref().map(|s| s.to_string()).unwrap_or_default();
+        let lsn = self.write(|file| write_record(file, RECORD_DELETE, &key, tombstone.start, tombstone.end).map(|_| 1))?;
+        debug!("WAL 写入删除记录 series={}, [{}, {}]", key, tombstone.start, tombstone.end);
+        Ok(lsn)
+    }
+
+    /// 在活跃段中写入若干条记录，返回最后一条记录的LSN
+    fn write<F>(&self, f: F) -> Result<Lsn>
+    where
+        F: FnOnce(&mut BufWriter<File>) -> Result<u64>,
+    {
+        let mut active = self.active.lock().unwrap();
+        let records = f(&mut active.file)?;
+        active.file.flush()?;
+        if self.mode == WalSyncMode::PerWrite {
+            active.file.get_ref().sync_data()?;
+        }
+        active.last_lsn += records;
+        Ok(active.last_lsn)
+    }
+
+    /// 按持久化方式确认`lsn`及之前的记录：组提交时等待它们落盘，其它方式立即返回
+    ///
+    /// 调用方应先释放MemTable等其它锁，让并发的写入者能共享同一次fdatasync
+    pub fn commit(&self, lsn: Lsn) -> Result<()> {
+        match self.mode {
+            WalSyncMode::GroupCommit => self.sync_to(lsn),
+            _ => Ok(()),
+        }
+    }
+
+    /// 把目前写入的所有记录落盘，供按时间间隔同步的后台线程调用
+    pub fn sync(&self) -> Result<()> {
+        let lsn = self.active.lock().unwrap().last_lsn;
+        self.sync_to(lsn)
+    }
+
+    /// 目前执行过的fdatasync次数（不含逐条写入模式）
+    pub fn sync_count(&self) -> u64 {
+        self.sync.lock().unwrap().syncs
+    }
+
+    /// 等待`lsn`及之前的记录落盘，没有其它写入者在同步时由自己执行fdatasync
+    fn sync_to(&self, lsn: Lsn) -> Result<()> {
+        let mut state = self.sync.lock().unwrap();
         loop {
-            match reader.read_exact(&mut buf) {
-                Ok(()) => {
-                    let ts = Timestamp::from_be_bytes(buf[0..8].try_into().unwrap());
-                    let val = Value::from_be_bytes(buf[8..16].try_into().unwrap());
-                    map.insert(ts, val);
+            if state.synced_lsn >= lsn {
+                return Ok(());
+            }
+            if !state.syncing {
+                break;
+            }
+            state = self.synced.wait(state).unwrap();
+        }
+        state.syncing = true;
+        drop(state);
+
+        // 一次同步覆盖到目前为止所有写入者追加的记录
+        let result = (|| {
+            let (file, last_lsn) = {
+                let mut active = self.active.lock().unwrap();
+                active.file.flush()?;
+                (active.file.get_ref().try_clone()?, active.last_lsn)
+            };
+            file.sync_data()?;
+            Ok(last_lsn)
+        })();
+
+        let mut state = self.sync.lock().unwrap();
+        state.syncing = false;
+        if let Ok(last_lsn) = result {
+            state.synced_lsn = state.synced_lsn.max(last_lsn);
+            state.syncs += 1;
+        }
+        self.synced.notify_all();
+        result.map(|_| ())
+    }
+
+    /// 按段的编号顺序回放所有段，并截掉最新的段中损坏的尾部
+    ///
+    /// 之后的段都为空时（如刚打开的活跃段）该段才是最新的段；封存的段损坏时返回错误，
+    /// 不截断它，也不跳过它继续回放之后的段，以免丢弃已确认的写入。
+    pub fn load(&self) -> Result<(MemTable, WalRecovery)> {
+        let mut map = MemTable::new();
+        let mut recovery = WalRecovery::default();
+        let segments = list_segments(&self.dir)?;
+        for (i, (id, path)) in segments.iter().enumerate() {
+            let mut buf = Vec::new();
+            File::open(path)?.read_to_end(&mut buf)?;
+
+            // 删除记录只影响它之前写入的数据
+            let mut pos = 0;
+            while pos < buf.len() {
+                let record = match decode_record(&buf[pos..]) {
+                    Ok((record, size)) => {
+                        pos += size;
+                        record
+                    }
+                    Err(e) => {
+                        warn!("WAL 段 {} 在偏移 {} 处损坏: {}", id, pos, e);
+                        break;
+                    }
+                };
+                match record {
+                    WalRecord::Put(series, ts, val) => map.insert(&series, ts, val),
+                    WalRecord::Delete(tombstone) => map.delete(tombstone),
+                }
+                recovery.recovered += 1;
+            }
+
+            if pos < buf.len() {
+                let mut sealed = false;
+                for (_, later) in &segments[i + 1..] {
+                    sealed |= fs::metadata(later)?.len() > 0;
                 }
-                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
-                Err(e) => {
-                    error!("WAL 读取错误: {}", e);
-                    return Err(Error::IoError(e));
+                if sealed {
+                    return Err(Error::DataError(format!(
+                        "WAL 封存的段 {} 在偏移 {} 处损坏，之后的段中还有写入",
+                        id, pos
+                    )));
                 }
+                let discarded = count_frames(&buf[pos..]);
+                recovery.discarded += discarded;
+                recovery.discarded_bytes += (buf.len() - pos) as u64;
+                let file = OpenOptions::new().write(true).open(path)?;
+                file.set_len(pos as u64)?;
+                file.sync_all()?;
+                warn!("WAL 段 {} 截断损坏的尾部: 丢弃 {} 条记录, {} 字节", id, discarded, buf.len() - pos);
             }
         }
-        info!("WAL 加载完成，恢复 {} 条数据", map.len());
-        Ok(map)
+
+        info!("WAL 加载完成，回放 {} 条记录，丢弃 {} 条记录，恢复 {} 个序列共 {} 条数据",
+              recovery.recovered, recovery.discarded, map.series_count(), map.len());
+        Ok((map, recovery))
+    }
+
+    /// 封存当前的活跃段并切换到新段，返回被封存的段号
+    ///
+    /// 调用方需保证封存时没有并发写入，使封存的段正好对应被冻结的MemTable
+    pub fn rotate(&self) -> Result<u64> {
+        let mut active = self.active.lock().unwrap();
+        active.file.flush()?;
+        active.file.get_ref().sync_data()?;
+        let next = Segment::create(&self.dir, active.id + 1, active.last_lsn)?;
+        let sealed = std::mem::replace(&mut *active, next);
+        {
+            let mut state = self.sync.lock().unwrap();
+            state.synced_lsn = state.synced_lsn.max(sealed.last_lsn);
+        }
+        let sealed = sealed.id;
+        info!("WAL 封存段 {}, 切换到段 {}", sealed, sealed + 1);
+        Ok(sealed)
     }
 
-    pub fn clear(&self) -> Result<()> {
-        let mut file = self.file.lock().unwrap();
-        file.get_mut().set_len(0)?;
-        file.get_mut().seek(SeekFrom::Start(0))?;
-        file.flush()?;
-        info!("WAL 文件清空");
+    /// 删除编号不大于`sealed`的段，在对应数据已写入SSTable后调用
+    pub fn remove_sealed(&self, sealed: u64) -> Result<()> {
+        for (id, path) in list_segments(&self.dir)? {
+            if id > sealed {
+                break;
+            }
+            fs::remove_file(&path)?;
+            debug!("WAL 删除已封存的段 {}", id);
+        }
         Ok(())
     }
 }
 
+impl Segment {
+    fn create(dir: &Path, id: u64, last_lsn: Lsn) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(segment_path(dir, id))?;
+        Ok(Segment {
+            id,
+            file: BufWriter::new(file),
+            last_lsn,
+        })
+    }
+}
+
+fn segment_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("wal-{:06}.log", id))
+}
+
+/// 目录下所有的段，按编号排序
+fn list_segments(dir: &Path) -> Result<Vec<(u64, PathBuf)>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let id = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix("wal-"))
+            .and_then(|name| name.strip_suffix(".log"))
+            .and_then(|id| id.parse::<u64>().ok());
+        if let Some(id) = id {
+            segments.push((id, path));
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+/// WAL中的一条记录
+enum WalRecord {
+    Put(SeriesKey, Timestamp, Value),
+    Delete(Tombstone),
+}
+
+fn write_record<W: Write>(w: &mut W, record_type: u8, key: &str, a: u64, b: u64) -> Result<()> {
+    let key_len = u16::try_from(key.len())
+        .map_err(|_| Error::SeriesError(format!("序列键过长: {} 字节", key.len())))?;
+    let mut body = Vec::with_capacity(2 + key.len() + 16);
+    body.extend_from_slice(&key_len.to_be_bytes());
+    body.extend_from_slice(key.as_bytes());
+    body.extend_from_slice(&a.to_be_bytes());
+    body.extend_from_slice(&b.to_be_bytes());
+    write_frame(w, record_type, &body)
+}
+
+/// 解码缓冲区开头的一条记录，返回记录和它占用的字节数
+fn decode_record(buf: &[u8]) -> Result<(WalRecord, usize)> {
+    let (record_type, body, size) = decode_frame(buf)?;
+    if body.len() < 2 {
+        return Err(Error::DataError(format!("记录体过短: {} 字节", body.len())));
+    }
+    let key_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+    if 2 + key_len + 16 != body.len() {
+        return Err(Error::DataError(format!("记录体长度 {} 与序列键长度 {} 不符", body.len(), key_len)));
+    }
+    let key = std::str::from_utf8(&body[2..2 + key_len])
+        .map_err(|_| Error::DataError("WAL 序列键不是合法的UTF-8".to_string()))?;
+    let a = u64::from_be_bytes(body[2 + key_len..10 + key_len].try_into().unwrap());
+    let b = u64::from_be_bytes(body[10 + key_len..18 + key_len].try_into().unwrap());
+
+    let record = match record_type {
+        RECORD_PUT => WalRecord::Put(SeriesKey::parse(key)?, a, Value::from_bits(b)),
+        RECORD_DELETE => {
+            let series = if key.is_empty() { None } else { Some(SeriesKey::parse(key)?) };
+            WalRecord::Delete(Tombstone { series, start: a, end: b })
+        }
+        t => return Err(Error::DataError(format!("未知的WAL记录类型: {}", t))),
+    };
+    Ok((record, size))
+}
+
+/// 写入一条带记录头的记录：`crc32c(u32) | len(u32) | type(u8) | body`，CRC32C覆盖长度、类型和记录体
+///
+/// 清单文件等其它追加写的日志也使用这种格式。
+pub(crate) fn write_frame<W: Write>(w: &mut W, record_type: u8, body: &[u8]) -> Result<()> {
+    let mut record = Vec::with_capacity(HEADER_SIZE + body.len());
+    record.extend_from_slice(&[0; 4]);
+    record.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    record.push(record_type);
+    record.extend_from_slice(body);
+    let crc = crc32c::crc32c(&record[4..]);
+    record[..4].copy_from_slice(&crc.to_be_bytes());
+    w.write_all(&record)?;
+    Ok(())
+}
+
+/// 校验并解码缓冲区开头的一条记录，返回记录类型、记录体和它占用的字节数
+pub(crate) fn decode_frame(buf: &[u8]) -> Result<(u8, &[u8], usize)> {
+    if buf.len() < HEADER_SIZE {
+        return Err(Error::DataError(format!("记录头不完整: {} 字节", buf.len())));
+    }
+    let crc = u32::from_be_bytes(buf[0..4].try_into().unwrap());
+    let len = u32::from_be_bytes(buf[4..8].try_into().unwrap()) as usize;
+    if len > MAX_BODY_SIZE {
+        return Err(Error::DataError(format!("记录长度无效: {}", len)));
+    }
+    if buf.len() < HEADER_SIZE + len {
+        return Err(Error::DataError(format!("记录不完整: 需要 {} 字节, 剩余 {} 字节", HEADER_SIZE + len, buf.len())));
+    }
+    if crc32c::crc32c(&buf[4..HEADER_SIZE + len]) != crc {
+        return Err(Error::DataError("记录校验和不匹配".to_string()));
+    }
+    Ok((buf[8], &buf[HEADER_SIZE..HEADER_SIZE + len], HEADER_SIZE + len))
+}
+
+/// 估算损坏的尾部中有多少条记录：按记录头中的长度跳过，长度不可信时把剩余部分算作一条
+pub(crate) fn count_frames(mut buf: &[u8]) -> usize {
+    let mut count = 0;
+    while !buf.is_empty() {
+        count += 1;
+        if buf.len() < HEADER_SIZE {
+            break;
+        }
+        let len = u32::from_be_bytes(buf[4..8].try_into().unwrap()) as usize;
+        if len > MAX_BODY_SIZE || buf.len() < HEADER_SIZE + len {
+            break;
+        }
+        buf = &buf[HEADER_SIZE + len..];
+    }
+    count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate_corrupt_tail() {
+        let dir = tempfile::tempdir().unwrap();
+        let wal_dir = dir.path().to_string_lossy().into_owned();
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+
+        let wal = Wal::open(&wal_dir, WalSyncMode::None).unwrap();
+        wal.batch_append(&cpu, &[(1, 1.0), (2, 2.0), (3, 3.0), (4, 4.0)]).unwrap();
+        wal.append_delete(&Tombstone { series: Some(cpu.clone()), start: 1, end: 1 }).unwrap();
+        drop(wal);
+
+        // 每条记录 9 + 2 + 10 + 16 = 37 字节：翻转第3条记录中的一位，并在末尾留下写了一半的记录
+        let path = segment_path(dir.path(), 1);
+        let mut bytes = fs::read(&path).unwrap();
+        assert_eq!(bytes.len(), 5 * 37);
+        bytes[2 * 37 + 20] ^= 0x01;
+        bytes.extend_from_slice(&[0, 0, 0]);
+        fs::write(&path, &bytes).unwrap();
+
+        let wal = Wal::open(&wal_dir, WalSyncMode::None).unwrap();
+        let (table, recovery) = wal.load().unwrap();
+        assert_eq!(recovery.recovered, 2);
+        assert_eq!(recovery.discarded, 4);
+        assert_eq!(recovery.discarded_bytes, 3 * 37 + 3);
+        assert_eq!(table.range(&cpu, 0, 10).collect::<Vec<_>>(), vec![(1, 1.0), (2, 2.0)]);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 2 * 37);
+
+        // 截断后继续写入新段，重新加载时没有损坏
+        wal.append(&cpu, 5, 5.0).unwrap();
+        let (table, recovery) = wal.load().unwrap();
+        assert_eq!((recovery.recovered, recovery.discarded), (3, 0));
+        assert_eq!(table.len(), 3);
+    }
+
+    #[test]
+    fn test_corrupt_sealed_segment() {
+        let dir = tempfile::tempdir().unwrap();
+        let wal_dir = dir.path().to_string_lossy().into_owned();
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+
+        let wal = Wal::open(&wal_dir, WalSyncMode::None).unwrap();
+        wal.batch_append(&cpu, &[(1, 1.0), (2, 2.0), (3, 3.0)]).unwrap();
+        wal.rotate().unwrap();
+        wal.append(&cpu, 4, 4.0).unwrap();
+        drop(wal);
+
+        // 封存的段1中间的记录损坏，段2中还有写入：报错，不截断也不跳过
+        let path = segment_path(dir.path(), 1);
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[37 + 20] ^= 0x01;
+        fs::write(&path, &bytes).unwrap();
+        let wal = Wal::open(&wal_dir, WalSyncMode::None).unwrap();
+        assert!(matches!(wal.load(), Err(Error::DataError(_))));
+        assert_eq!(fs::metadata(&path).unwrap().len(), 3 * 37);
+
+        // 之后的段都为空时段1是最新的段，损坏的尾部被截断
+        fs::remove_file(segment_path(dir.path(), 2)).unwrap();
+        let (table, recovery) = wal.load().unwrap();
+        assert_eq!((recovery.recovered, recovery.discarded), (1, 2));
+        assert_eq!(table.range(&cpu, 0, 10).collect::<Vec<_>>(), vec![(1, 1.0)]);
+        assert_eq!(fs::metadata(&path).unwrap().len(), 37);
+    }
+
+    #[test]
+    fn test_rotate_and_remove_sealed() {
+        let dir = tempfile::tempdir().unwrap();
+        let wal_dir = dir.path().to_string_lossy().into_owned();
+        let cpu = SeriesKey::parse("cpu,host=a").unwrap();
+
+        let wal = Wal::open(&wal_dir, WalSyncMode::None).unwrap();
+        wal.append(&cpu, 1, 1.0).unwrap();
+        assert_eq!(wal.rotate().unwrap(), 1);
+        wal.append(&cpu, 2, 2.0).unwrap();
+
+        // 封存的段在删除前仍参与回放
+        let (table, _) = wal.load().unwrap();
+        assert_eq!(table.len(), 2);
+
+        wal.remove_sealed(1).unwrap();
+        assert!(!segment_path(dir.path(), 1).exists());
+        let (table, _) = wal.load().unwrap();
+        assert_eq!(table.range(&cpu, 0, 10).collect::<Vec<_>>(), vec![(2, 2.0)]);
+
+        // 重新打开时从新的段开始写入
+        drop(wal);
+        let wal = Wal::open(&wal_dir, WalSyncMode::None).unwrap();
+        wal.append(&cpu, 3, 3.0).unwrap();
+        assert!(segment_path(dir.path(), 3).exists());
+        assert_eq!(wal.load().unwrap().0.len(), 2);
+    }
+
+    #[test]
+    fn test_import_legacy() {
+        let dir = tempfile::tempdir().unwrap();
+        let wal_dir = dir.path().join("wal").to_string_lossy().into_owned();
+        let legacy = SeriesKey::legacy();
+
+        // 基线版本的wal.log：每条记录16字节大端序，末尾留下不完整的记录
+        let legacy_path = dir.path().join(LEGACY_FILE);
+        let mut bytes = Vec::new();
+        for (ts, value) in [(1622000000u64, 25.0f64), (1622000060, 26.0), (1622000000, 27.0)] {
+            bytes.extend_from_slice(&ts.to_be_bytes());
+            bytes.extend_from_slice(&value.to_be_bytes());
+        }
+        bytes.extend_from_slice(&[0; 5]);
+        fs::write(&legacy_path, &bytes).unwrap();
+
+        let wal = Wal::open(&wal_dir, WalSyncMode::None).unwrap();
+        assert!(!legacy_path.exists());
+        let (table, recovery) = wal.load().unwrap();
+        assert_eq!(recovery.recovered, 3);
+        assert_eq!(table.range(&legacy, 0, u64::MAX).collect::<Vec<_>>(), vec![(1622000000, 27.0), (1622000060, 26.0)]);
+
+        // 转写后的数据在新的段中，重新打开时照常回放
+        wal.append(&legacy, 1622000060, 28.0).unwrap();
+        drop(wal);
+        let wal = Wal::open(&wal_dir, WalSyncMode::None).unwrap();
+        let (table, _) = wal.load().unwrap();
+        assert_eq!(table.range(&legacy, 0, u64::MAX).collect::<Vec<_>>(), vec![(1622000000, 27.0), (1622000060, 28.0)]);
+    }
+
+    #[test]
+    fn test_group_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let wal_dir = dir.path().to_string_lossy().into_owned();
+        let wal = std::sync::Arc::new(Wal::open(&wal_dir, WalSyncMode::GroupCommit).unwrap());
+
+        // 每一轮8个写入者都追加完记录后才开始提交，同一轮的提交应共享一次fdatasync
+        let barrier = std::sync::Arc::new(std::sync::Barrier::new(8));
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let wal = std::sync::Arc::clone(&wal);
+                let barrier = std::sync::Arc::clone(&barrier);
+                std::thread::spawn(move || {
+                    let series = SeriesKey::parse(&format!("cpu,host=h{}", i)).unwrap();
+                    for ts in 0..50 {
+                        let lsn = wal.append(&series, ts, ts as f64).unwrap();
+                        barrier.wait();
+                        wal.commit(lsn).unwrap();
+                        assert!(wal.sync.lock().unwrap().synced_lsn >= lsn);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // 每次提交都已落盘，400次提交每轮只同步一次
+        assert_eq!(wal.sync_count(), 50);
+        assert_eq!(wal.load().unwrap().0.len(), 400);
+
+        // 其它方式下提交立即返回
+        let wal = Wal::open(&wal_dir, WalSyncMode::Interval(Duration::from_millis(100))).unwrap();
+        let lsn = wal.append(&SeriesKey::parse("cpu,host=a").unwrap(), 1, 1.0).unwrap();
+        wal.commit(lsn).unwrap();
+        assert_eq!(wal.sync_count(), 0);
+        wal.sync().unwrap();
+        assert_eq!(wal.sync_count(), 1);
+    }
+}